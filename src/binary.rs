@@ -1,29 +1,43 @@
 use std::{
+    borrow::BorrowMut,
+    collections::VecDeque,
+    future::Future,
     io::{self, Cursor, Read},
+    ops::{Deref, DerefMut, Range},
     ptr::copy_nonoverlapping,
 };
 
 use byteorder::{BigEndian, ReadBytesExt};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use monoio::{
-    buf::{IoBufMut, SliceMut},
-    io::AsyncReadRent,
+    buf::{IoBufMut, IoVecBufMut, SliceMut},
+    io::{
+        AsyncReadRent, AsyncWriteRent, AsyncWriteRentExt, CancelHandle, CancelableAsyncReadRent,
+    },
+    time::Instant,
+    BufResult,
 };
 use smallvec::SmallVec;
 
 use crate::{
-    protocol::{TAsyncInputProtocol, TAsyncSkipProtocol, TInputProtocol, TOutputProtocol},
+    codec::{parts::EncodeParts, ttheader::ProtocolId},
+    protocol::{
+        MessageMeta, NameInterner, ProtocolConfig, TAsyncInputProtocol, TAsyncOutputProtocol,
+        TAsyncSkipProtocol, TInputProtocol, TMetaPeek, TOutputProtocol,
+    },
     thrift::{
         CowBytes, TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier,
         TMessageType, TSetIdentifier, TStructIdentifier, TType,
     },
-    CodecError, CodecErrorKind,
+    CodecError, CodecErrorKind, ErrorContext,
 };
 
 const VERSION_1: u32 = 0x80010000;
 const VERSION_MASK: u32 = 0xffff0000;
 
 const MOST_COMMON_DEPTH: usize = 16;
+/// Default max nesting of structs and containers, as in Apache Thrift.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
 
 #[inline]
 fn field_type_from_u8(ttype: u8) -> Result<TType, CodecError> {
@@ -37,21 +51,210 @@ fn field_type_from_u8(ttype: u8) -> Result<TType, CodecError> {
     Ok(ttype)
 }
 
+#[inline]
+fn message_type_from_u8(type_u8: u8) -> Result<TMessageType, CodecError> {
+    TMessageType::try_from(type_u8).map_err(|_| {
+        CodecError::new(
+            CodecErrorKind::InvalidData,
+            format!("invalid message type {}", type_u8),
+        )
+    })
+}
+
+#[inline]
+fn check_len(len: i32, max: usize, what: &str) -> Result<usize, CodecError> {
+    if len < 0 {
+        return Err(CodecError::new(
+            CodecErrorKind::NegativeSize,
+            format!("negative {what} length {len}"),
+        ));
+    }
+    let len = len as usize;
+    if len > max {
+        return Err(CodecError::new(
+            CodecErrorKind::SizeLimitExceeded,
+            format!("{what} length {len} exceeds limit {max}"),
+        ));
+    }
+    Ok(len)
+}
+
+// Reading past the end of a frame that is already buffered means the frame is
+// malformed, the transport didn't end early.
+#[inline]
+fn overrun() -> CodecError {
+    CodecError::new(
+        CodecErrorKind::InvalidData,
+        "read past the end of the frame",
+    )
+}
+
+#[inline]
+fn in_frame(err: io::Error) -> CodecError {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => overrun(),
+        _ => err.into(),
+    }
+}
+
+#[inline]
+pub(crate) fn validate_utf8(data: &[u8]) -> Result<&str, CodecError> {
+    #[cfg(feature = "simdutf8")]
+    let s = simdutf8::basic::from_utf8(data).ok();
+    #[cfg(not(feature = "simdutf8"))]
+    let s = std::str::from_utf8(data).ok();
+    s.ok_or_else(|| CodecError::new(CodecErrorKind::InvalidData, "not a valid utf8 string"))
+}
+
+// Take a message name of `len` bytes, which `buf` must hold. With `names`, a
+// known name is the cached one and a new one is cached if there is room.
+fn take_name(
+    buf: &mut impl Buf,
+    len: usize,
+    names: Option<&mut NameInterner>,
+) -> Result<Bytes, CodecError> {
+    let Some(names) = names else {
+        let name = buf.copy_to_bytes(len);
+        validate_utf8(&name)?;
+        return Ok(name);
+    };
+    if buf.chunk().len() >= len {
+        let name = &buf.chunk()[..len];
+        let name = match names.get(name) {
+            Some(cached) => cached,
+            None => names.intern(validate_utf8(name)?),
+        };
+        buf.advance(len);
+        return Ok(name);
+    }
+    let name = buf.copy_to_bytes(len);
+    Ok(names.intern(validate_utf8(&name)?))
+}
+
+// Read the type and id of a field with a single bounds check, the id of a stop
+// field is 0.
+#[inline(always)]
+fn read_field_header(trans: &mut Cursor<&[u8]>) -> Result<(TType, i16), CodecError> {
+    let pos = trans.position() as usize;
+    let rest = trans.get_ref().get(pos..).unwrap_or_default();
+    let header = match *rest {
+        [ttype, ..] if ttype == TType::Stop as u8 => {
+            trans.set_position(pos as u64 + 1);
+            return Ok((TType::Stop, 0));
+        }
+        [ttype, id0, id1, ..] => (field_type_from_u8(ttype)?, i16::from_be_bytes([id0, id1])),
+        _ => return Err(overrun()),
+    };
+    trans.set_position(pos as u64 + 3);
+    Ok(header)
+}
+
+mod attachment {
+    use std::io::Cursor;
+
+    use bytes::{Buf, BytesMut};
+
+    /// Buffer of the async protocols, the data is read into `buf_mut` and
+    /// consumed through `Buf`.
+    pub trait AsyncAttachment: Buf {
+        fn buf_mut(&mut self) -> &mut BytesMut;
+        /// Like `Buf::advance`, without the check the callers already did.
+        fn skip(&mut self, cnt: usize);
+    }
+
+    impl AsyncAttachment for BytesMut {
+        #[inline(always)]
+        fn buf_mut(&mut self) -> &mut BytesMut {
+            self
+        }
+
+        #[inline(always)]
+        fn skip(&mut self, cnt: usize) {
+            self.advance(cnt);
+        }
+    }
+
+    impl AsyncAttachment for Cursor<BytesMut> {
+        #[inline(always)]
+        fn buf_mut(&mut self) -> &mut BytesMut {
+            self.get_mut()
+        }
+
+        #[inline(always)]
+        fn skip(&mut self, cnt: usize) {
+            let pos = self.position() + cnt as u64;
+            self.set_position(pos);
+        }
+    }
+}
+use attachment::AsyncAttachment;
+
 #[inline(always)]
-fn advance(cursor: &mut Cursor<BytesMut>, cnt: usize) {
-    let pos = cursor.position() + cnt as u64;
-    cursor.set_position(pos);
+fn advance<A: AsyncAttachment>(attachment: &mut A, cnt: usize) {
+    attachment.skip(cnt);
+}
+
+/// How much room the reads from an io reserve in their buffer, ahead of the
+/// bytes they need.
+///
+/// Reserving ahead saves syscalls, but the room stays allocated while the
+/// connection waits for its next message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadBufPolicy {
+    /// Least room reserved by a read, which is all an empty buffer gets.
+    pub initial_size: usize,
+    /// The room reserved grows to the capacity times `growth_factor - 1`, so
+    /// a buffer that filled up grows by this factor. 1 never grows beyond
+    /// `initial_size`.
+    pub growth_factor: usize,
+    /// Most room reserved ahead, reads needing more still get what they need.
+    pub max_reserve: usize,
+}
+
+impl ReadBufPolicy {
+    /// 4KB reserved by every read.
+    pub const DEFAULT: Self = Self {
+        initial_size: 4096,
+        growth_factor: 1,
+        max_reserve: 4096,
+    };
+
+    /// Room a read of `to_read` bytes reserves in `buffer`.
+    #[inline]
+    pub fn reserve_len(&self, buffer: &BytesMut, to_read: usize) -> usize {
+        let grown = buffer
+            .capacity()
+            .saturating_mul(self.growth_factor.saturating_sub(1));
+        grown
+            .max(self.initial_size)
+            .min(self.max_reserve)
+            .max(to_read)
+    }
+}
+
+impl Default for ReadBufPolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
 }
 
 // Read more data(at least to_read).
 pub async fn read_more_at_least<T: AsyncReadRent>(
+    io: T,
+    buffer: &mut BytesMut,
+    to_read: usize,
+) -> std::io::Result<()> {
+    read_more_at_least_with(io, buffer, to_read, &ReadBufPolicy::DEFAULT).await
+}
+
+/// [`read_more_at_least`] reserving room in `buffer` as `policy` says.
+pub async fn read_more_at_least_with<T: AsyncReadRent>(
     mut io: T,
     buffer: &mut BytesMut,
     to_read: usize,
+    policy: &ReadBufPolicy,
 ) -> std::io::Result<()> {
-    // We will reserve at least that much buffer to save syscalls.
-    const MIN_CAPACITY: usize = 4096;
-    buffer.reserve(to_read.max(MIN_CAPACITY));
+    buffer.reserve(policy.reserve_len(buffer, to_read));
 
     let mut read = buffer.len();
     let end = buffer.capacity();
@@ -71,6 +274,317 @@ pub async fn read_more_at_least<T: AsyncReadRent>(
     Ok(())
 }
 
+/// [`read_more_at_least`] failing with [`io::ErrorKind::TimedOut`] if the data
+/// is not read by `deadline`, e.g. when the peer stalls in the middle of a
+/// frame. The runtime must have its timer enabled.
+///
+/// A timeout drops the pending read along with `buffer`'s content, the io
+/// can't be read further.
+pub async fn read_more_at_least_until<T: AsyncReadRent>(
+    io: T,
+    buffer: &mut BytesMut,
+    to_read: usize,
+    deadline: Instant,
+) -> std::io::Result<()> {
+    until(deadline, read_more_at_least(io, buffer, to_read)).await
+}
+
+#[inline]
+fn timed_out_before() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        "read deadline elapsed before, the buffered data is lost",
+    )
+}
+
+async fn until(deadline: Instant, read: impl Future<Output = io::Result<()>>) -> io::Result<()> {
+    match monoio::time::timeout_at(deadline, read).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "read deadline elapsed",
+        )),
+    }
+}
+
+/// Io whose reads are aborted when the [`Canceller`](monoio::io::Canceller)
+/// of `handle` cancels, e.g. on shutdown or when the request is canceled.
+///
+/// A protocol or [`read_more_at_least`] reading from it then fails with the
+/// io's cancellation error instead of waiting for the peer. The data read
+/// before stays buffered.
+pub struct CancelableRead<T> {
+    io: T,
+    handle: CancelHandle,
+}
+
+impl<T: CancelableAsyncReadRent> CancelableRead<T> {
+    #[inline]
+    pub fn new(io: T, handle: CancelHandle) -> Self {
+        Self { io, handle }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl<T: CancelableAsyncReadRent> AsyncReadRent for CancelableRead<T> {
+    #[inline]
+    fn read<B: IoBufMut>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+        self.io.cancelable_read(buf, self.handle.clone())
+    }
+
+    #[inline]
+    fn readv<B: IoVecBufMut>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+        self.io.cancelable_readv(buf, self.handle.clone())
+    }
+}
+
+/// Buffers retired from the attachments of async protocols, kept until the
+/// [`Bytes`] read from them are dropped so their allocation can be reused.
+///
+/// Binaries read as `Bytes` share the attachment's allocation, so while a
+/// handler keeps some, the next reads go to fresh allocations. A server can
+/// share a pool among its connections and [`recycle`](TBinaryProtocol::recycle)
+/// their attachments between messages.
+#[derive(Debug)]
+pub struct BufferPool {
+    buffers: VecDeque<BytesMut>,
+    max_buffers: usize,
+    buffer_capacity: usize,
+}
+
+impl BufferPool {
+    /// Pool keeping at most `max_buffers` buffers, which are handed out with
+    /// at least `buffer_capacity` bytes of capacity.
+    pub fn new(max_buffers: usize, buffer_capacity: usize) -> Self {
+        Self {
+            buffers: VecDeque::new(),
+            max_buffers,
+            buffer_capacity,
+        }
+    }
+
+    /// An empty buffer, reusing the allocation of one whose `Bytes` are all
+    /// dropped if any.
+    pub fn take(&mut self) -> BytesMut {
+        let capacity = self.buffer_capacity;
+        match self
+            .buffers
+            .iter_mut()
+            .position(|b| b.try_reclaim(capacity))
+        {
+            Some(i) => self.buffers.swap_remove_back(i).unwrap_or_default(),
+            None => BytesMut::with_capacity(capacity),
+        }
+    }
+
+    /// Keep `buf` for reuse, its content is dropped. The oldest buffer is let
+    /// go if the pool is full.
+    pub fn put(&mut self, mut buf: BytesMut) {
+        if self.max_buffers == 0 {
+            return;
+        }
+        buf.clear();
+        if self.buffers.len() == self.max_buffers {
+            self.buffers.pop_front();
+        }
+        self.buffers.push_back(buf);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+}
+
+/// Buffers read by [`read_chunked`], in order, read through [`Buf`] e.g. by a
+/// [`TBinaryBufReader`].
+#[derive(Clone, Debug, Default)]
+pub struct ChunkedBuf {
+    chunks: VecDeque<Bytes>,
+    remaining: usize,
+}
+
+impl ChunkedBuf {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `chunk` to the data.
+    #[inline]
+    pub fn push(&mut self, chunk: Bytes) {
+        if !chunk.is_empty() {
+            self.remaining += chunk.len();
+            self.chunks.push_back(chunk);
+        }
+    }
+
+    #[inline]
+    pub fn chunks(&self) -> impl Iterator<Item = &Bytes> {
+        self.chunks.iter()
+    }
+
+    #[inline]
+    pub fn into_chunks(self) -> VecDeque<Bytes> {
+        self.chunks
+    }
+}
+
+impl Buf for ChunkedBuf {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    #[inline]
+    fn chunk(&self) -> &[u8] {
+        self.chunks.front().map_or(&[], |chunk| chunk)
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        assert!(cnt <= self.remaining, "advance past the end of the buffer");
+        self.remaining -= cnt;
+        while let Some(front) = self.chunks.front_mut() {
+            if cnt < front.len() {
+                front.advance(cnt);
+                return;
+            }
+            cnt -= front.len();
+            self.chunks.pop_front();
+        }
+    }
+
+    // Bytes within a chunk are shared instead of copied.
+    fn copy_to_bytes(&mut self, len: usize) -> Bytes {
+        assert!(len <= self.remaining, "copy past the end of the buffer");
+        match self.chunks.front_mut() {
+            Some(front) if front.len() >= len => {
+                let out = front.split_to(len);
+                if front.is_empty() {
+                    self.chunks.pop_front();
+                }
+                self.remaining -= len;
+                out
+            }
+            _ => {
+                let mut out = BytesMut::with_capacity(len);
+                out.put((&mut *self).take(len));
+                out.freeze()
+            }
+        }
+    }
+}
+
+// Most buffers a vectored read takes, the usual IOV_MAX.
+#[cfg(unix)]
+const MAX_IOVECS: usize = 1024;
+
+// Buffers filled by vectored reads, each up to its wanted length.
+#[cfg(unix)]
+#[derive(Default)]
+struct ChunkIoVec {
+    chunks: Vec<(BytesMut, usize)>,
+    iovecs: Vec<libc::iovec>,
+}
+
+#[cfg(unix)]
+impl ChunkIoVec {
+    // Point the iovecs at the room left in the chunks.
+    fn refresh(&mut self) {
+        self.iovecs.clear();
+        for (chunk, want) in self.chunks.iter_mut() {
+            if chunk.len() < *want && self.iovecs.len() < MAX_IOVECS {
+                self.iovecs.push(libc::iovec {
+                    iov_base: chunk.spare_capacity_mut().as_mut_ptr().cast(),
+                    iov_len: *want - chunk.len(),
+                });
+            }
+        }
+    }
+}
+
+// The iovecs point into the chunks, which are not touched until the read
+// completes.
+#[cfg(unix)]
+unsafe impl IoVecBufMut for ChunkIoVec {
+    #[inline]
+    fn write_iovec_ptr(&mut self) -> *mut libc::iovec {
+        self.iovecs.as_mut_ptr()
+    }
+
+    #[inline]
+    fn write_iovec_len(&mut self) -> usize {
+        self.iovecs.len()
+    }
+
+    unsafe fn set_init(&mut self, mut pos: usize) {
+        for (chunk, want) in self.chunks.iter_mut() {
+            let n = (*want - chunk.len()).min(pos);
+            chunk.set_len(chunk.len() + n);
+            pos -= n;
+        }
+    }
+}
+
+/// Read exactly `len` bytes, those already in `buffered` first, with vectored
+/// reads into buffers taken from `pool`, instead of reserving `len`
+/// contiguous bytes.
+///
+/// Meant for large payloads, e.g. once [`read_ttheader`] told their length.
+/// The buffers go back to `pool` to be reused once the returned data is
+/// dropped.
+///
+/// [`read_ttheader`]: crate::codec::ttheader::read_ttheader
+#[cfg(unix)]
+pub async fn read_chunked<T: AsyncReadRent>(
+    mut io: T,
+    buffered: &mut BytesMut,
+    len: usize,
+    pool: &mut BufferPool,
+) -> io::Result<ChunkedBuf> {
+    let mut out = ChunkedBuf::new();
+    out.push(buffered.split_to(len.min(buffered.len())).freeze());
+    let mut remaining = len - out.remaining();
+    let mut vec = ChunkIoVec::default();
+    while remaining > 0 {
+        let mut chunk = pool.take();
+        if chunk.capacity() == 0 {
+            chunk.reserve(remaining);
+        }
+        let want = chunk.capacity().min(remaining);
+        remaining -= want;
+        vec.chunks.push((chunk, want));
+    }
+    vec.refresh();
+    while !vec.iovecs.is_empty() {
+        let (result, buf) = io.readv(vec).await;
+        vec = buf;
+        if result? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        vec.refresh();
+    }
+    for (mut chunk, _) in vec.chunks {
+        out.push(chunk.split().freeze());
+        pool.put(chunk);
+    }
+    Ok(out)
+}
+
 #[derive(Debug)]
 enum SkipData {
     Collection(u32, [TType; 2]),
@@ -80,79 +594,428 @@ type SkipDataStack = SmallVec<[SkipData; MOST_COMMON_DEPTH]>;
 pub type TBinarySkipper<IO> = TBinaryProtocol<IO, Cursor<BytesMut>>;
 type PositionStack = SmallVec<[usize; MOST_COMMON_DEPTH]>;
 pub type TBinaryReader<'a> = TBinaryProtocol<Cursor<&'a [u8]>, PositionStack>;
+/// Reader whose binaries and strings share the refcount of the read buffer,
+/// so they can be kept after the reader is gone.
+pub type TBinaryBytesReader = TBinaryProtocol<Cursor<Bytes>, PositionStack>;
 pub type TBinaryWriter<'a> = TBinaryProtocol<&'a mut BytesMut, PositionStack>;
+/// Writer owning its buffer, e.g. to build a message in a spawned task.
+pub type TBinaryOwnedWriter = TBinaryProtocol<BytesMut, PositionStack>;
+pub type TBinaryVecWriter = TBinaryProtocol<Vec<u8>, PositionStack>;
+pub type TBinarySegmentedWriter = TBinaryProtocol<SegmentedBuf, PositionStack>;
+/// Reader over any [`Buf`], e.g. a `Chain` of receive buffers, so a frame
+/// doesn't have to be made contiguous first.
+pub type TBinaryBufReader<B> = TBinaryProtocol<B, ()>;
+
+/// Reading position saved by `checkpoint`, to `rewind` to after a speculative
+/// parse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TBinaryCheckpoint {
+    position: u64,
+    depth: usize,
+}
+
+impl TBinaryCheckpoint {
+    /// Offset in the buffer.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position as usize
+    }
+}
 
 pub struct TBinaryProtocol<T, A> {
     pub(crate) trans: T,
     // this buffer is only used for async decoder impl.
     pub(crate) attachment: A,
+    // nesting of the structs and containers being read
+    pub(crate) depth: usize,
+    pub(crate) config: ProtocolConfig,
+    // bytes read from the io into the attachment
+    pub(crate) received: usize,
+    // cache of the message names read, if enabled
+    pub(crate) names: Option<NameInterner>,
+    // reads from the io fail once it has passed
+    pub(crate) read_deadline: Option<Instant>,
+    // set when a read timed out, the buffered data went with the pending read
+    pub(crate) timed_out: bool,
 }
 
 impl<T> TBinaryProtocol<T, Cursor<BytesMut>> {
     pub fn new(io: T) -> Self {
-        Self {
-            trans: io,
-            attachment: Cursor::new(BytesMut::new()),
-        }
+        Self::from_parts(io, Cursor::new(BytesMut::new()))
     }
 }
 
 impl<'a> TBinaryProtocol<Cursor<&'a [u8]>, PositionStack> {
     pub fn new(trans: Cursor<&'a [u8]>) -> Self {
-        Self {
-            trans,
-            attachment: SmallVec::new(),
-        }
+        Self::from_parts(trans, SmallVec::new())
     }
 }
 
 impl<'a> TBinaryProtocol<&'a mut BytesMut, PositionStack> {
     pub fn new(trans: &'a mut BytesMut) -> Self {
+        Self::from_parts(trans, SmallVec::new())
+    }
+}
+
+impl TBinaryProtocol<BytesMut, PositionStack> {
+    pub fn new(trans: BytesMut) -> Self {
+        Self::from_parts(trans, SmallVec::new())
+    }
+
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::new(BytesMut::with_capacity(capacity))
+    }
+
+    /// The encoded bytes.
+    #[inline]
+    pub fn into_buf(self) -> BytesMut {
+        self.trans
+    }
+}
+
+impl TBinaryProtocol<Vec<u8>, PositionStack> {
+    pub fn new(trans: Vec<u8>) -> Self {
+        Self::from_parts(trans, SmallVec::new())
+    }
+
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::new(Vec::with_capacity(capacity))
+    }
+
+    /// The encoded bytes.
+    #[inline]
+    pub fn into_buf(self) -> Vec<u8> {
+        self.trans
+    }
+}
+
+/// Output of [`TBinarySegmentedWriter`]: a contiguous buffer plus binaries
+/// kept as separate segments, each with the buffer offset it goes to.
+///
+/// Derefs to the contiguous buffer.
+#[derive(Debug, Default)]
+pub struct SegmentedBuf {
+    buf: BytesMut,
+    segments: SmallVec<[(usize, Bytes); 4]>,
+    threshold: usize,
+}
+
+impl SegmentedBuf {
+    /// Binaries of at least `threshold` bytes are kept as segments.
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            buf: BytesMut::new(),
+            segments: SmallVec::new(),
+            threshold,
+        }
+    }
+
+    /// Total length, segments included.
+    pub fn total_len(&self) -> usize {
+        self.buf.len() + self.segments.iter().map(|(_, b)| b.len()).sum::<usize>()
+    }
+
+    /// The buffers in wire order, ready for a vectored write.
+    pub fn into_parts(self) -> EncodeParts {
+        let mut parts = EncodeParts::new();
+        let mut buf = self.buf;
+        let mut consumed = 0;
+        for (at, segment) in self.segments {
+            let head = buf.split_to(at - consumed);
+            if !head.is_empty() {
+                parts.push(head.freeze());
+            }
+            parts.push(segment);
+            consumed = at;
+        }
+        if !buf.is_empty() {
+            parts.push(buf.freeze());
+        }
+        parts
+    }
+}
+
+impl Deref for SegmentedBuf {
+    type Target = BytesMut;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for SegmentedBuf {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+// Destination of the binary writers, taking the binaries written with
+// `write_bytes_owned`.
+trait PutBytesOwned {
+    fn put_bytes_owned(&mut self, b: Bytes);
+}
+
+impl PutBytesOwned for &mut BytesMut {
+    #[inline]
+    fn put_bytes_owned(&mut self, b: Bytes) {
+        self.put_slice(&b);
+    }
+}
+
+impl PutBytesOwned for BytesMut {
+    #[inline]
+    fn put_bytes_owned(&mut self, b: Bytes) {
+        self.put_slice(&b);
+    }
+}
+
+impl PutBytesOwned for Vec<u8> {
+    #[inline]
+    fn put_bytes_owned(&mut self, b: Bytes) {
+        self.put_slice(&b);
+    }
+}
+
+impl PutBytesOwned for SegmentedBuf {
+    #[inline]
+    fn put_bytes_owned(&mut self, b: Bytes) {
+        if b.len() >= self.threshold {
+            self.segments.push((self.buf.len(), b));
+        } else {
+            self.buf.put_slice(&b);
+        }
+    }
+}
+
+impl TBinaryProtocol<SegmentedBuf, PositionStack> {
+    /// Writer keeping binaries of at least `threshold` bytes passed to
+    /// [`write_bytes_owned`](TOutputProtocol::write_bytes_owned) out of the
+    /// buffer.
+    pub fn new(threshold: usize) -> Self {
+        Self::from_parts(SegmentedBuf::new(threshold), SmallVec::new())
+    }
+
+    /// Write a binary, large ones are recorded as a segment instead of being
+    /// copied. Same as [`write_bytes_owned`](TOutputProtocol::write_bytes_owned).
+    #[inline]
+    pub fn write_bytes_shared(&mut self, b: Bytes) {
+        self.write_bytes_owned(b);
+    }
+
+    #[inline]
+    pub fn into_parts(self) -> EncodeParts {
+        self.trans.into_parts()
+    }
+}
+
+impl<T, A> TBinaryProtocol<T, A> {
+    #[inline]
+    pub fn into_inner(self) -> (T, A) {
+        (self.trans, self.attachment)
+    }
+    #[inline]
+    pub fn from_parts(trans: T, attachment: A) -> Self {
         Self {
             trans,
-            attachment: SmallVec::new(),
+            attachment,
+            depth: 0,
+            config: ProtocolConfig::DEFAULT,
+            received: 0,
+            names: None,
+            read_deadline: None,
+            timed_out: false,
         }
     }
 
     #[inline]
-    fn write_length(&mut self, len: usize) {
-        let pos = self.attachment.pop().expect("illegal thrift pair");
-        let len = len as i32;
-        // Note: use big endian for length as thrift encoding
-        self.trans[pos..pos + 4].copy_from_slice(&len.to_be_bytes());
+    pub fn config(&self) -> &ProtocolConfig {
+        &self.config
+    }
+
+    /// Apply all the limits and checks of `config`.
+    #[inline]
+    pub fn with_config(mut self, config: ProtocolConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    #[inline]
+    pub fn set_config(&mut self, config: ProtocolConfig) {
+        self.config = config;
+    }
+
+    /// Resolve the names of the messages read through `names`, see
+    /// [`NameInterner`].
+    #[inline]
+    pub fn with_name_interner(mut self, names: NameInterner) -> Self {
+        self.names = Some(names);
+        self
+    }
+
+    #[inline]
+    pub fn set_name_interner(&mut self, names: Option<NameInterner>) {
+        self.names = names;
+    }
+
+    #[inline]
+    pub fn name_interner(&self) -> Option<&NameInterner> {
+        self.names.as_ref()
+    }
+
+    /// Limit the nesting of structs and containers, deeper data is rejected
+    /// with [`CodecErrorKind::DepthLimit`].
+    #[inline]
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.config.max_depth = max_depth;
+        self
+    }
+
+    #[inline]
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.config.max_depth = max_depth;
+    }
+
+    /// Limit the element count of lists, sets and maps.
+    #[inline]
+    pub fn with_max_container_len(mut self, max_container_len: usize) -> Self {
+        self.config.max_container_len = max_container_len;
+        self
+    }
+
+    #[inline]
+    pub fn set_max_container_len(&mut self, max_container_len: usize) {
+        self.config.max_container_len = max_container_len;
+    }
+
+    /// Limit the length of binaries and strings.
+    #[inline]
+    pub fn with_max_string_len(mut self, max_string_len: usize) -> Self {
+        self.config.max_string_len = max_string_len;
+        self
+    }
+
+    #[inline]
+    pub fn set_max_string_len(&mut self, max_string_len: usize) {
+        self.config.max_string_len = max_string_len;
+    }
+
+    /// Reserve room in the buffer as `policy` says when reading from the io.
+    #[inline]
+    pub fn with_read_buf_policy(mut self, policy: ReadBufPolicy) -> Self {
+        self.config.read_buf = policy;
+        self
+    }
+
+    #[inline]
+    pub fn set_read_buf_policy(&mut self, policy: ReadBufPolicy) {
+        self.config.read_buf = policy;
+    }
+
+    #[inline]
+    fn container_len(&self, size: i32) -> Result<usize, CodecError> {
+        check_len(size, self.config.max_container_len, "container")
+    }
+
+    #[inline]
+    fn string_len(&self, len: i32) -> Result<usize, CodecError> {
+        check_len(len, self.config.max_string_len, "bytes")
     }
-}
 
-impl<T, A> TBinaryProtocol<T, A> {
     #[inline]
-    pub fn into_inner(self) -> (T, A) {
-        (self.trans, self.attachment)
+    fn enter(&mut self) -> Result<(), CodecError> {
+        if self.depth >= self.config.max_depth {
+            return Err(CodecError::depth_limit());
+        }
+        self.depth += 1;
+        Ok(())
     }
+
     #[inline]
-    pub fn from_parts(trans: T, attachment: A) -> Self {
-        Self { trans, attachment }
+    fn leave(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
     }
 }
 
-impl<T: AsyncReadRent> TBinaryProtocol<T, BytesMut> {
+impl<T: AsyncReadRent, A: AsyncAttachment> TBinaryProtocol<T, A> {
     async fn fill_at_least(&mut self, n: usize) -> std::io::Result<()> {
+        if self.timed_out {
+            return Err(timed_out_before());
+        }
+        if let Some(deadline) = self.read_deadline {
+            return self.fill_at_least_until(n, deadline).await;
+        }
         let rem = self.attachment.remaining();
         if rem >= n {
             return Ok(());
         }
         let to_read = n - rem;
-        read_more_at_least(&mut self.trans, &mut self.attachment, to_read).await
+        let policy = self.config.read_buf;
+        let before = self.attachment.buf_mut().len();
+        let result =
+            read_more_at_least_with(&mut self.trans, self.attachment.buf_mut(), to_read, &policy)
+                .await;
+        self.received += self.attachment.buf_mut().len() - before;
+        result
     }
-}
 
-impl<T: AsyncReadRent> TBinaryProtocol<T, Cursor<BytesMut>> {
-    async fn fill_at_least(&mut self, n: usize) -> std::io::Result<()> {
+    /// Buffer at least `n` bytes, failing with [`io::ErrorKind::TimedOut`] if
+    /// they are not read by `deadline`, see [`read_more_at_least_until`].
+    ///
+    /// After a timeout every read fails, see [`Self::set_read_deadline`].
+    pub async fn fill_at_least_until(&mut self, n: usize, deadline: Instant) -> io::Result<()> {
+        if self.timed_out {
+            return Err(timed_out_before());
+        }
         let rem = self.attachment.remaining();
         if rem >= n {
             return Ok(());
         }
         let to_read = n - rem;
-        read_more_at_least(&mut self.trans, self.attachment.get_mut(), to_read).await
+        let policy = self.config.read_buf;
+        let before = self.attachment.buf_mut().len();
+        let read =
+            read_more_at_least_with(&mut self.trans, self.attachment.buf_mut(), to_read, &policy);
+        let result = until(deadline, read).await;
+        // the buffer is dropped on timeout
+        self.received += self.attachment.buf_mut().len().saturating_sub(before);
+        if matches!(&result, Err(e) if e.kind() == io::ErrorKind::TimedOut) {
+            self.timed_out = true;
+        }
+        result
+    }
+
+    /// Fail the reads from the io with [`io::ErrorKind::TimedOut`] once
+    /// `deadline` has passed, `None` to wait for data indefinitely. Until
+    /// then, reads served from the buffered data never fail.
+    ///
+    /// A timeout drops the buffered data along with the pending read, so the
+    /// protocol must be dropped after one: every later read fails with
+    /// [`io::ErrorKind::TimedOut`], whatever the deadline.
+    #[inline]
+    pub fn set_read_deadline(&mut self, deadline: Option<Instant>) {
+        self.read_deadline = deadline;
+    }
+
+    #[inline]
+    pub fn read_deadline(&self) -> Option<Instant> {
+        self.read_deadline
+    }
+
+    /// Bytes read from the io and consumed, data already in the attachment
+    /// passed to `from_parts` is not counted.
+    #[inline]
+    pub fn bytes_consumed(&self) -> usize {
+        self.received.saturating_sub(self.attachment.remaining())
+    }
+
+    /// Bytes buffered and not consumed yet.
+    #[inline]
+    pub fn bytes_remaining(&self) -> usize {
+        self.attachment.remaining()
     }
 }
 
@@ -162,13 +1025,23 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
         Self: 'b;
 
     fn read_message_begin(&mut self) -> Result<TMessageIdentifier, CodecError> {
-        let size: i32 = self.trans.read_i32::<BigEndian>()?;
+        let size: i32 = self.trans.read_i32::<BigEndian>().map_err(in_frame)?;
 
         if size > 0 {
-            return Err(CodecError::new(
-                CodecErrorKind::BadVersion,
-                "Missing version in ReadMessageBegin".to_string(),
-            ));
+            if self.config.strict {
+                return Err(CodecError::new(
+                    CodecErrorKind::BadVersion,
+                    "Missing version in ReadMessageBegin".to_string(),
+                ));
+            }
+            // unversioned: the size is the name length, then the message type
+            // and sequence number
+            let pos = self.trans.position();
+            self.trans.set_position(pos - 4);
+            let name = CowBytes::Borrowed(self.read_string()?);
+            let message_type = message_type_from_u8(self.read_byte()?)?;
+            let sequence_number = self.read_i32()?;
+            return Ok(TMessageIdentifier::new(name, message_type, sequence_number));
         }
         let type_u8 = (size & 0xf) as u8;
 
@@ -200,27 +1073,19 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
 
     #[inline]
     fn read_struct_begin(&mut self) -> Result<TStructIdentifier, CodecError> {
+        self.enter()?;
         Ok(TStructIdentifier::new(None))
     }
 
     #[inline]
     fn read_struct_end(&mut self) -> Result<(), CodecError> {
+        self.leave();
         Ok(())
     }
 
     #[inline]
     fn read_field_begin(&mut self) -> Result<TFieldIdentifier, CodecError> {
-        let field_type_byte = self.read_byte()?;
-        let field_type: TType = field_type_byte.try_into().map_err(|_| {
-            CodecError::new(
-                CodecErrorKind::InvalidData,
-                format!("invalid ttype {}", field_type_byte),
-            )
-        })?;
-        let id = match field_type {
-            TType::Stop => Ok(0),
-            _ => self.read_i16(),
-        }?;
+        let (field_type, id) = read_field_header(&mut self.trans)?;
         Ok(TFieldIdentifier::new(None, field_type, Some(id)))
     }
 
@@ -231,44 +1096,53 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
 
     #[inline]
     fn read_list_begin(&mut self) -> Result<TListIdentifier, CodecError> {
+        self.enter()?;
         let element_type = self.read_byte().and_then(field_type_from_u8)?;
         let size = self.read_i32()?;
-        Ok(TListIdentifier::new(element_type, size as usize))
+        let size = self.container_len(size)?;
+        Ok(TListIdentifier::new(element_type, size))
     }
 
     #[inline]
     fn read_list_end(&mut self) -> Result<(), CodecError> {
+        self.leave();
         Ok(())
     }
 
     #[inline]
     fn read_set_begin(&mut self) -> Result<TSetIdentifier, CodecError> {
+        self.enter()?;
         let element_type = self.read_byte().and_then(field_type_from_u8)?;
         let size = self.read_i32()?;
-        Ok(TSetIdentifier::new(element_type, size as usize))
+        let size = self.container_len(size)?;
+        Ok(TSetIdentifier::new(element_type, size))
     }
 
     #[inline]
     fn read_set_end(&mut self) -> Result<(), CodecError> {
+        self.leave();
         Ok(())
     }
 
     #[inline]
     fn read_map_begin(&mut self) -> Result<TMapIdentifier, CodecError> {
+        self.enter()?;
         let key_type = self.read_byte().and_then(field_type_from_u8)?;
         let value_type = self.read_byte().and_then(field_type_from_u8)?;
         let size = self.read_i32()?;
-        Ok(TMapIdentifier::new(key_type, value_type, size as usize))
+        let size = self.container_len(size)?;
+        Ok(TMapIdentifier::new(key_type, value_type, size))
     }
 
     #[inline]
     fn read_map_end(&mut self) -> Result<(), CodecError> {
+        self.leave();
         Ok(())
     }
 
     #[inline]
     fn read_byte(&mut self) -> Result<u8, CodecError> {
-        Ok(self.trans.read_u8()?)
+        self.trans.read_u8().map_err(in_frame)
     }
 
     #[inline]
@@ -278,39 +1152,46 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
 
     #[inline]
     fn read_i8(&mut self) -> Result<i8, CodecError> {
-        self.trans.read_i8().map_err(Into::into)
+        self.trans.read_i8().map_err(in_frame)
     }
 
     #[inline]
     fn read_i16(&mut self) -> Result<i16, CodecError> {
-        self.trans.read_i16::<BigEndian>().map_err(Into::into)
+        self.trans.read_i16::<BigEndian>().map_err(in_frame)
     }
 
     #[inline]
     fn read_i32(&mut self) -> Result<i32, CodecError> {
-        self.trans.read_i32::<BigEndian>().map_err(Into::into)
+        self.trans.read_i32::<BigEndian>().map_err(in_frame)
     }
 
     #[inline]
     fn read_i64(&mut self) -> Result<i64, CodecError> {
-        self.trans.read_i64::<BigEndian>().map_err(Into::into)
+        self.trans.read_i64::<BigEndian>().map_err(in_frame)
     }
 
     #[inline]
     fn read_double(&mut self) -> Result<f64, CodecError> {
-        self.trans.read_f64::<BigEndian>().map_err(Into::into)
+        self.trans.read_f64::<BigEndian>().map_err(in_frame)
+    }
+
+    #[cfg(feature = "float")]
+    #[inline]
+    fn read_float(&mut self) -> Result<f32, CodecError> {
+        self.trans.read_f32::<BigEndian>().map_err(in_frame)
     }
 
     #[inline]
     fn read_uuid(&mut self) -> Result<[u8; 16], CodecError> {
         let mut u = [0; 16];
-        self.trans.read_exact(&mut u)?;
+        self.trans.read_exact(&mut u).map_err(in_frame)?;
         Ok(u)
     }
 
     #[inline]
     fn read_bytes(&mut self) -> Result<&'x [u8], CodecError> {
-        let len = self.trans.read_i32::<BigEndian>()? as usize;
+        let len = self.trans.read_i32::<BigEndian>().map_err(in_frame)?;
+        let len = self.string_len(len)?;
         let total = self.trans.get_ref().len();
         let pos = self.trans.position() as usize;
         let target_pos = pos + len;
@@ -329,23 +1210,44 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
     #[inline]
     fn read_string(&mut self) -> Result<&'x str, CodecError> {
         let data = self.read_bytes()?;
-        if data.is_empty() {
-            return Ok("");
-        }
-        if let Some(chunk) = data.utf8_chunks().next() {
-            let s = chunk.valid();
-            if s.len() == data.len() {
-                return Ok(s);
+        validate_utf8(data)
+    }
+
+    fn skip_message(&mut self) -> Result<(), CodecError> {
+        let size: i32 = self.trans.read_i32::<BigEndian>().map_err(in_frame)?;
+
+        if size > 0 {
+            if self.config.strict {
+                return Err(CodecError::new(
+                    CodecErrorKind::BadVersion,
+                    "Missing version in ReadMessageBegin".to_string(),
+                ));
             }
+            // unversioned: skip name, message type and sequence number
+            let pos = self.trans.position();
+            self.trans.set_position(pos - 4);
+            self.read_bytes()?;
+            self.read_byte()?;
+            self.read_i32()?;
+            return self.skip_field(TType::Struct);
         }
-        Err(CodecError::new(
-            CodecErrorKind::InvalidData,
-            "not a valid utf8 string",
-        ))
+
+        let version = size & (VERSION_MASK as i32);
+        if version != (VERSION_1 as i32) {
+            return Err(CodecError::new(
+                CodecErrorKind::BadVersion,
+                "Bad version in ReadMessageBegin",
+            ));
+        }
+        // skip name without checking utf8, and sequence number
+        self.read_bytes()?;
+        self.read_i32()?;
+        // skip struct
+        self.skip_field(TType::Struct)
     }
 
     fn skip_field(&mut self, ttype: TType) -> Result<(), CodecError> {
-        const BINARY_BASIC_TYPE_FIXED_SIZE: [usize; 17] = [
+        const BINARY_BASIC_TYPE_FIXED_SIZE: [usize; 20] = [
             0,  // TType::Stop
             0,  // TType::Void
             1,  // TType::Bool
@@ -363,6 +1265,9 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
             0,  // TType::List
             0,  // TType::Set
             16, // TType::Uuid
+            0,  // NAN
+            0,  // NAN
+            4,  // TType::Float
         ];
 
         macro_rules! pop {
@@ -373,6 +1278,14 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
                 }
             };
         }
+        macro_rules! push {
+            ($stack:expr, $data:expr) => {
+                if self.depth + $stack.len() >= self.config.max_depth {
+                    return Err(CodecError::depth_limit());
+                }
+                $stack.push($data)
+            };
+        }
         macro_rules! read_ttype {
             ($trans: expr) => {{
                 let field_type_byte = $trans.get_u8();
@@ -419,7 +1332,7 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
                         _ => {
                             require_data!(self, 2);
                             self.trans.advance(2); // field id
-                            stack.push(current);
+                            push!(stack, current);
                             current = SkipData::Other(field_type);
                         }
                     }
@@ -447,7 +1360,8 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
                     }
                     TType::Binary => {
                         require_data!(self, 4);
-                        let len = self.trans.get_i32() as usize;
+                        let len = self.trans.get_i32();
+                        let len = self.string_len(len)?;
                         require_data!(self, len);
                         self.trans.advance(len);
                         current = pop!(stack);
@@ -457,10 +1371,17 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
                         self.trans.advance(16);
                         current = pop!(stack);
                     }
+                    #[cfg(feature = "float")]
+                    TType::Float => {
+                        require_data!(self, 4);
+                        self.trans.advance(4);
+                        current = pop!(stack);
+                    }
                     TType::List | TType::Set => {
                         require_data!(self, 5);
                         let element_type = read_ttype!(self.trans);
-                        let element_len = self.trans.get_i32() as u32;
+                        let element_len = self.trans.get_i32();
+                        let element_len = self.container_len(element_len)? as u32;
                         let size = unsafe {
                             *BINARY_BASIC_TYPE_FIXED_SIZE.get_unchecked(element_type as usize)
                         };
@@ -478,7 +1399,8 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
                         require_data!(self, 6);
                         let element_type = read_ttype!(self.trans);
                         let element_type2 = read_ttype!(self.trans);
-                        let element_len = self.trans.get_i32() as u32;
+                        let element_len = self.trans.get_i32();
+                        let element_len = self.container_len(element_len)? as u32;
                         let size = unsafe {
                             *BINARY_BASIC_TYPE_FIXED_SIZE.get_unchecked(element_type as usize)
                         };
@@ -509,21 +1431,533 @@ impl<'x, A: 'static> TInputProtocol<'x> for TBinaryProtocol<Cursor<&'x [u8]>, A>
                         current = pop!(stack);
                         continue;
                     }
-                    current = SkipData::Other(ttypes[(len & 1) as usize]);
-                    stack.push(SkipData::Collection(len - 1, ttypes));
+                    current = SkipData::Other(ttypes[(len & 1) as usize]);
+                    push!(stack, SkipData::Collection(len - 1, ttypes));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn buf<'a>(&'a mut self) -> &'a mut Self::Buf<'x>
+    where
+        'x: 'a,
+    {
+        &mut self.trans
+    }
+}
+
+macro_rules! impl_read_list {
+    ($($fname: ident($ttype: ident, $ty: ty);)*) => {
+        $(
+            #[doc = concat!("Read a `list<", stringify!($ty), ">` with a single bounds check.")]
+            pub fn $fname(&mut self, out: &mut Vec<$ty>) -> Result<(), CodecError> {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+                let list = self.read_list_begin()?;
+                if list.element_type != TType::$ttype {
+                    return Err(CodecError::type_mismatch(TType::$ttype, list.element_type, None));
+                }
+                let pos = self.trans.position() as usize;
+                let end = list
+                    .size
+                    .checked_mul(SIZE)
+                    .and_then(|len| pos.checked_add(len))
+                    .ok_or_else(overrun)?;
+                let data = self
+                    .trans
+                    .get_ref()
+                    .get(pos..end)
+                    .ok_or_else(overrun)?;
+                out.reserve(list.size);
+                out.extend(
+                    data.chunks_exact(SIZE)
+                        .map(|chunk| <$ty>::from_be_bytes(chunk.try_into().unwrap())),
+                );
+                self.trans.set_position(end as u64);
+                self.read_list_end()
+            }
+        )*
+    };
+}
+
+impl<'x> TBinaryProtocol<Cursor<&'x [u8]>, PositionStack> {
+    #[inline]
+    pub fn bytes_consumed(&self) -> usize {
+        self.trans.position() as usize
+    }
+
+    #[inline]
+    pub fn bytes_remaining(&self) -> usize {
+        self.trans.get_ref().len().saturating_sub(self.bytes_consumed())
+    }
+
+    impl_read_list! {
+        read_i16_list(I16, i16);
+        read_i32_list(I32, i32);
+        read_i64_list(I64, i64);
+        read_double_list(Double, f64);
+    }
+
+    /// Read the type and id of a field, like `read_field_begin` without
+    /// building the identifier. The id of a stop field is 0.
+    #[inline]
+    pub fn read_field_header(&mut self) -> Result<(TType, i16), CodecError> {
+        read_field_header(&mut self.trans)
+    }
+
+    #[inline]
+    pub fn checkpoint(&self) -> TBinaryCheckpoint {
+        TBinaryCheckpoint {
+            position: self.trans.position(),
+            depth: self.depth,
+        }
+    }
+
+    /// Go back to `checkpoint`, everything read since is read again.
+    #[inline]
+    pub fn rewind(&mut self, checkpoint: TBinaryCheckpoint) {
+        self.trans.set_position(checkpoint.position);
+        self.depth = checkpoint.depth;
+    }
+
+    /// Skip a field, returning the range of its value in the buffer.
+    pub fn skip_field_ranged(&mut self, ttype: TType) -> Result<Range<usize>, CodecError> {
+        let start = self.trans.position() as usize;
+        self.skip_field(ttype)?;
+        Ok(start..self.trans.position() as usize)
+    }
+
+    /// Skip a field, returning its raw value, e.g. to forward it verbatim.
+    pub fn skip_field_bytes(&mut self, ttype: TType) -> Result<&'x [u8], CodecError> {
+        let range = self.skip_field_ranged(ttype)?;
+        let buf: &'x [u8] = self.trans.get_ref();
+        Ok(&buf[range])
+    }
+}
+
+macro_rules! impl_borrowed_fn {
+    ($($(#[$meta: meta])* $fname: ident($($arg: ident: $arg_type: ty),*) -> $out: ty;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            pub fn $fname(&mut self $(,$arg: $arg_type)*) -> Result<$out, CodecError> {
+                self.borrowed(|r| r.$fname($($arg),*))
+            }
+        )*
+    };
+}
+
+impl TBinaryProtocol<Cursor<Bytes>, PositionStack> {
+    pub fn new(trans: Cursor<Bytes>) -> Self {
+        Self::from_parts(trans, SmallVec::new())
+    }
+
+    #[inline]
+    pub fn bytes_consumed(&self) -> usize {
+        self.trans.position() as usize
+    }
+
+    #[inline]
+    pub fn bytes_remaining(&self) -> usize {
+        self.trans.get_ref().len().saturating_sub(self.bytes_consumed())
+    }
+
+    // Run `f` with a borrowed reader at the current position.
+    #[inline]
+    fn borrowed<R>(&mut self, f: impl FnOnce(&mut TBinaryReader<'_>) -> R) -> R {
+        let mut reader = TBinaryReader::new(Cursor::new(&self.trans.get_ref()[..]));
+        reader.trans.set_position(self.trans.position());
+        reader.depth = self.depth;
+        reader.config = self.config;
+        let out = f(&mut reader);
+        let pos = reader.trans.position();
+        self.depth = reader.depth;
+        self.trans.set_position(pos);
+        out
+    }
+
+    pub fn read_message_begin(&mut self) -> Result<TMessageIdentifier<'static>, CodecError> {
+        let start = self.trans.position() as usize;
+        let (message_type, sequence_number, name_len) = self.borrowed(|r| {
+            r.read_message_begin()
+                .map(|id| (id.message_type, id.sequence_number, id.name.as_bytes().len()))
+        })?;
+        // the name follows the 4-byte version and its 4-byte length, or only
+        // its length when unversioned
+        let unversioned = self.trans.get_ref()[start] & 0x80 == 0;
+        let name_start = start + if unversioned { 4 } else { 8 };
+        let name = self.trans.get_ref().slice(name_start..name_start + name_len);
+        Ok(TMessageIdentifier::new(
+            CowBytes::Owned(name),
+            message_type,
+            sequence_number,
+        ))
+    }
+
+    impl_borrowed_fn! {
+        read_message_end() -> ();
+        read_struct_begin() -> TStructIdentifier;
+        read_struct_end() -> ();
+        read_field_begin() -> TFieldIdentifier;
+        read_field_header() -> (TType, i16);
+        read_i16_list(out: &mut Vec<i16>) -> ();
+        read_i32_list(out: &mut Vec<i32>) -> ();
+        read_i64_list(out: &mut Vec<i64>) -> ();
+        read_double_list(out: &mut Vec<f64>) -> ();
+        read_field_end() -> ();
+        read_list_begin() -> TListIdentifier;
+        read_list_end() -> ();
+        read_set_begin() -> TSetIdentifier;
+        read_set_end() -> ();
+        read_map_begin() -> TMapIdentifier;
+        read_map_end() -> ();
+        read_byte() -> u8;
+        read_bool() -> bool;
+        read_i8() -> i8;
+        read_i16() -> i16;
+        read_i32() -> i32;
+        read_i64() -> i64;
+        read_double() -> f64;
+        #[cfg(feature = "float")]
+        read_float() -> f32;
+        read_uuid() -> [u8; 16];
+        skip_field(ttype: TType) -> ();
+        skip_message() -> ();
+        skip_until_field(id: i16) -> Option<TType>;
+        skip_remaining() -> ();
+    }
+
+    #[cfg(feature = "uuid")]
+    #[inline]
+    pub fn read_uuid_typed(&mut self) -> Result<uuid::Uuid, CodecError> {
+        self.read_uuid().map(uuid::Uuid::from_bytes)
+    }
+
+    #[inline]
+    pub fn checkpoint(&self) -> TBinaryCheckpoint {
+        TBinaryCheckpoint {
+            position: self.trans.position(),
+            depth: self.depth,
+        }
+    }
+
+    /// Go back to `checkpoint`, everything read since is read again.
+    #[inline]
+    pub fn rewind(&mut self, checkpoint: TBinaryCheckpoint) {
+        self.trans.set_position(checkpoint.position);
+        self.depth = checkpoint.depth;
+    }
+
+    /// Skip a field, returning the range of its value in the buffer.
+    #[inline]
+    pub fn skip_field_ranged(&mut self, ttype: TType) -> Result<Range<usize>, CodecError> {
+        self.borrowed(|r| r.skip_field_ranged(ttype))
+    }
+
+    /// Skip a field, returning its raw value without copying.
+    #[inline]
+    pub fn skip_field_bytes(&mut self, ttype: TType) -> Result<Bytes, CodecError> {
+        let range = self.skip_field_ranged(ttype)?;
+        Ok(self.trans.get_ref().slice(range))
+    }
+
+    /// Read a binary without copying.
+    #[inline]
+    pub fn read_bytes(&mut self) -> Result<Bytes, CodecError> {
+        let len = self.borrowed(|r| r.read_bytes().map(<[u8]>::len))?;
+        let end = self.trans.position() as usize;
+        Ok(self.trans.get_ref().slice(end - len..end))
+    }
+
+    /// Same as `read_bytes`, matching [`TInputProtocol::read_bytes_owned`].
+    #[inline]
+    pub fn read_bytes_owned(&mut self) -> Result<Bytes, CodecError> {
+        self.read_bytes()
+    }
+
+    /// Read a string without copying, the returned bytes are valid utf8
+    /// unless [`ProtocolConfig::validate_utf8`] is off.
+    #[inline]
+    pub fn read_string(&mut self) -> Result<Bytes, CodecError> {
+        let data = self.read_bytes()?;
+        if self.config.validate_utf8 {
+            validate_utf8(&data)?;
+        }
+        Ok(data)
+    }
+}
+
+impl<B: Buf> TBinaryProtocol<B, ()> {
+    pub fn new(buf: B) -> Self {
+        Self::from_parts(buf, ())
+    }
+
+    #[inline]
+    pub fn into_buf(self) -> B {
+        self.trans
+    }
+
+    #[inline]
+    pub fn bytes_remaining(&self) -> usize {
+        self.trans.remaining()
+    }
+
+    #[inline]
+    fn require(&self, n: usize) -> Result<(), CodecError> {
+        if self.trans.remaining() < n {
+            return Err(overrun());
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn read_name(&mut self, len: i32) -> Result<Bytes, CodecError> {
+        let len = self.string_len(len)?;
+        self.require(len)?;
+        take_name(&mut self.trans, len, self.names.as_mut())
+    }
+
+    pub fn read_message_begin(&mut self) -> Result<TMessageIdentifier<'static>, CodecError> {
+        let size = self.read_i32()?;
+        let (name, message_type) = if size > 0 {
+            if self.config.strict {
+                return Err(CodecError::new(
+                    CodecErrorKind::BadVersion,
+                    "Missing version in ReadMessageBegin".to_string(),
+                ));
+            }
+            // unversioned: the size is the name length, then the message type
+            let name = self.read_name(size)?;
+            (name, message_type_from_u8(self.read_byte()?)?)
+        } else {
+            let version = size & (VERSION_MASK as i32);
+            if version != (VERSION_1 as i32) {
+                return Err(CodecError::new(
+                    CodecErrorKind::BadVersion,
+                    "Bad version in ReadMessageBegin",
+                ));
+            }
+            let message_type = message_type_from_u8((size & 0xf) as u8)?;
+            let len = self.read_i32()?;
+            (self.read_name(len)?, message_type)
+        };
+        let sequence_number = self.read_i32()?;
+        Ok(TMessageIdentifier::new(
+            CowBytes::Owned(name),
+            message_type,
+            sequence_number,
+        ))
+    }
+
+    #[inline]
+    pub fn read_message_end(&mut self) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_struct_begin(&mut self) -> Result<TStructIdentifier, CodecError> {
+        self.enter()?;
+        Ok(TStructIdentifier::new(None))
+    }
+
+    #[inline]
+    pub fn read_struct_end(&mut self) -> Result<(), CodecError> {
+        self.leave();
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_field_begin(&mut self) -> Result<TFieldIdentifier, CodecError> {
+        let field_type = field_type_from_u8(self.read_byte()?)?;
+        let id = match field_type {
+            TType::Stop => 0,
+            _ => self.read_i16()?,
+        };
+        Ok(TFieldIdentifier::new(None, field_type, Some(id)))
+    }
+
+    #[inline]
+    pub fn read_field_end(&mut self) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_list_begin(&mut self) -> Result<TListIdentifier, CodecError> {
+        self.enter()?;
+        let element_type = field_type_from_u8(self.read_byte()?)?;
+        let size = self.read_i32()?;
+        let size = self.container_len(size)?;
+        Ok(TListIdentifier::new(element_type, size))
+    }
+
+    #[inline]
+    pub fn read_list_end(&mut self) -> Result<(), CodecError> {
+        self.leave();
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_set_begin(&mut self) -> Result<TSetIdentifier, CodecError> {
+        self.enter()?;
+        let element_type = field_type_from_u8(self.read_byte()?)?;
+        let size = self.read_i32()?;
+        let size = self.container_len(size)?;
+        Ok(TSetIdentifier::new(element_type, size))
+    }
+
+    #[inline]
+    pub fn read_set_end(&mut self) -> Result<(), CodecError> {
+        self.leave();
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_map_begin(&mut self) -> Result<TMapIdentifier, CodecError> {
+        self.enter()?;
+        let key_type = field_type_from_u8(self.read_byte()?)?;
+        let value_type = field_type_from_u8(self.read_byte()?)?;
+        let size = self.read_i32()?;
+        let size = self.container_len(size)?;
+        Ok(TMapIdentifier::new(key_type, value_type, size))
+    }
+
+    #[inline]
+    pub fn read_map_end(&mut self) -> Result<(), CodecError> {
+        self.leave();
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_byte(&mut self) -> Result<u8, CodecError> {
+        self.require(1)?;
+        Ok(self.trans.get_u8())
+    }
+
+    #[inline]
+    pub fn read_bool(&mut self) -> Result<bool, CodecError> {
+        Ok(self.read_byte()? != 0)
+    }
+
+    #[inline]
+    pub fn read_i8(&mut self) -> Result<i8, CodecError> {
+        self.require(1)?;
+        Ok(self.trans.get_i8())
+    }
+
+    #[inline]
+    pub fn read_i16(&mut self) -> Result<i16, CodecError> {
+        self.require(2)?;
+        Ok(self.trans.get_i16())
+    }
+
+    #[inline]
+    pub fn read_i32(&mut self) -> Result<i32, CodecError> {
+        self.require(4)?;
+        Ok(self.trans.get_i32())
+    }
+
+    #[inline]
+    pub fn read_i64(&mut self) -> Result<i64, CodecError> {
+        self.require(8)?;
+        Ok(self.trans.get_i64())
+    }
+
+    #[inline]
+    pub fn read_double(&mut self) -> Result<f64, CodecError> {
+        self.require(8)?;
+        Ok(self.trans.get_f64())
+    }
+
+    #[cfg(feature = "float")]
+    #[inline]
+    pub fn read_float(&mut self) -> Result<f32, CodecError> {
+        self.require(4)?;
+        Ok(self.trans.get_f32())
+    }
+
+    #[inline]
+    pub fn read_uuid(&mut self) -> Result<[u8; 16], CodecError> {
+        self.require(16)?;
+        let mut u = [0; 16];
+        self.trans.copy_to_slice(&mut u);
+        Ok(u)
+    }
+
+    /// Read a binary, without copying if it lies in a single chunk of a
+    /// [`Bytes`] backed buffer.
+    #[inline]
+    pub fn read_bytes(&mut self) -> Result<Bytes, CodecError> {
+        let len = self.read_i32()?;
+        let len = self.string_len(len)?;
+        self.require(len)?;
+        Ok(self.trans.copy_to_bytes(len))
+    }
+
+    /// Read a string, the returned bytes are valid utf8 unless
+    /// [`ProtocolConfig::validate_utf8`] is off.
+    #[inline]
+    pub fn read_string(&mut self) -> Result<Bytes, CodecError> {
+        let data = self.read_bytes()?;
+        if self.config.validate_utf8 {
+            validate_utf8(&data)?;
+        }
+        Ok(data)
+    }
+
+    pub fn skip_message(&mut self) -> Result<(), CodecError> {
+        self.read_message_begin()?;
+        self.skip_field(TType::Struct)
+    }
+
+    pub fn skip_field(&mut self, ttype: TType) -> Result<(), CodecError> {
+        let size = match ttype {
+            TType::Bool | TType::I8 => 1,
+            TType::I16 => 2,
+            TType::I32 => 4,
+            #[cfg(feature = "float")]
+            TType::Float => 4,
+            TType::I64 | TType::Double => 8,
+            TType::Uuid => 16,
+            TType::Binary => {
+                let len = self.read_i32()?;
+                self.string_len(len)?
+            }
+            TType::Struct => {
+                self.enter()?;
+                loop {
+                    let field = self.read_field_begin()?;
+                    if field.field_type == TType::Stop {
+                        break;
+                    }
+                    self.skip_field(field.field_type)?;
                 }
+                self.leave();
+                return Ok(());
             }
-        }
+            TType::List | TType::Set => {
+                let list = self.read_list_begin()?;
+                for _ in 0..list.size {
+                    self.skip_field(list.element_type)?;
+                }
+                self.leave();
+                return Ok(());
+            }
+            TType::Map => {
+                let map = self.read_map_begin()?;
+                for _ in 0..map.size {
+                    self.skip_field(map.key_type)?;
+                    self.skip_field(map.value_type)?;
+                }
+                self.leave();
+                return Ok(());
+            }
+            _ => return Err(CodecError::invalid_data()),
+        };
+        self.require(size)?;
+        self.trans.advance(size);
         Ok(())
     }
-
-    #[inline]
-    fn buf<'a>(&'a mut self) -> &'a mut Self::Buf<'x>
-    where
-        'x: 'a,
-    {
-        &mut self.trans
-    }
 }
 
 macro_rules! impl_async_fn {
@@ -546,17 +1980,75 @@ macro_rules! require_data {
     };
 }
 
-impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut>> {
+impl<T: AsyncReadRent> TBinaryProtocol<T, Cursor<BytesMut>> {
+    /// Save the position, the data read since stays buffered so a
+    /// [`rewind`](Self::rewind) doesn't read it from the io again.
+    #[inline]
+    pub fn checkpoint(&self) -> TBinaryCheckpoint {
+        TBinaryCheckpoint {
+            position: self.attachment.position(),
+            depth: self.depth,
+        }
+    }
+
+    #[inline]
+    pub fn rewind(&mut self, checkpoint: TBinaryCheckpoint) {
+        self.attachment.set_position(checkpoint.position);
+        self.depth = checkpoint.depth;
+    }
+
+    /// Skip a field, returning the range of its value in the data buffered so
+    /// far, which stays in the attachment.
+    pub async fn skip_field_ranged(&mut self, ttype: TType) -> Result<Range<usize>, CodecError> {
+        let start = self.attachment.position() as usize;
+        self.skip_field(ttype).await?;
+        Ok(start..self.attachment.position() as usize)
+    }
+
+    /// Skip a field, returning a copy of its raw value.
+    pub async fn skip_field_bytes(&mut self, ttype: TType) -> Result<Bytes, CodecError> {
+        let range = self.skip_field_ranged(ttype).await?;
+        Ok(Bytes::copy_from_slice(&self.attachment.get_ref()[range]))
+    }
+
+    /// Skip a message, checking it takes exactly `frame_len` bytes, with a
+    /// [`CodecErrorKind::LengthMismatch`] error otherwise.
+    pub async fn skip_message_exact(&mut self, frame_len: usize) -> Result<(), CodecError> {
+        let start = self.attachment.position() as usize;
+        self.skip_message().await?;
+        let consumed = self.attachment.position() as usize - start;
+        if consumed != frame_len {
+            return Err(CodecError::new(
+                CodecErrorKind::LengthMismatch {
+                    expected: frame_len,
+                    actual: consumed,
+                },
+                "message length differs from frame length",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<T: AsyncReadRent, A: AsyncAttachment> TAsyncSkipProtocol for TBinaryProtocol<T, A> {
     impl_async_fn! {
         async fn skip_message(&mut self) -> Result<SkipMessage(())> {
             require_data!(self, 4);
             let size = self.attachment.get_i32();
 
             if size > 0 {
-                return Err(CodecError::new(
-                    CodecErrorKind::BadVersion,
-                    "Missing version in ReadMessageBegin".to_string(),
-                ));
+                if self.config.strict {
+                    return Err(CodecError::new(
+                        CodecErrorKind::BadVersion,
+                        "Missing version in ReadMessageBegin".to_string(),
+                    ));
+                }
+                // unversioned: skip name, message type and sequence number
+                let len = self.string_len(size)?;
+                require_data!(self, len + 5);
+                advance(&mut self.attachment, len + 5);
+                self.skip_field(TType::Struct).await?;
+                return Ok(());
             }
 
             let version = size & (VERSION_MASK as i32);
@@ -568,15 +2060,42 @@ impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut
             }
             // skip name and sequence number
             require_data!(self, 4);
-            let len = self.attachment.get_i32() as usize;
+            let len = self.attachment.get_i32();
+            let len = self.string_len(len)?;
             require_data!(self, len + 4);
             advance(&mut self.attachment, len + 4);
             // skip struct
             self.skip_field(TType::Struct).await?;
             Ok(())
         }
+        async fn skip_until_field(&mut self, id: i16) -> Result<SkipUntilField(Option<TType>)> {
+            loop {
+                require_data!(self, 1);
+                let field_type = field_type_from_u8(self.attachment.get_u8())?;
+                if field_type == TType::Stop {
+                    return Ok(None);
+                }
+                require_data!(self, 2);
+                if self.attachment.get_i16() == id {
+                    return Ok(Some(field_type));
+                }
+                self.skip_field(field_type).await?;
+            }
+        }
+        async fn skip_remaining(&mut self) -> Result<SkipRemaining(())> {
+            loop {
+                require_data!(self, 1);
+                let field_type = field_type_from_u8(self.attachment.get_u8())?;
+                if field_type == TType::Stop {
+                    return Ok(());
+                }
+                require_data!(self, 2);
+                advance(&mut self.attachment, 2);
+                self.skip_field(field_type).await?;
+            }
+        }
         async fn skip_field(&mut self, ttype: TType) -> Result<SkipField(())> {
-            const BINARY_BASIC_TYPE_FIXED_SIZE: [usize; 17] = [
+            const BINARY_BASIC_TYPE_FIXED_SIZE: [usize; 20] = [
                 0,  // TType::Stop
                 0,  // TType::Void
                 1,  // TType::Bool
@@ -594,6 +2113,9 @@ impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut
                 0,  // TType::List
                 0,  // TType::Set
                 16, // TType::Uuid
+                0,  // NAN
+                0,  // NAN
+                4,  // TType::Float
             ];
 
             macro_rules! pop {
@@ -604,6 +2126,14 @@ impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut
                     }
                 };
             }
+            macro_rules! push {
+                ($stack:expr, $data:expr) => {
+                    if self.depth + $stack.len() >= self.config.max_depth {
+                        return Err(CodecError::depth_limit());
+                    }
+                    $stack.push($data)
+                };
+            }
             macro_rules! read_ttype {
                 ($attachment: expr) => {
                     {
@@ -643,7 +2173,7 @@ impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut
                             _ => {
                                 require_data!(self, 2);
                                 advance(&mut self.attachment, 2); // field id
-                                stack.push(current);
+                                push!(stack, current);
                                 current = SkipData::Other(field_type);
                             }
                         }
@@ -672,7 +2202,8 @@ impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut
                             },
                             TType::Binary => {
                                 require_data!(self, 4);
-                                let len = self.attachment.get_i32() as usize;
+                                let len = self.attachment.get_i32();
+                                let len = self.string_len(len)?;
                                 require_data!(self, len);
                                 advance(&mut self.attachment, len);
                                 current = pop!(stack);
@@ -682,10 +2213,17 @@ impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut
                                 advance(&mut self.attachment, 16);
                                 current = pop!(stack);
                             },
+                            #[cfg(feature = "float")]
+                            TType::Float => {
+                                require_data!(self, 4);
+                                advance(&mut self.attachment, 4);
+                                current = pop!(stack);
+                            },
                             TType::List | TType::Set => {
                                 require_data!(self, 5);
                                 let element_type = read_ttype!(self.attachment);
-                                let element_len = self.attachment.get_i32() as u32;
+                                let element_len = self.attachment.get_i32();
+                                let element_len = self.container_len(element_len)? as u32;
                                 let size = unsafe{ *BINARY_BASIC_TYPE_FIXED_SIZE.get_unchecked(element_type as usize) };
                                 if size != 0 {
                                     let skip = element_len as usize * size;
@@ -700,7 +2238,8 @@ impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut
                                 require_data!(self, 6);
                                 let element_type = read_ttype!(self.attachment);
                                 let element_type2 = read_ttype!(self.attachment);
-                                let element_len = self.attachment.get_i32() as u32;
+                                let element_len = self.attachment.get_i32();
+                                let element_len = self.container_len(element_len)? as u32;
                                 let size = unsafe{ *BINARY_BASIC_TYPE_FIXED_SIZE.get_unchecked(element_type as usize) };
                                 let size2 = unsafe{ *BINARY_BASIC_TYPE_FIXED_SIZE.get_unchecked(element_type2 as usize) };
                                 if size != 0 && size2 != 0 {
@@ -726,7 +2265,7 @@ impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut
                             continue;
                         }
                         current = SkipData::Other(ttypes[(len & 1) as usize]);
-                        stack.push(SkipData::Collection(len - 1, ttypes));
+                        push!(stack, SkipData::Collection(len - 1, ttypes));
                     }
                 }
             }
@@ -735,15 +2274,82 @@ impl<T: AsyncReadRent> TAsyncSkipProtocol for TBinaryProtocol<T, Cursor<BytesMut
     }
 }
 
+impl<T> TBinaryProtocol<T, BytesMut> {
+    /// Reuse an allocation for the attachment once the message read is
+    /// consumed, its own if none of the [`Bytes`] read from it are alive, or a
+    /// free one from `pool` otherwise.
+    ///
+    /// Nothing is done while data of a pipelined message is buffered.
+    pub fn recycle(&mut self, pool: &mut BufferPool) {
+        if !self.attachment.is_empty() || self.attachment.try_reclaim(pool.buffer_capacity) {
+            return;
+        }
+        let retired = std::mem::replace(&mut self.attachment, pool.take());
+        pool.put(retired);
+    }
+}
+
+impl<T: AsyncReadRent> TBinaryProtocol<T, BytesMut> {
+    /// Read the identifier of the next message without consuming it, so a
+    /// dispatcher can pick the handler that then reads the message itself.
+    pub async fn peek_message_begin(&mut self) -> Result<TMessageIdentifier<'static>, CodecError> {
+        self.fill_at_least(4).await?;
+        let size = i32::from_be_bytes(self.attachment[..4].try_into().unwrap());
+        let header_len = if size > 0 && !self.config.strict {
+            // unversioned: name, message type and sequence number
+            4 + self.string_len(size)? + 1 + 4
+        } else {
+            // version and name length
+            self.fill_at_least(8).await?;
+            if size as u32 & VERSION_MASK != VERSION_1 {
+                return Err(CodecError::new(
+                    CodecErrorKind::BadVersion,
+                    "Bad version in ReadMessageBegin",
+                ));
+            }
+            let name_len = i32::from_be_bytes(self.attachment[4..8].try_into().unwrap());
+            // name and sequence number
+            8 + self.string_len(name_len)? + 4
+        };
+        self.fill_at_least(header_len).await?;
+        let mut reader =
+            TBinaryReader::new(Cursor::new(&self.attachment[..])).with_config(self.config);
+        let identifier = reader.read_message_begin()?;
+        let name = match self.names.as_mut() {
+            Some(names) => names.intern(identifier.name.as_str()),
+            None => Bytes::copy_from_slice(identifier.name.as_bytes()),
+        };
+        Ok(TMessageIdentifier::new(
+            CowBytes::Owned(name),
+            identifier.message_type,
+            identifier.sequence_number,
+        ))
+    }
+}
+
 impl<T: AsyncReadRent> TAsyncInputProtocol for TBinaryProtocol<T, BytesMut> {
     impl_async_fn! {
         async fn read_message_begin(&mut self) -> Result<ReadMessageBegin(TMessageIdentifier<'static>)> {
             let size = self.read_i32().await?;
 
             if size > 0 {
-                return Err(CodecError::new(
-                    CodecErrorKind::BadVersion,
-                    "Missing version in ReadMessageBegin".to_string(),
+                if self.config.strict {
+                    return Err(CodecError::new(
+                        CodecErrorKind::BadVersion,
+                        "Missing version in ReadMessageBegin".to_string(),
+                    ));
+                }
+                // unversioned: the size is the name length, then the message
+                // type and sequence number
+                let len = self.string_len(size)?;
+                require_data!(self, len);
+                let name = take_name(&mut self.attachment, len, self.names.as_mut())?;
+                let message_type = message_type_from_u8(self.read_byte().await?)?;
+                let sequence_number = self.read_i32().await?;
+                return Ok(TMessageIdentifier::new(
+                    CowBytes::Owned(name),
+                    message_type,
+                    sequence_number,
                 ));
             }
             let type_u8 = (size & 0xf) as u8;
@@ -763,7 +2369,12 @@ impl<T: AsyncReadRent> TAsyncInputProtocol for TBinaryProtocol<T, BytesMut> {
                 ));
             }
 
-            let name = CowBytes::Owned(self.read_string().await?);
+            // names are checked whatever the config
+            let len = self.read_i32().await?;
+            let len = self.string_len(len)?;
+            require_data!(self, len);
+            let name = take_name(&mut self.attachment, len, self.names.as_mut())?;
+            let name = CowBytes::Owned(name);
 
             let sequence_number = self.read_i32().await?;
             Ok(TMessageIdentifier::new(name, message_type, sequence_number))
@@ -772,10 +2383,12 @@ impl<T: AsyncReadRent> TAsyncInputProtocol for TBinaryProtocol<T, BytesMut> {
             instant(Ok(()))
         }
         async fn read_struct_begin(&mut self) -> Result<ReadStructBegin(TStructIdentifier)> {
-            instant(Ok(TStructIdentifier::new(None)))
+            self.enter()?;
+            Ok(TStructIdentifier::new(None))
         }
         async fn read_struct_end(&mut self) -> Result<ReadStructEnd(())> {
-            instant(Ok(()))
+            self.leave();
+            Ok(())
         }
         async fn read_field_begin(&mut self) -> Result<ReadFieldBegin(TFieldIdentifier)> {
             let field_type_byte = self.read_byte().await?;
@@ -795,29 +2408,38 @@ impl<T: AsyncReadRent> TAsyncInputProtocol for TBinaryProtocol<T, BytesMut> {
             instant(Ok(()))
         }
         async fn read_list_begin(&mut self) -> Result<ReadListBegin(TListIdentifier)> {
+            self.enter()?;
             let element_type = self.read_byte().await.and_then(field_type_from_u8)?;
             let size = self.read_i32().await?;
-            Ok(TListIdentifier::new(element_type, size as usize))
+            let size = self.container_len(size)?;
+            Ok(TListIdentifier::new(element_type, size))
         }
         async fn read_list_end(&mut self) -> Result<ReadListEnd(())> {
-            instant(Ok(()))
+            self.leave();
+            Ok(())
         }
         async fn read_set_begin(&mut self) -> Result<ReadSetBegin(TSetIdentifier)> {
+            self.enter()?;
             let element_type = self.read_byte().await.and_then(field_type_from_u8)?;
             let size = self.read_i32().await?;
-            Ok(TSetIdentifier::new(element_type, size as usize))
+            let size = self.container_len(size)?;
+            Ok(TSetIdentifier::new(element_type, size))
         }
         async fn read_set_end(&mut self) -> Result<ReadSetEnd(())> {
-            instant(Ok(()))
+            self.leave();
+            Ok(())
         }
         async fn read_map_begin(&mut self) -> Result<ReadMapBegin(TMapIdentifier)> {
+            self.enter()?;
             let key_type = self.read_byte().await.and_then(field_type_from_u8)?;
             let value_type = self.read_byte().await.and_then(field_type_from_u8)?;
             let size = self.read_i32().await?;
-            Ok(TMapIdentifier::new(key_type, value_type, size as usize))
+            let size = self.container_len(size)?;
+            Ok(TMapIdentifier::new(key_type, value_type, size))
         }
         async fn read_map_end(&mut self) -> Result<ReadMapEnd(())> {
-            instant(Ok(()))
+            self.leave();
+            Ok(())
         }
         async fn read_byte(&mut self) -> Result<ReadByte(u8)> {
             require_data!(self, 1);
@@ -854,32 +2476,77 @@ impl<T: AsyncReadRent> TAsyncInputProtocol for TBinaryProtocol<T, BytesMut> {
             Ok(out)
         }
         async fn read_bytes(&mut self) -> Result<ReadBytes(Bytes)> {
-            let length = self.read_i32().await? as usize;
+            let length = self.read_i32().await?;
+            let length = self.string_len(length)?;
             require_data!(self, length);
             let out = self.attachment.split_to(length).freeze();
             Ok(out)
         }
         async fn read_string(&mut self) -> Result<ReadString(Bytes)> {
             let data = self.read_bytes().await?;
-            if data.is_empty() {
-                return Ok(data);
+            if self.config.validate_utf8 {
+                validate_utf8(&data)?;
             }
-            if let Some(chunk) = data.utf8_chunks().next() {
-                let s = chunk.valid();
-                if s.len() == data.len() {
-                    return Ok(data);
+            Ok(data)
+        }
+    }
+
+    #[cfg(feature = "float")]
+    #[inline]
+    async fn read_float(&mut self) -> Result<f32, CodecError> {
+        require_data!(self, 4);
+        Ok(self.attachment.get_f32())
+    }
+}
+
+macro_rules! impl_write_slice {
+    ($($fname: ident($ttype: ident, $ty: ty);)*) => {
+        $(
+            #[doc = concat!("Write `values` as a `list<", stringify!($ty), ">`.")]
+            pub fn $fname(&mut self, values: &[$ty]) {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+                self.trans.reserve(5 + values.len() * SIZE);
+                self.trans.put_u8(TType::$ttype as u8);
+                self.trans.put_i32(values.len() as i32);
+                let mut chunks = values.chunks_exact(4);
+                for chunk in &mut chunks {
+                    let mut data = [0; 4 * SIZE];
+                    data[..SIZE].copy_from_slice(&chunk[0].to_be_bytes());
+                    data[SIZE..2 * SIZE].copy_from_slice(&chunk[1].to_be_bytes());
+                    data[2 * SIZE..3 * SIZE].copy_from_slice(&chunk[2].to_be_bytes());
+                    data[3 * SIZE..].copy_from_slice(&chunk[3].to_be_bytes());
+                    self.trans.put_slice(&data);
+                }
+                for v in chunks.remainder() {
+                    self.trans.put_slice(&v.to_be_bytes());
                 }
             }
-            Err(CodecError::new(
-                CodecErrorKind::InvalidData,
-                "not a valid utf8 string",
-            ))
-        }
+        )*
+    };
+}
+
+macro_rules! impl_binary_writer {
+    ($($trans: ty => $buf: ty,)*) => {
+        $(
+impl TBinaryProtocol<$trans, PositionStack> {
+    #[inline]
+    fn write_length(&mut self, len: usize) {
+        let pos = self.attachment.pop().expect("illegal thrift pair");
+        let len = len as i32;
+        // Note: use big endian for length as thrift encoding
+        self.trans[pos..pos + 4].copy_from_slice(&len.to_be_bytes());
+    }
+
+    impl_write_slice! {
+        write_i16_slice(I16, i16);
+        write_i32_slice(I32, i32);
+        write_i64_slice(I64, i64);
+        write_double_slice(Double, f64);
     }
 }
 
-impl TOutputProtocol for TBinaryProtocol<&mut BytesMut, PositionStack> {
-    type Buf = BytesMut;
+impl TOutputProtocol for TBinaryProtocol<$trans, PositionStack> {
+    type Buf = $buf;
 
     #[inline]
     fn write_message_begin(&mut self, identifier: &TMessageIdentifier) {
@@ -991,6 +2658,12 @@ impl TOutputProtocol for TBinaryProtocol<&mut BytesMut, PositionStack> {
         self.trans.put_f64(d);
     }
 
+    #[cfg(feature = "float")]
+    #[inline]
+    fn write_float(&mut self, f: f32) {
+        self.trans.put_f32(f);
+    }
+
     #[inline]
     fn write_uuid(&mut self, u: [u8; 16]) {
         self.trans.put_slice(&u);
@@ -1007,11 +2680,358 @@ impl TOutputProtocol for TBinaryProtocol<&mut BytesMut, PositionStack> {
         self.write_bytes(s.as_bytes());
     }
 
+    #[inline]
+    fn write_bytes_owned(&mut self, b: Bytes) {
+        self.write_i32(b.len() as i32);
+        self.trans.put_bytes_owned(b);
+    }
+
     #[inline(always)]
     fn flush(&mut self) {}
 
     #[inline]
     fn buf(&mut self) -> &mut Self::Buf {
-        self.trans
+        self.trans.borrow_mut()
+    }
+}
+        )*
+    };
+}
+
+impl_binary_writer! {
+    &mut BytesMut => BytesMut,
+    BytesMut => BytesMut,
+    Vec<u8> => Vec<u8>,
+    SegmentedBuf => SegmentedBuf,
+}
+
+/// Async writer buffering into a [`TBinaryOwnedWriter`] until flushed.
+pub type TBinaryAsyncWriter<IO> = TBinaryProtocol<IO, TBinaryOwnedWriter>;
+
+impl<T: AsyncWriteRent> TBinaryProtocol<T, TBinaryOwnedWriter> {
+    pub fn new(io: T) -> Self {
+        Self::from_parts(io, TBinaryOwnedWriter::new(BytesMut::new()))
+    }
+}
+
+macro_rules! impl_async_write_fn {
+    ($($(#[$meta: meta])* $fname: ident($($arg: ident: $arg_type: ty),*);)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            fn $fname(&mut self $(,$arg: $arg_type)*) {
+                self.attachment.$fname($($arg),*)
+            }
+        )*
+    };
+}
+
+impl<T: AsyncWriteRent> TAsyncOutputProtocol for TBinaryProtocol<T, TBinaryOwnedWriter> {
+    impl_async_write_fn! {
+        write_message_begin(identifier: &TMessageIdentifier);
+        write_message_end();
+        write_struct_begin(identifier: &TStructIdentifier);
+        write_struct_end();
+        write_field_begin(field_type: TType, id: i16);
+        write_field_end();
+        write_field_stop();
+        write_list_begin(identifier: &TListIdentifier);
+        write_list_end(len: usize);
+        write_set_begin(identifier: &TSetIdentifier);
+        write_set_end(len: usize);
+        write_map_begin(identifier: &TMapIdentifier);
+        write_map_end(len: usize);
+        write_byte(b: u8);
+        write_bool(b: bool);
+        write_i8(i: i8);
+        write_i16(i: i16);
+        write_i32(i: i32);
+        write_i64(i: i64);
+        write_double(d: f64);
+        #[cfg(feature = "float")]
+        write_float(f: f32);
+        write_uuid(u: [u8; 16]);
+        write_bytes(b: &[u8]);
+        write_string(s: &str);
+    }
+
+    async fn flush(&mut self) -> Result<(), CodecError> {
+        if !self.attachment.trans.is_empty() {
+            let buf = self.attachment.trans.split().freeze();
+            self.trans.write_all(buf).await.0?;
+        }
+        self.trans.flush().await?;
+        Ok(())
+    }
+}
+
+impl TMetaPeek for TBinaryReader<'_> {
+    fn peek_meta(data: &[u8]) -> Result<MessageMeta<'_>, CodecError> {
+        let mut reader = TBinaryReader::new(Cursor::new(data));
+        let version = reader.read_i32()?;
+        if version & (VERSION_MASK as i32) != (VERSION_1 as i32) {
+            return Err(CodecError::new(
+                CodecErrorKind::BadVersion,
+                "Bad version in ReadMessageBegin",
+            ));
+        }
+        let msg_type = message_type_from_u8((version & 0xf) as u8)?;
+        let name = reader.read_string()?;
+        let seq_id = reader.read_i32()?;
+        Ok(MessageMeta {
+            name,
+            msg_type,
+            seq_id,
+            protocol: ProtocolId::Binary,
+        })
+    }
+}
+
+/// Write a message to `buf`: the message begin, the body written by `f` and
+/// the message end, preceded by the 4-byte frame length when `framed`.
+pub fn write_message<F, R>(
+    buf: &mut BytesMut,
+    identifier: &TMessageIdentifier,
+    framed: bool,
+    f: F,
+) -> R
+where
+    F: FnOnce(&mut TBinaryWriter<'_>) -> R,
+{
+    let start = buf.len();
+    if framed {
+        buf.put_u32(0);
+    }
+    let mut writer = TBinaryWriter::new(buf);
+    writer.write_message_begin(identifier);
+    let out = f(&mut writer);
+    writer.write_message_end();
+    if framed {
+        let len = (buf.len() - start - 4) as u32;
+        buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+    out
+}
+
+/// Find a field in an encoded struct by the ids along `path`, every id but
+/// the last naming a struct field. Returns the raw encoded value, or `None` if
+/// the field is absent or the data is malformed.
+pub fn extract_field<'a>(data: &'a [u8], path: &[i16]) -> Option<&'a [u8]> {
+    let mut reader = TBinaryReader::new(Cursor::new(data));
+    let Some((&last, parents)) = path.split_last() else {
+        return reader.skip_field_bytes(TType::Struct).ok();
+    };
+    for &id in parents {
+        if find_field(&mut reader, id)? != TType::Struct {
+            return None;
+        }
+    }
+    let field_type = find_field(&mut reader, last)?;
+    reader.skip_field_bytes(field_type).ok()
+}
+
+// Move to the value of field `id` of the struct at the reader's position.
+fn find_field(reader: &mut TBinaryReader<'_>, id: i16) -> Option<TType> {
+    loop {
+        match reader.read_field_header().ok()? {
+            (TType::Stop, _) => return None,
+            (field_type, field_id) if field_id == id => return Some(field_type),
+            (field_type, _) => reader.skip_field(field_type).ok()?,
+        }
+    }
+}
+
+/// A top-level field found by [`FieldIndex::build`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldEntry {
+    pub id: i16,
+    pub field_type: TType,
+    /// Range of the encoded value, the field header excluded.
+    pub range: Range<usize>,
+}
+
+/// Position of each top-level field of an encoded struct, found in a single
+/// scan, so fields can be read or patched in place without scanning again.
+#[derive(Clone, Debug, Default)]
+pub struct FieldIndex {
+    fields: SmallVec<[FieldEntry; MOST_COMMON_DEPTH]>,
+    end: usize,
+}
+
+impl FieldIndex {
+    pub fn build(data: &[u8]) -> Result<Self, CodecError> {
+        let mut reader = TBinaryReader::new(Cursor::new(data));
+        let mut index = Self::default();
+        loop {
+            let (field_type, id) = reader.read_field_header()?;
+            if field_type == TType::Stop {
+                break;
+            }
+            let range = reader.skip_field_ranged(field_type)?;
+            index.fields.push(FieldEntry {
+                id,
+                field_type,
+                range,
+            });
+        }
+        index.end = reader.trans.position() as usize;
+        Ok(index)
+    }
+
+    /// The field `id`, the first one if it's repeated.
+    #[inline]
+    pub fn get(&self, id: i16) -> Option<&FieldEntry> {
+        self.fields.iter().find(|field| field.id == id)
+    }
+
+    /// The encoded value of field `id` in `data`, the buffer the index was
+    /// built from.
+    #[inline]
+    pub fn value<'a>(&self, data: &'a [u8], id: i16) -> Option<&'a [u8]> {
+        data.get(self.get(id)?.range.clone())
+    }
+
+    /// Fields in encoding order.
+    #[inline]
+    pub fn fields(&self) -> &[FieldEntry] {
+        &self.fields
+    }
+
+    /// Length of the struct, the stop field included.
+    #[inline]
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
+
+/// [`TBinaryReader`] attaching to every error an [`ErrorContext`]: the offset
+/// where the failed read started, the ids and types of the fields being read
+/// and the failed operation, displayed e.g. as "invalid data at offset 42 in
+/// field 3(Struct).1(Binary) during read_bytes".
+pub struct TDiagnosticReader<'x> {
+    inner: TBinaryReader<'x>,
+    // field being read in each struct entered
+    path: SmallVec<[Option<(i16, TType)>; MOST_COMMON_DEPTH]>,
+}
+
+macro_rules! impl_diagnostic_fn {
+    ($($(#[$meta: meta])* $fname: ident($($arg: ident: $arg_type: ty),*) -> $out: ty;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            fn $fname(&mut self $(,$arg: $arg_type)*) -> Result<$out, CodecError> {
+                let offset = self.inner.bytes_consumed();
+                self.inner
+                    .$fname($($arg),*)
+                    .map_err(|e| Self::annotate(&self.path, offset, stringify!($fname), e))
+            }
+        )*
+    };
+}
+
+impl<'x> TDiagnosticReader<'x> {
+    pub fn new(reader: TBinaryReader<'x>) -> Self {
+        Self {
+            inner: reader,
+            path: SmallVec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> TBinaryReader<'x> {
+        self.inner
+    }
+
+    #[cold]
+    fn annotate(
+        path: &[Option<(i16, TType)>],
+        offset: usize,
+        operation: &'static str,
+        e: CodecError,
+    ) -> CodecError {
+        e.with_context(ErrorContext {
+            offset: Some(offset),
+            field_path: path.iter().map_while(|field| *field).collect(),
+            operation: Some(operation),
+        })
+    }
+}
+
+impl<'x> TInputProtocol<'x> for TDiagnosticReader<'x> {
+    type Buf<'b> = Cursor<&'b [u8]>
+    where
+        Self: 'b;
+
+    #[inline]
+    fn read_message_begin(&mut self) -> Result<TMessageIdentifier<'_>, CodecError> {
+        let offset = self.inner.bytes_consumed();
+        self.inner
+            .read_message_begin()
+            .map_err(|e| Self::annotate(&self.path, offset, "read_message_begin", e))
+    }
+
+    #[inline]
+    fn read_struct_begin(&mut self) -> Result<TStructIdentifier, CodecError> {
+        let offset = self.inner.bytes_consumed();
+        let identifier = self
+            .inner
+            .read_struct_begin()
+            .map_err(|e| Self::annotate(&self.path, offset, "read_struct_begin", e))?;
+        self.path.push(None);
+        Ok(identifier)
+    }
+
+    #[inline]
+    fn read_struct_end(&mut self) -> Result<(), CodecError> {
+        self.path.pop();
+        self.inner.read_struct_end()
+    }
+
+    #[inline]
+    fn read_field_begin(&mut self) -> Result<TFieldIdentifier, CodecError> {
+        let offset = self.inner.bytes_consumed();
+        let identifier = self
+            .inner
+            .read_field_begin()
+            .map_err(|e| Self::annotate(&self.path, offset, "read_field_begin", e))?;
+        if let Some(field) = self.path.last_mut() {
+            *field = match identifier.field_type {
+                TType::Stop => None,
+                field_type => Some((identifier.id.unwrap_or_default(), field_type)),
+            };
+        }
+        Ok(identifier)
+    }
+
+    impl_diagnostic_fn! {
+        read_message_end() -> ();
+        read_field_end() -> ();
+        read_list_begin() -> TListIdentifier;
+        read_list_end() -> ();
+        read_set_begin() -> TSetIdentifier;
+        read_set_end() -> ();
+        read_map_begin() -> TMapIdentifier;
+        read_map_end() -> ();
+        read_byte() -> u8;
+        read_bool() -> bool;
+        read_i8() -> i8;
+        read_i16() -> i16;
+        read_i32() -> i32;
+        read_i64() -> i64;
+        read_double() -> f64;
+        #[cfg(feature = "float")]
+        read_float() -> f32;
+        read_uuid() -> [u8; 16];
+        read_bytes() -> &'x [u8];
+        read_string() -> &'x str;
+        skip_field(ttype: TType) -> ();
+    }
+
+    #[inline]
+    fn buf<'a>(&'a mut self) -> &'a mut Self::Buf<'x>
+    where
+        'x: 'a,
+    {
+        self.inner.buf()
     }
 }
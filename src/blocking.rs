@@ -0,0 +1,168 @@
+//! Blocking binary reader over a [`std::io::Read`].
+//!
+//! [`TBinaryBlockingReader`] runs the async binary protocol on an io that is
+//! always ready, so offline tools and tests decode with the same code as the
+//! servers, without a runtime.
+
+use std::{
+    future::Future,
+    io::{self, Read},
+    pin::pin,
+    ptr,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use bytes::{Bytes, BytesMut};
+use monoio::{
+    buf::{IoBufMut, IoVecBufMut},
+    io::AsyncReadRent,
+    BufResult,
+};
+
+use crate::{
+    binary::TBinaryProtocol,
+    protocol::{NameInterner, ProtocolConfig, TAsyncInputProtocol, TAsyncSkipProtocol},
+    thrift::{
+        TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TSetIdentifier,
+        TStructIdentifier, TType,
+    },
+    CodecError,
+};
+
+// Size of the scratch buffer reads go through.
+const READ_CHUNK: usize = 64 * 1024;
+
+// Adapter whose reads complete immediately, by blocking.
+//
+// `Read` may not write to uninitialized memory, so the reads go through a
+// scratch buffer zeroed once instead of zeroing the room of every read, which
+// for a large binary is reserved whole up front.
+struct BlockingRead<R> {
+    reader: R,
+    scratch: Vec<u8>,
+}
+
+impl<R> BlockingRead<R> {
+    #[inline]
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read> AsyncReadRent for BlockingRead<R> {
+    fn read<T: IoBufMut>(&mut self, mut buf: T) -> impl Future<Output = BufResult<usize, T>> {
+        if self.scratch.is_empty() {
+            self.scratch = vec![0; READ_CHUNK];
+        }
+        let len = buf.bytes_total().min(READ_CHUNK);
+        let result = self.reader.read(&mut self.scratch[..len]);
+        if let Ok(n) = result {
+            unsafe {
+                ptr::copy_nonoverlapping(self.scratch.as_ptr(), buf.write_ptr(), n);
+                buf.set_init(n);
+            }
+        }
+        async move { (result, buf) }
+    }
+
+    async fn readv<T: IoVecBufMut>(&mut self, buf: T) -> BufResult<usize, T> {
+        // the protocol only reads into a single buffer
+        (Err(io::ErrorKind::Unsupported.into()), buf)
+    }
+}
+
+// Poll `f` once, it never waits since the io never does.
+fn block_on<F: Future>(f: F) -> F::Output {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |_| RawWaker::new(ptr::null(), &VTABLE),
+        |_| {},
+        |_| {},
+        |_| {},
+    );
+    let waker = unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) };
+    match pin!(f).poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(out) => out,
+        Poll::Pending => unreachable!("blocking read is always ready"),
+    }
+}
+
+macro_rules! impl_blocking_fn {
+    ($($(#[$meta: meta])* $fname: ident($($arg: ident: $arg_type: ty),*) -> $out: ty;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            pub fn $fname(&mut self $(,$arg: $arg_type)*) -> Result<$out, CodecError> {
+                block_on(self.inner.$fname($($arg),*))
+            }
+        )*
+    };
+}
+
+/// Binary reader over a [`std::io::Read`], buffering what it reads.
+pub struct TBinaryBlockingReader<R> {
+    inner: TBinaryProtocol<BlockingRead<R>, BytesMut>,
+}
+
+impl<R: Read> TBinaryBlockingReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            inner: TBinaryProtocol::from_parts(BlockingRead::new(reader), BytesMut::new()),
+        }
+    }
+
+    /// Apply the limits and checks of `config`.
+    #[inline]
+    pub fn with_config(mut self, config: ProtocolConfig) -> Self {
+        self.inner.set_config(config);
+        self
+    }
+
+    /// Resolve the names of the messages read through `names`.
+    #[inline]
+    pub fn with_name_interner(mut self, names: NameInterner) -> Self {
+        self.inner.set_name_interner(Some(names));
+        self
+    }
+
+    /// The reader and the data buffered but not consumed yet.
+    #[inline]
+    pub fn into_inner(self) -> (R, BytesMut) {
+        let (trans, attachment) = self.inner.into_inner();
+        (trans.reader, attachment)
+    }
+
+    impl_blocking_fn! {
+        read_message_begin() -> TMessageIdentifier<'static>;
+        read_message_end() -> ();
+        read_struct_begin() -> TStructIdentifier;
+        read_struct_end() -> ();
+        read_field_begin() -> TFieldIdentifier;
+        read_field_end() -> ();
+        read_list_begin() -> TListIdentifier;
+        read_list_end() -> ();
+        read_set_begin() -> TSetIdentifier;
+        read_set_end() -> ();
+        read_map_begin() -> TMapIdentifier;
+        read_map_end() -> ();
+        read_byte() -> u8;
+        read_bool() -> bool;
+        read_i8() -> i8;
+        read_i16() -> i16;
+        read_i32() -> i32;
+        read_i64() -> i64;
+        read_double() -> f64;
+        #[cfg(feature = "float")]
+        read_float() -> f32;
+        read_uuid() -> [u8; 16];
+        read_bytes() -> Bytes;
+        read_string() -> Bytes;
+        peek_message_begin() -> TMessageIdentifier<'static>;
+        skip_message() -> ();
+        skip_field(ttype: TType) -> ();
+        skip_until_field(id: i16) -> Option<TType>;
+        skip_remaining() -> ();
+    }
+}
@@ -0,0 +1,120 @@
+//! Encoding several frames for a single write.
+//!
+//! A server answering pipelined requests can push every response into a
+//! [`BatchEncoder`] and flush the whole batch at once, while still knowing
+//! where each frame ends.
+
+use bytes::{Bytes, BytesMut};
+use monoio_codec::Encoder;
+use smallvec::SmallVec;
+
+/// Encoded frames laid out back to back.
+#[derive(Clone, Debug, Default)]
+pub struct EncodedBatch {
+    pub data: Bytes,
+    /// End offset of each frame in `data`.
+    pub frame_ends: SmallVec<[usize; 8]>,
+}
+
+impl EncodedBatch {
+    /// Number of frames.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.frame_ends.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.frame_ends.is_empty()
+    }
+
+    /// The `index`-th frame, without copying.
+    pub fn frame(&self, index: usize) -> Option<Bytes> {
+        let end = *self.frame_ends.get(index)?;
+        let start = if index == 0 {
+            0
+        } else {
+            self.frame_ends[index - 1]
+        };
+        Some(self.data.slice(start..end))
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = Bytes> + '_ {
+        (0..self.len()).filter_map(|index| self.frame(index))
+    }
+}
+
+/// Encoder accumulating frames into one buffer.
+pub struct BatchEncoder<E> {
+    inner: E,
+    buf: BytesMut,
+    frame_ends: SmallVec<[usize; 8]>,
+}
+
+impl<E> BatchEncoder<E> {
+    pub fn new(inner: E) -> Self {
+        Self::with_capacity(inner, 0)
+    }
+
+    pub fn with_capacity(inner: E, capacity: usize) -> Self {
+        Self {
+            inner,
+            buf: BytesMut::with_capacity(capacity),
+            frame_ends: SmallVec::new(),
+        }
+    }
+
+    /// Encode `item` as the next frame of the batch.
+    ///
+    /// If encoding fails the frames pushed before are kept and nothing of
+    /// `item` is left in the batch.
+    pub fn push<Item>(&mut self, item: Item) -> Result<(), E::Error>
+    where
+        E: Encoder<Item>,
+    {
+        let start = self.buf.len();
+        if let Err(e) = self.inner.encode(item, &mut self.buf) {
+            self.buf.truncate(start);
+            return Err(e);
+        }
+        self.frame_ends.push(self.buf.len());
+        Ok(())
+    }
+
+    /// Number of frames pushed since the last `take`.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.frame_ends.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.frame_ends.is_empty()
+    }
+
+    /// Bytes encoded since the last `take`.
+    #[inline]
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Take the frames encoded so far, leaving the batch empty.
+    pub fn take(&mut self) -> EncodedBatch {
+        EncodedBatch {
+            data: self.buf.split().freeze(),
+            frame_ends: std::mem::take(&mut self.frame_ends),
+        }
+    }
+
+    /// Write the frames encoded so far to `dst`, leaving the batch empty.
+    pub fn flush_into(&mut self, dst: &mut BytesMut) {
+        if dst.is_empty() {
+            // reuse the allocation instead of copying
+            *dst = self.buf.split();
+        } else {
+            dst.extend_from_slice(&self.buf);
+            self.buf.clear();
+        }
+        self.frame_ends.clear();
+    }
+}
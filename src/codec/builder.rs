@@ -0,0 +1,89 @@
+//! Type-checked composition of codec layers.
+//!
+//! ```ignore
+//! let codec = CodecBuilder::new(RawPayloadCodec::new()).framed().build();
+//! ```
+//!
+//! Each layer is only offered where it makes sense on the wire: a payload can
+//! be framed or put behind a TTHeader, a framed payload can be put behind a
+//! mesh header, but a TTHeader is never framed again.
+
+use std::marker::PhantomData;
+
+use super::{
+    framed::FramedHeader,
+    mesh::MeshHeaderPayloadCodec,
+    ttheader::{TTHeaderDecoderConfig, TTHeaderPayloadCodec},
+};
+
+/// Markers for the outermost layer of a [`CodecBuilder`].
+pub mod layer {
+    pub struct Payload;
+    pub struct Framed;
+    pub struct TTHeader;
+    pub struct Mesh;
+}
+
+pub struct CodecBuilder<C, L = layer::Payload> {
+    codec: C,
+    _layer: PhantomData<L>,
+}
+
+impl<C, L> CodecBuilder<C, L> {
+    #[inline]
+    fn wrap<N, M>(codec: N) -> CodecBuilder<N, M> {
+        CodecBuilder {
+            codec,
+            _layer: PhantomData,
+        }
+    }
+
+    /// The composed codec, acting as both decoder and encoder.
+    #[inline]
+    pub fn build(self) -> C {
+        self.codec
+    }
+}
+
+impl<P> CodecBuilder<P, layer::Payload> {
+    /// Start from the codec of the bare payload.
+    #[inline]
+    pub fn new(payload: P) -> Self {
+        Self::wrap(payload)
+    }
+
+    /// `Framed(payload)`: a 4-byte length in front of the payload.
+    #[inline]
+    pub fn framed(self) -> CodecBuilder<FramedHeader<P>, layer::Framed> {
+        Self::wrap(FramedHeader::new(self.codec))
+    }
+
+    /// `TTHeader(payload)`.
+    #[inline]
+    pub fn ttheader(self) -> CodecBuilder<TTHeaderPayloadCodec<P>, layer::TTHeader> {
+        Self::wrap(TTHeaderPayloadCodec::new(self.codec))
+    }
+
+    /// `TTHeader(payload)` decoded with custom limits.
+    #[inline]
+    pub fn ttheader_with_config(
+        self,
+        config: TTHeaderDecoderConfig,
+    ) -> CodecBuilder<TTHeaderPayloadCodec<P>, layer::TTHeader> {
+        Self::wrap(TTHeaderPayloadCodec::with_config(self.codec, config))
+    }
+
+    /// `Mesh(payload)`, the payload codec must find the end of the payload.
+    #[inline]
+    pub fn mesh(self) -> CodecBuilder<MeshHeaderPayloadCodec<P>, layer::Mesh> {
+        Self::wrap(MeshHeaderPayloadCodec::new(self.codec))
+    }
+}
+
+impl<C> CodecBuilder<C, layer::Framed> {
+    /// `Mesh(Framed(payload))`.
+    #[inline]
+    pub fn mesh(self) -> CodecBuilder<MeshHeaderPayloadCodec<C>, layer::Mesh> {
+        Self::wrap(MeshHeaderPayloadCodec::new(self.codec))
+    }
+}
@@ -0,0 +1,244 @@
+//! Protocol detection for servers accepting several transports on one port.
+//!
+//! [`detect`] sniffs the first bytes of a connection, [`DetectCodec`] detects
+//! once and then keeps decoding frames of that protocol. The negotiated
+//! protocol is shared through a [`Negotiated`] handle, so the write half of a
+//! split connection replies in the protocol the read half detected.
+
+use std::{cell::Cell, io, rc::Rc};
+
+use bytes::BytesMut;
+use monoio_codec::{Decoded, Decoder, Encoder};
+
+use super::{
+    framed::FramedHeader,
+    ttheader::{TTHeaderPayload, TTHeaderPayloadCodec},
+};
+use crate::{CodecError, CodecErrorKind};
+
+const BINARY_VERSION_1: [u8; 2] = [0x80, 0x01];
+const COMPACT_PROTOCOL_ID: u8 = 0x82;
+const TTHEADER_MAGIC: [u8; 2] = [0x10, 0x00];
+// Includes the HTTP/2 connection preface.
+const HTTP_METHODS: [&[u8; 4]; 10] = [
+    b"GET ", b"POST", b"PUT ", b"HEAD", b"DELE", b"OPTI", b"PATC", b"CONN", b"TRAC", b"PRI ",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DetectedProtocol {
+    /// TTHeader frame, with any payload protocol.
+    TTHeader,
+    /// Binary message behind a 4-byte length.
+    FramedBinary,
+    /// Binary message without framing.
+    Binary,
+    /// Compact message without framing.
+    Compact,
+    /// HTTP/1.x request or HTTP/2 preface.
+    Http,
+}
+
+/// Detect the protocol from the first bytes of a connection.
+///
+/// Returns `InsufficientAtLeast` until enough bytes are buffered to decide,
+/// and an `InvalidData` error when nothing matches.
+pub fn detect(src: &[u8]) -> Result<Decoded<DetectedProtocol>, CodecError> {
+    // Negative frame lengths are illegal, so a leading 0x80 or 0x82 can only
+    // be an unframed message.
+    match src.first() {
+        None => return Ok(Decoded::InsufficientAtLeast(1)),
+        Some(&COMPACT_PROTOCOL_ID) => return Ok(Decoded::Some(DetectedProtocol::Compact)),
+        Some(_) if src.len() < 2 => return Ok(Decoded::InsufficientAtLeast(2)),
+        Some(_) if src[..2] == BINARY_VERSION_1 => {
+            return Ok(Decoded::Some(DetectedProtocol::Binary))
+        }
+        Some(_) => {}
+    }
+    if src.len() < 4 {
+        return Ok(Decoded::InsufficientAtLeast(4));
+    }
+    if HTTP_METHODS.iter().any(|m| src[..4] == m[..]) {
+        return Ok(Decoded::Some(DetectedProtocol::Http));
+    }
+    if src.len() < 8 {
+        return Ok(Decoded::InsufficientAtLeast(8));
+    }
+    if src[4..6] == TTHEADER_MAGIC {
+        return Ok(Decoded::Some(DetectedProtocol::TTHeader));
+    }
+    if src[4..6] == BINARY_VERSION_1 {
+        return Ok(Decoded::Some(DetectedProtocol::FramedBinary));
+    }
+    Err(CodecError::new(
+        CodecErrorKind::UnsupportedProtocol,
+        "unknown protocol",
+    ))
+}
+
+/// Protocol negotiated on a connection, shared by the codecs handling it.
+#[derive(Clone, Debug, Default)]
+pub struct Negotiated(Rc<Cell<Option<DetectedProtocol>>>);
+
+impl Negotiated {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The negotiated protocol, `None` until the first frame is detected.
+    #[inline]
+    pub fn get(&self) -> Option<DetectedProtocol> {
+        self.0.get()
+    }
+
+    #[inline]
+    fn set(&self, protocol: DetectedProtocol) {
+        self.0.set(Some(protocol));
+    }
+}
+
+/// Item handled by [`DetectCodec`], tagged with its protocol.
+pub enum DetectedItem<T, F, B> {
+    TTHeader(Box<TTHeaderPayload<T>>),
+    FramedBinary(F),
+    Binary(B),
+}
+
+impl<T, F, B> DetectedItem<T, F, B> {
+    #[inline]
+    pub fn protocol(&self) -> DetectedProtocol {
+        match self {
+            DetectedItem::TTHeader(_) => DetectedProtocol::TTHeader,
+            DetectedItem::FramedBinary(_) => DetectedProtocol::FramedBinary,
+            DetectedItem::Binary(_) => DetectedProtocol::Binary,
+        }
+    }
+}
+
+/// Decoder detecting the protocol of the first frame and decoding every frame
+/// with the matching decoder.
+///
+/// `T` decodes TTHeader payloads, `F` the body of length framed messages and
+/// `B` unframed binary messages. Compact and HTTP are detected but not
+/// decoded, the decode fails with an `Unsupported` error and the caller can
+/// check [`DetectCodec::protocol`] to hand the connection off.
+///
+/// Once negotiated the connection is locked to the protocol: frames are never
+/// sniffed again and encoding an item of another protocol fails.
+pub struct DetectCodec<T, F, B> {
+    protocol: Negotiated,
+    ttheader: TTHeaderPayloadCodec<T>,
+    framed: FramedHeader<F>,
+    binary: B,
+}
+
+impl<T, F, B> DetectCodec<T, F, B> {
+    pub fn new(ttheader: T, framed: F, binary: B) -> Self {
+        Self::with_negotiated(Negotiated::new(), ttheader, framed, binary)
+    }
+
+    /// Share the negotiated protocol with another codec of the connection,
+    /// typically the decoder of the read half.
+    pub fn with_negotiated(protocol: Negotiated, ttheader: T, framed: F, binary: B) -> Self {
+        Self {
+            protocol,
+            ttheader: TTHeaderPayloadCodec::new(ttheader),
+            framed: FramedHeader::new(framed),
+            binary,
+        }
+    }
+
+    /// Protocol of the connection, once detected.
+    #[inline]
+    pub fn protocol(&self) -> Option<DetectedProtocol> {
+        self.protocol.get()
+    }
+
+    /// Handle to the negotiated protocol of this connection.
+    #[inline]
+    pub fn negotiated(&self) -> Negotiated {
+        self.protocol.clone()
+    }
+}
+
+impl<T, F, B> Decoder for DetectCodec<T, F, B>
+where
+    T: Decoder,
+    T::Error: From<io::Error>,
+    F: Decoder<Error = T::Error>,
+    B: Decoder<Error = T::Error>,
+{
+    type Item = DetectedItem<T::Item, F::Item, B::Item>;
+    type Error = T::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        let protocol = match self.protocol.get() {
+            Some(protocol) => protocol,
+            None => match detect(src).map_err(io::Error::from)? {
+                Decoded::Some(protocol) => {
+                    self.protocol.set(protocol);
+                    protocol
+                }
+                Decoded::Insufficient => return Ok(Decoded::Insufficient),
+                Decoded::InsufficientAtLeast(n) => return Ok(Decoded::InsufficientAtLeast(n)),
+            },
+        };
+        macro_rules! decode_as {
+            ($codec: expr, $wrap: expr) => {
+                match $codec.decode(src)? {
+                    Decoded::Some(item) => Ok(Decoded::Some($wrap(item))),
+                    Decoded::Insufficient => Ok(Decoded::Insufficient),
+                    Decoded::InsufficientAtLeast(n) => Ok(Decoded::InsufficientAtLeast(n)),
+                }
+            };
+        }
+        match protocol {
+            DetectedProtocol::TTHeader => {
+                decode_as!(self.ttheader, |item| DetectedItem::TTHeader(Box::new(item)))
+            }
+            DetectedProtocol::FramedBinary => decode_as!(self.framed, DetectedItem::FramedBinary),
+            DetectedProtocol::Binary => decode_as!(self.binary, DetectedItem::Binary),
+            DetectedProtocol::Compact | DetectedProtocol::Http => {
+                Err(io::Error::from(CodecError::new(
+                    CodecErrorKind::UnsupportedProtocol,
+                    format!("no decoder for detected protocol {protocol:?}"),
+                ))
+                .into())
+            }
+        }
+    }
+}
+
+impl<T, F, B, TI, FI, BI> Encoder<DetectedItem<TI, FI, BI>> for DetectCodec<T, F, B>
+where
+    T: Encoder<TI>,
+    T::Error: From<io::Error>,
+    F: Encoder<FI, Error = T::Error>,
+    B: Encoder<BI, Error = T::Error>,
+{
+    type Error = T::Error;
+
+    fn encode(
+        &mut self,
+        item: DetectedItem<TI, FI, BI>,
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        if let Some(protocol) = self.protocol.get() {
+            if protocol != item.protocol() {
+                return Err(io::Error::from(CodecError::new(
+                    CodecErrorKind::InvalidData,
+                    format!(
+                        "connection negotiated {protocol:?}, cannot encode {:?}",
+                        item.protocol()
+                    ),
+                ))
+                .into());
+            }
+        }
+        match item {
+            DetectedItem::TTHeader(item) => self.ttheader.encode(*item, dst),
+            DetectedItem::FramedBinary(item) => self.framed.encode(item, dst),
+            DetectedItem::Binary(item) => self.binary.encode(item, dst),
+        }
+    }
+}
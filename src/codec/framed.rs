@@ -1,8 +1,11 @@
 use std::{io, ptr::copy_nonoverlapping};
 
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
 use monoio_codec::{Decoded, Decoder, Encoder};
 
+use super::parts::{parts_len, EncodeParts, PartsEncoder};
+use crate::{CodecError, CodecErrorKind};
+
 pub struct FramedHeader<T> {
     inner: T,
 }
@@ -11,6 +14,51 @@ impl<T> FramedHeader<T> {
     pub fn new(inner: T) -> Self {
         Self { inner }
     }
+
+    /// Encode `item` whose encoded size is known to be `len` bytes.
+    ///
+    /// The length is written up front instead of being patched in after the
+    /// payload, and the buffer is reserved once. Fails if the payload turns out
+    /// to have a different size, in which case `dst` is left as it was.
+    pub fn encode_sized<Item>(
+        &mut self,
+        item: Item,
+        len: usize,
+        dst: &mut bytes::BytesMut,
+    ) -> Result<(), T::Error>
+    where
+        T: Encoder<Item>,
+        T::Error: From<io::Error>,
+    {
+        if len > i32::MAX as usize {
+            return Err(io::Error::from(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "thrift body too large",
+            ))
+            .into());
+        }
+        let start = dst.len();
+        dst.reserve(4 + len);
+        dst.put_i32(len as i32);
+        let offset = dst.len();
+        if let Err(e) = self.inner.encode(item, dst) {
+            dst.truncate(start);
+            return Err(e);
+        }
+        let actual = dst.len() - offset;
+        if actual != len {
+            dst.truncate(start);
+            return Err(io::Error::from(CodecError::new(
+                CodecErrorKind::LengthMismatch {
+                    expected: len,
+                    actual,
+                },
+                format!("thrift body size mismatch: expected {len}, got {actual}"),
+            ))
+            .into());
+        }
+        Ok(())
+    }
 }
 
 impl<T: Decoder> Decoder for FramedHeader<T>
@@ -29,9 +77,11 @@ where
             unsafe { copy_nonoverlapping(src.as_ptr(), length.as_mut_ptr(), 4) };
             let length = i32::from_be_bytes(length);
             if length <= 0 {
-                return Err(
-                    io::Error::new(io::ErrorKind::Other, "illegal thrift body size").into(),
-                );
+                return Err(io::Error::from(CodecError::new(
+                    CodecErrorKind::InvalidData,
+                    "illegal thrift body size",
+                ))
+                .into());
             }
             length as usize
         };
@@ -64,3 +114,24 @@ impl<T: Encoder<Item>, Item> Encoder<Item> for FramedHeader<T> {
         Ok(())
     }
 }
+
+impl<T: PartsEncoder<Item>, Item> PartsEncoder<Item> for FramedHeader<T>
+where
+    T::Error: From<io::Error>,
+{
+    fn encode_parts(&mut self, item: Item, parts: &mut EncodeParts) -> Result<(), Self::Error> {
+        let length_index = parts.len();
+        parts.push(Bytes::new());
+        self.inner.encode_parts(item, parts)?;
+        let len = parts_len(parts, length_index + 1);
+        if len > i32::MAX as usize {
+            return Err(io::Error::from(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "thrift body too large",
+            ))
+            .into());
+        }
+        parts[length_index] = Bytes::copy_from_slice(&(len as i32).to_be_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Small ordered map for TTHeader string headers.
+//!
+//! A request typically carries only a handful of string headers, so a linear
+//! scan over inline storage beats hashing and avoids allocating on decode.
+//! Past a few dozen headers keys are indexed by hash, so a frame packed with
+//! headers doesn't make decoding quadratic.
+//!
+//! This is a breaking change for users of `ttheader::HeaderMap`, which used to
+//! be an alias of `HashMap<SmolStr, SmolStr>`. The common map methods are kept
+//! and [`From`] converts between the two.
+
+use std::collections::HashMap;
+
+use smallvec::SmallVec;
+use smol_str::SmolStr;
+
+const INLINE_HEADERS: usize = 4;
+
+// Above this many headers, keys are looked up through a hash index.
+const INDEX_THRESHOLD: usize = 32;
+
+/// Insertion ordered map from header key to value.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderMap {
+    entries: SmallVec<[(SmolStr, SmolStr); INLINE_HEADERS]>,
+    // position of each key in `entries`, once there are many of them
+    index: Option<HashMap<SmolStr, usize>>,
+}
+
+impl HeaderMap {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: SmallVec::with_capacity(capacity),
+            index: None,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index = None;
+    }
+
+    #[inline]
+    fn position(&self, key: &str) -> Option<usize> {
+        match self.index {
+            Some(ref index) => index.get(key).copied(),
+            None => self.entries.iter().position(|(k, _)| k == key),
+        }
+    }
+
+    // Index the keys, once there are enough of them.
+    fn build_index(&mut self) {
+        self.index = Some(
+            self.entries
+                .iter()
+                .enumerate()
+                .map(|(pos, (k, _))| (k.clone(), pos))
+                .collect(),
+        );
+    }
+
+    #[inline]
+    pub fn get(&self, key: &str) -> Option<&SmolStr> {
+        self.position(key).map(|pos| &self.entries[pos].1)
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut SmolStr> {
+        self.position(key).map(|pos| &mut self.entries[pos].1)
+    }
+
+    #[inline]
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.position(key).is_some()
+    }
+
+    /// Insert a header, returning the previous value of `key` if any.
+    #[inline]
+    pub fn insert(&mut self, key: SmolStr, val: SmolStr) -> Option<SmolStr> {
+        match self.position(&key) {
+            Some(pos) => Some(std::mem::replace(&mut self.entries[pos].1, val)),
+            None => {
+                if let Some(ref mut index) = self.index {
+                    index.insert(key.clone(), self.entries.len());
+                }
+                self.entries.push((key, val));
+                if self.index.is_none() && self.entries.len() > INDEX_THRESHOLD {
+                    self.build_index();
+                }
+                None
+            }
+        }
+    }
+
+    /// Remove a header, keeping the order of the others.
+    #[inline]
+    pub fn remove(&mut self, key: &str) -> Option<SmolStr> {
+        let pos = self.position(key)?;
+        let val = self.entries.remove(pos).1;
+        if let Some(ref mut index) = self.index {
+            index.remove(key);
+            // the following entries moved down by one
+            for (k, _) in &self.entries[pos..] {
+                if let Some(p) = index.get_mut(k.as_str()) {
+                    *p -= 1;
+                }
+            }
+        }
+        Some(val)
+    }
+
+    #[inline]
+    pub fn retain(&mut self, mut f: impl FnMut(&SmolStr, &mut SmolStr) -> bool) {
+        let Some(ref mut index) = self.index else {
+            self.entries.retain(|(k, v)| f(k, v));
+            return;
+        };
+        let mut kept = 0;
+        self.entries.retain(|(k, v)| {
+            if f(k, v) {
+                if let Some(p) = index.get_mut(k.as_str()) {
+                    *p = kept;
+                }
+                kept += 1;
+                true
+            } else {
+                index.remove(k.as_str());
+                false
+            }
+        });
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&SmolStr, &SmolStr)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    #[inline]
+    pub fn keys(&self) -> impl Iterator<Item = &SmolStr> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+}
+
+// Like a `HashMap`, maps holding the same headers are equal whatever the
+// order they were inserted in.
+impl PartialEq for HeaderMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl Eq for HeaderMap {}
+
+impl Extend<(SmolStr, SmolStr)> for HeaderMap {
+    fn extend<I: IntoIterator<Item = (SmolStr, SmolStr)>>(&mut self, iter: I) {
+        for (key, val) in iter {
+            self.insert(key, val);
+        }
+    }
+}
+
+impl FromIterator<(SmolStr, SmolStr)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (SmolStr, SmolStr)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl From<HashMap<SmolStr, SmolStr>> for HeaderMap {
+    fn from(map: HashMap<SmolStr, SmolStr>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl From<HeaderMap> for HashMap<SmolStr, SmolStr> {
+    fn from(map: HeaderMap) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl IntoIterator for HeaderMap {
+    type Item = (SmolStr, SmolStr);
+    type IntoIter = smallvec::IntoIter<[(SmolStr, SmolStr); INLINE_HEADERS]>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for HeaderMap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for HeaderMap {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = HeaderMap;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a map of string headers")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut access: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut map = HeaderMap::new();
+                while let Some((key, val)) = access.next_entry()? {
+                    map.insert(key, val);
+                }
+                Ok(map)
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
@@ -0,0 +1,222 @@
+//! MeshHeader is the header Kitex mesh sidecars put in front of a thrift frame.
+//!
+//! ```text
+//! +-----------2Byte-----------+-----------2Byte-----------+
+//! |       MAGIC (0xFFAF)      |           flags           |
+//! +---------------------------+---------------------------+
+//! |  header size (2Byte)      | string KVs ... | payload ...
+//! +---------------------------+---------------------------+
+//! ```
+//!
+//! The header size counts the bytes of the string KVs, each key and value is
+//! prefixed by its u16 length. The header does not carry the payload length, so
+//! the payload codec must be able to find the end of the payload itself.
+
+use std::io;
+
+use bytes::{Buf, BufMut, BytesMut};
+use monoio_codec::{Decoded, Decoder, Encoder};
+use smol_str::SmolStr;
+
+use super::header_map::HeaderMap;
+use crate::{CodecError, CodecErrorKind};
+
+pub const MESH_HEADER_MAGIC: u16 = 0xFFAF;
+const MESH_HEADER_FIXED_LENGTH: usize = 6;
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MeshHeader {
+    pub flags: u16,
+    pub headers: HeaderMap,
+}
+
+impl MeshHeader {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Exact number of bytes `MeshHeaderEncoder` writes for this header.
+    pub fn encoded_len(&self) -> usize {
+        MESH_HEADER_FIXED_LENGTH
+            + self
+                .headers
+                .iter()
+                .map(|(key, val)| 4 + key.len() + val.len())
+                .sum::<usize>()
+    }
+
+    /// Total length of the mesh header at the front of `src`.
+    fn detect(src: &[u8]) -> io::Result<Decoded<usize>> {
+        if src.len() < MESH_HEADER_FIXED_LENGTH {
+            return Ok(Decoded::InsufficientAtLeast(MESH_HEADER_FIXED_LENGTH));
+        }
+        if u16::from_be_bytes([src[0], src[1]]) != MESH_HEADER_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "illegal mesh header",
+            ));
+        }
+        let header_size = u16::from_be_bytes([src[4], src[5]]) as usize;
+        let total = MESH_HEADER_FIXED_LENGTH + header_size;
+        if src.len() < total {
+            return Ok(Decoded::InsufficientAtLeast(total));
+        }
+        Ok(Decoded::Some(total))
+    }
+
+    // `src` must hold the whole header as reported by `detect`.
+    fn decode_header(src: &mut BytesMut, total: usize) -> io::Result<Self> {
+        fn read_str(buf: &mut &[u8]) -> io::Result<SmolStr> {
+            if buf.len() < 2 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid data"));
+            }
+            let len = buf.get_u16() as usize;
+            if buf.len() < len {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid data"));
+            }
+            let s = std::str::from_utf8(&buf[..len])
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid utf8 header"))?;
+            let s = SmolStr::new(s);
+            buf.advance(len);
+            Ok(s)
+        }
+
+        let header_buf = src.split_to(total);
+        let flags = u16::from_be_bytes([header_buf[2], header_buf[3]]);
+        let mut buf = &header_buf[MESH_HEADER_FIXED_LENGTH..];
+        let mut headers = HeaderMap::new();
+        while !buf.is_empty() {
+            let key = read_str(&mut buf)?;
+            let val = read_str(&mut buf)?;
+            headers.insert(key, val);
+        }
+        Ok(Self { flags, headers })
+    }
+}
+
+#[derive(Default)]
+pub struct MeshHeaderDecoder;
+
+impl MeshHeaderDecoder {
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+impl Decoder for MeshHeaderDecoder {
+    type Item = MeshHeader;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        match MeshHeader::detect(src)? {
+            Decoded::Some(total) => Ok(Decoded::Some(MeshHeader::decode_header(src, total)?)),
+            Decoded::Insufficient => Ok(Decoded::Insufficient),
+            Decoded::InsufficientAtLeast(n) => Ok(Decoded::InsufficientAtLeast(n)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MeshHeaderEncoder;
+
+impl MeshHeaderEncoder {
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+impl Encoder<MeshHeader> for MeshHeaderEncoder {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: MeshHeader, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let header_size = item.encoded_len() - MESH_HEADER_FIXED_LENGTH;
+        if header_size > u16::MAX as usize {
+            return Err(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "mesh header too large",
+            ));
+        }
+        dst.reserve(item.encoded_len());
+        dst.put_u16(MESH_HEADER_MAGIC);
+        dst.put_u16(item.flags);
+        dst.put_u16(header_size as u16);
+        for (key, val) in item.headers.iter() {
+            dst.put_u16(key.len() as u16);
+            dst.put_slice(key.as_bytes());
+            dst.put_u16(val.len() as u16);
+            dst.put_slice(val.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MeshHeaderPayload<T> {
+    pub mesh_header: MeshHeader,
+    pub payload: T,
+}
+
+/// Codec for a mesh header followed by a payload decoded with `T`.
+pub struct MeshHeaderPayloadCodec<T> {
+    inner: T,
+}
+
+impl<T> MeshHeaderPayloadCodec<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+}
+
+impl<T: Decoder> Decoder for MeshHeaderPayloadCodec<T>
+where
+    T::Error: From<io::Error>,
+{
+    type Item = MeshHeaderPayload<T::Item>;
+    type Error = T::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        let total = match MeshHeader::detect(src)? {
+            Decoded::Some(total) => total,
+            Decoded::Insufficient => return Ok(Decoded::Insufficient),
+            Decoded::InsufficientAtLeast(n) => return Ok(Decoded::InsufficientAtLeast(n)),
+        };
+        // Only consume the header once the payload is complete.
+        let mut rest = src.split_off(total);
+        let payload = match self.inner.decode(&mut rest) {
+            Ok(Decoded::Some(payload)) => payload,
+            Ok(Decoded::Insufficient) => {
+                src.unsplit(rest);
+                return Ok(Decoded::Insufficient);
+            }
+            Ok(Decoded::InsufficientAtLeast(n)) => {
+                src.unsplit(rest);
+                return Ok(Decoded::InsufficientAtLeast(total + n));
+            }
+            Err(e) => return Err(e),
+        };
+        let mesh_header = MeshHeader::decode_header(src, total)?;
+        *src = rest;
+        Ok(Decoded::Some(MeshHeaderPayload {
+            mesh_header,
+            payload,
+        }))
+    }
+}
+
+impl<T, E: Encoder<T>> Encoder<MeshHeaderPayload<T>> for MeshHeaderPayloadCodec<E>
+where
+    E::Error: From<io::Error> + From<CodecError>,
+{
+    type Error = E::Error;
+
+    fn encode(
+        &mut self,
+        item: MeshHeaderPayload<T>,
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        MeshHeaderEncoder.encode(item.mesh_header, dst)?;
+        self.inner.encode(item.payload, dst)
+    }
+}
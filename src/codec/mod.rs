@@ -1,2 +1,14 @@
+pub mod batch;
+pub mod builder;
+pub mod detect;
 pub mod framed;
+pub mod header_map;
+pub mod mesh;
+pub mod parts;
+pub mod passthrough;
+pub mod seq_id;
+pub mod theader;
+#[cfg(feature = "tokio-compat")]
+pub mod tokio_compat;
+pub mod transport;
 pub mod ttheader;
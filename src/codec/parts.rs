@@ -0,0 +1,112 @@
+//! Encoding into a list of buffers.
+//!
+//! A large payload already held in [`Bytes`] can be emitted as its own part
+//! instead of being copied into the destination buffer, the parts are then sent
+//! with a single vectored write.
+
+use bytes::{Bytes, BytesMut};
+#[cfg(unix)]
+use monoio::{
+    buf::IoVecBuf,
+    io::{AsyncWriteRent, AsyncWriteRentExt},
+    BufResult,
+};
+use monoio_codec::Encoder;
+use smallvec::SmallVec;
+
+/// Buffers making up encoded frames, in wire order.
+pub type EncodeParts = SmallVec<[Bytes; 4]>;
+
+/// Encoder that can emit its output as separate [`EncodeParts`].
+///
+/// The default `encode_parts` encodes into a single contiguous part, encoders
+/// that can hand out buffers without copying override it.
+pub trait PartsEncoder<Item>: Encoder<Item> {
+    fn encode_parts(&mut self, item: Item, parts: &mut EncodeParts) -> Result<(), Self::Error> {
+        let mut dst = BytesMut::new();
+        self.encode(item, &mut dst)?;
+        parts.push(dst.freeze());
+        Ok(())
+    }
+}
+
+/// Total length of the parts from `start` on.
+#[inline]
+pub fn parts_len(parts: &EncodeParts, start: usize) -> usize {
+    parts[start..].iter().map(Bytes::len).sum()
+}
+
+/// [`EncodeParts`] as an [`IoVecBuf`], for a vectored write without copying
+/// the parts into one buffer.
+///
+/// Empty parts are left out. A write takes at most `IOV_MAX` (1024 on Linux)
+/// parts.
+#[cfg(unix)]
+pub struct IoVecParts {
+    parts: EncodeParts,
+    iovecs: Vec<libc::iovec>,
+}
+
+#[cfg(unix)]
+impl IoVecParts {
+    pub fn new(parts: EncodeParts) -> Self {
+        let iovecs = parts
+            .iter()
+            .filter(|part| !part.is_empty())
+            .map(|part| libc::iovec {
+                iov_base: part.as_ptr() as *mut _,
+                iov_len: part.len(),
+            })
+            .collect();
+        Self { parts, iovecs }
+    }
+
+    /// Total length of the parts.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.iovecs.iter().map(|iovec| iovec.iov_len).sum()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.iovecs.is_empty()
+    }
+
+    #[inline]
+    pub fn into_parts(self) -> EncodeParts {
+        self.parts
+    }
+}
+
+#[cfg(unix)]
+impl From<EncodeParts> for IoVecParts {
+    #[inline]
+    fn from(parts: EncodeParts) -> Self {
+        Self::new(parts)
+    }
+}
+
+// The iovecs point into the parts, whose `Bytes` never move their data.
+#[cfg(unix)]
+unsafe impl IoVecBuf for IoVecParts {
+    #[inline]
+    fn read_iovec_ptr(&self) -> *const libc::iovec {
+        self.iovecs.as_ptr()
+    }
+
+    #[inline]
+    fn read_iovec_len(&self) -> usize {
+        self.iovecs.len()
+    }
+}
+
+/// Write all of `parts` to `io` with vectored writes, returning the number of
+/// bytes written and the parts, e.g. to clear and reuse them.
+#[cfg(unix)]
+pub async fn write_parts<W: AsyncWriteRent>(
+    io: &mut W,
+    parts: EncodeParts,
+) -> BufResult<usize, EncodeParts> {
+    let (result, buf) = io.write_vectored_all(IoVecParts::new(parts)).await;
+    (result, buf.into_parts())
+}
@@ -0,0 +1,139 @@
+//! Frame forwarding without parsing.
+//!
+//! TTHeader and framed binary frames both start with a 4-byte length of the
+//! rest of the frame, [`PassthroughCodec`] uses it to cut whole frames out of
+//! the stream so a proxy can move them between connections untouched.
+
+#[cfg(all(target_os = "linux", feature = "splice"))]
+use std::io;
+
+use bytes::{BufMut, Bytes, BytesMut};
+#[cfg(all(target_os = "linux", feature = "splice"))]
+use monoio::{
+    io::{
+        splice::{SpliceDestination, SpliceSource},
+        AsyncWriteRent, AsyncWriteRentExt,
+    },
+    net::unix::{new_pipe, Pipe},
+};
+use monoio_codec::{Decoded, Decoder, Encoder};
+
+use crate::{CodecError, CodecErrorKind};
+
+const TTHEADER_MAGIC: [u8; 2] = [0x10, 0x00];
+const BINARY_VERSION_1: [u8; 2] = [0x80, 0x01];
+
+/// Codec yielding complete TTHeader or framed binary frames, length prefix
+/// included, and writing them back as is.
+#[derive(Default)]
+pub struct PassthroughCodec;
+
+impl PassthroughCodec {
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+/// Total length of the frame `head` starts with, length prefix included, or
+/// `None` until `head` holds its first 8 bytes.
+pub fn frame_len(head: &[u8]) -> Result<Option<usize>, CodecError> {
+    if head.len() < 8 {
+        return Ok(None);
+    }
+    if head[4..6] != TTHEADER_MAGIC && head[4..6] != BINARY_VERSION_1 {
+        return Err(CodecError::new(
+            CodecErrorKind::UnsupportedProtocol,
+            "not a ttheader or framed binary frame",
+        ));
+    }
+    let length = i32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+    if length <= 0 {
+        return Err(CodecError::new(
+            CodecErrorKind::InvalidData,
+            "illegal frame size",
+        ));
+    }
+    Ok(Some(length as usize + 4))
+}
+
+impl Decoder for PassthroughCodec {
+    type Item = Bytes;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        let total = match frame_len(src)? {
+            Some(total) => total,
+            None => return Ok(Decoded::InsufficientAtLeast(8)),
+        };
+        if src.len() < total {
+            return Ok(Decoded::InsufficientAtLeast(total));
+        }
+        Ok(Decoded::Some(src.split_to(total).freeze()))
+    }
+}
+
+impl Encoder<Bytes> for PassthroughCodec {
+    type Error = CodecError;
+
+    #[inline]
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.put_slice(&item);
+        Ok(())
+    }
+}
+
+/// Moves frames between connections with `splice(2)`, through a pipe kept
+/// across frames, so payloads never enter user space.
+///
+/// After an error the pipe may still hold bytes of the failed frame, the
+/// forwarder should be dropped along with the connections.
+#[cfg(all(target_os = "linux", feature = "splice"))]
+pub struct SpliceForwarder {
+    pipe_rx: Pipe,
+    pipe_tx: Pipe,
+}
+
+#[cfg(all(target_os = "linux", feature = "splice"))]
+impl SpliceForwarder {
+    pub fn new() -> io::Result<Self> {
+        let (pipe_rx, pipe_tx) = new_pipe()?;
+        Ok(Self { pipe_rx, pipe_tx })
+    }
+
+    /// Forward a frame of `total` bytes, e.g. from [`frame_len`], from `src`
+    /// to `dst`.
+    ///
+    /// The start of the frame already read into `buffered` is written out
+    /// first, the rest is spliced. Bytes in `buffered` past the frame are left
+    /// there.
+    pub async fn forward<R: SpliceSource, W: SpliceDestination + AsyncWriteRent>(
+        &mut self,
+        src: &mut R,
+        dst: &mut W,
+        buffered: &mut BytesMut,
+        total: usize,
+    ) -> io::Result<()> {
+        let head = buffered.split_to(total.min(buffered.len())).freeze();
+        let mut remaining = total - head.len();
+        if !head.is_empty() {
+            dst.write_all(head).await.0?;
+        }
+        while remaining > 0 {
+            let len = remaining.min(u32::MAX as usize) as u32;
+            let n = src.splice_to_pipe(&mut self.pipe_tx, len).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let mut in_pipe = n;
+            while in_pipe > 0 {
+                let m = dst.splice_from_pipe(&mut self.pipe_rx, in_pipe).await?;
+                if m == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                in_pipe -= m;
+            }
+            remaining -= n as usize;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+//! Sequence id management for clients.
+//!
+//! [`SeqIdEncoder`] stamps a fresh id from a [`SeqIdAllocator`] into both the
+//! TTHeader and the binary message header of every outgoing frame, so the two
+//! never disagree. [`check_seq_id`] validates the id echoed by a response, and
+//! [`SeqIdDecoder`] reads it off incoming frames without decoding them.
+
+use std::{cell::Cell, io};
+
+use bytes::{Bytes, BytesMut};
+use monoio_codec::{Decoded, Decoder, Encoder};
+
+use super::{
+    passthrough::PassthroughCodec,
+    ttheader::{refresh_payload_crc32c, ProtocolId, TTHeaderPayload, TT_HEADER_MAGIC},
+};
+use crate::{CodecError, CodecErrorKind};
+
+/// Hands out monotonically increasing sequence ids.
+///
+/// Ids are always positive, wrapping from `i32::MAX` back to 1.
+#[derive(Debug)]
+pub struct SeqIdAllocator {
+    next: Cell<i32>,
+}
+
+impl Default for SeqIdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SeqIdAllocator {
+    pub const fn new() -> Self {
+        Self::starting_at(1)
+    }
+
+    /// Start from `seq_id`, which is clamped to at least 1.
+    pub const fn starting_at(seq_id: i32) -> Self {
+        let seq_id = if seq_id < 1 { 1 } else { seq_id };
+        Self {
+            next: Cell::new(seq_id),
+        }
+    }
+
+    #[inline]
+    pub fn next_id(&self) -> i32 {
+        let id = self.next.get();
+        self.next.set(if id == i32::MAX { 1 } else { id + 1 });
+        id
+    }
+}
+
+// Offset of the sequence id in the strict binary message header at the front
+// of `buf`.
+fn binary_seq_id_offset(buf: &[u8]) -> Result<usize, CodecError> {
+    const VERSION_MASK: u32 = 0xffff0000;
+    const VERSION_1: u32 = 0x80010000;
+
+    if buf.len() < 8 {
+        return Err(CodecError::new(
+            CodecErrorKind::InvalidData,
+            "truncated message header",
+        ));
+    }
+    let version = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    if version & VERSION_MASK != VERSION_1 {
+        return Err(CodecError::new(
+            CodecErrorKind::InvalidData,
+            "not a strict binary message",
+        ));
+    }
+    let name_len = i32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    let offset = 8 + usize::try_from(name_len)
+        .map_err(|_| CodecError::new(CodecErrorKind::NegativeSize, "negative name length"))?;
+    if buf.len() < offset + 4 {
+        return Err(CodecError::new(
+            CodecErrorKind::InvalidData,
+            "truncated message header",
+        ));
+    }
+    Ok(offset)
+}
+
+/// Overwrite the sequence id of the strict binary message header at the front
+/// of `buf`.
+pub fn stamp_binary_message(buf: &mut [u8], seq_id: i32) -> Result<(), CodecError> {
+    let offset = binary_seq_id_offset(buf)?;
+    buf[offset..offset + 4].copy_from_slice(&seq_id.to_be_bytes());
+    Ok(())
+}
+
+/// Sequence id of the strict binary message header at the front of `buf`.
+pub fn binary_message_seq_id(buf: &[u8]) -> Result<i32, CodecError> {
+    let offset = binary_seq_id_offset(buf)?;
+    Ok(i32::from_be_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ]))
+}
+
+/// Sequence id of a TTHeader or framed binary frame, length prefix included.
+///
+/// For TTHeader frames the id of the header is used, the payload is not
+/// looked at.
+pub fn frame_seq_id(frame: &[u8]) -> Result<i32, CodecError> {
+    if frame.len() >= 12 && u16::from_be_bytes([frame[4], frame[5]]) == TT_HEADER_MAGIC {
+        return Ok(i32::from_be_bytes([
+            frame[8], frame[9], frame[10], frame[11],
+        ]));
+    }
+    binary_message_seq_id(frame.get(4..).unwrap_or_default())
+}
+
+/// Check that a response carries the sequence id of its request.
+#[inline]
+pub fn check_seq_id(expected: i32, actual: i32) -> Result<(), CodecError> {
+    if expected != actual {
+        return Err(CodecError::new(
+            CodecErrorKind::InvalidData,
+            format!("seq id mismatch: expected {expected}, got {actual}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Encoder wrapper assigning a new sequence id to every [`TTHeaderPayload`].
+///
+/// The id replaces `TTHeader::seq_id` and, for binary payloads, the id in the
+/// message header of the encoded payload, whose checksum is then updated.
+pub struct SeqIdEncoder<E> {
+    inner: E,
+    ids: SeqIdAllocator,
+    last_seq_id: Option<i32>,
+}
+
+impl<E> SeqIdEncoder<E> {
+    pub fn new(inner: E) -> Self {
+        Self::with_allocator(inner, SeqIdAllocator::new())
+    }
+
+    pub fn with_allocator(inner: E, ids: SeqIdAllocator) -> Self {
+        Self {
+            inner,
+            ids,
+            last_seq_id: None,
+        }
+    }
+
+    /// Id stamped into the last encoded frame.
+    #[inline]
+    pub fn last_seq_id(&self) -> Option<i32> {
+        self.last_seq_id
+    }
+}
+
+impl<T, E> Encoder<TTHeaderPayload<T>> for SeqIdEncoder<E>
+where
+    E: Encoder<TTHeaderPayload<T>>,
+    E::Error: From<io::Error>,
+{
+    type Error = E::Error;
+
+    fn encode(
+        &mut self,
+        mut item: TTHeaderPayload<T>,
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        let seq_id = self.ids.next_id();
+        let binary = matches!(item.ttheader.protocol_id, ProtocolId::Binary);
+        item.ttheader.seq_id = seq_id;
+        let zero_index = dst.len();
+        self.inner.encode(item, dst)?;
+        if binary {
+            // 4-byte length + 10 bytes up to the header size, which counts
+            // 4-byte words
+            let header_size = u16::from_be_bytes([dst[zero_index + 12], dst[zero_index + 13]]);
+            let payload_index = zero_index + 14 + header_size as usize * 4;
+            stamp_binary_message(&mut dst[payload_index..], seq_id).map_err(io::Error::from)?;
+            // the checksum covers the id just written
+            refresh_payload_crc32c(&mut dst[zero_index..]).map_err(io::Error::from)?;
+        }
+        self.last_seq_id = Some(seq_id);
+        Ok(())
+    }
+}
+
+/// Decoder wrapper yielding whole frames with their sequence id, so responses
+/// can be matched to requests before anything is decoded.
+///
+/// `D` cuts frames out of the stream, length prefix included, see
+/// [`frame_seq_id`].
+pub struct SeqIdDecoder<D = PassthroughCodec> {
+    inner: D,
+}
+
+impl SeqIdDecoder {
+    pub const fn new() -> Self {
+        Self::with_decoder(PassthroughCodec::new())
+    }
+}
+
+impl Default for SeqIdDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> SeqIdDecoder<D> {
+    pub const fn with_decoder(inner: D) -> Self {
+        Self { inner }
+    }
+}
+
+impl<D: Decoder<Item = Bytes>> Decoder for SeqIdDecoder<D>
+where
+    D::Error: From<io::Error>,
+{
+    type Item = (i32, Bytes);
+    type Error = D::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        match self.inner.decode(src)? {
+            Decoded::Some(frame) => {
+                let seq_id = frame_seq_id(&frame).map_err(io::Error::from)?;
+                Ok(Decoded::Some((seq_id, frame)))
+            }
+            Decoded::Insufficient => Ok(Decoded::Insufficient),
+            Decoded::InsufficientAtLeast(n) => Ok(Decoded::InsufficientAtLeast(n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::ttheader::{RawPayloadCodec, TTHeader, TTHeaderPayloadCodec};
+
+    #[test]
+    fn stamped_frame_keeps_checksum() {
+        // strict binary call "ping" with seq id 0 and an empty struct
+        let payload = Bytes::from_static(&[
+            0x80, 0x01, 0x00, 0x01, 0, 0, 0, 4, b'p', b'i', b'n', b'g', 0, 0, 0, 0, 0,
+        ]);
+        let mut encoder = SeqIdEncoder::with_allocator(
+            TTHeaderPayloadCodec::new(RawPayloadCodec::new()).checksum(true),
+            SeqIdAllocator::starting_at(7),
+        );
+        let mut dst = BytesMut::new();
+        let item = TTHeaderPayload {
+            ttheader: TTHeader::new(),
+            payload: Some(payload),
+        };
+        encoder.encode(item, &mut dst).unwrap();
+
+        let mut decoder = TTHeaderPayloadCodec::new(RawPayloadCodec::new()).checksum(true);
+        let decoded = match decoder.decode(&mut dst).unwrap() {
+            Decoded::Some(decoded) => decoded,
+            _ => panic!("frame is complete"),
+        };
+        assert_eq!(decoded.ttheader.seq_id, 7);
+        assert_eq!(binary_message_seq_id(&decoded.payload.unwrap()).unwrap(), 7);
+    }
+}
@@ -0,0 +1,534 @@
+//! THeader is the header transport of Apache Thrift and fbthrift, not to be
+//! confused with the CloudWeGo [TTHeader](super::ttheader).
+//!
+//! ```text
+//! +----------------------------------------------------------------+
+//! | 0|                          LENGTH                             |
+//! +----------------------------------------------------------------+
+//! | 0|       HEADER MAGIC (0x0FFF)   |            FLAGS              |
+//! +----------------------------------------------------------------+
+//! |                         SEQUENCE NUMBER                        |
+//! +----------------------------------------------------------------+
+//! | 0|     Header Size(/32)          | ...
+//! +---------------------------------
+//!
+//! Header is of variable size:
+//! +----------------------------------------------------------------+
+//! |         PROTOCOL ID  (varint)  |   NUM TRANSFORMS (varint)     |
+//! +----------------------------------------------------------------+
+//! |      TRANSFORM 0 ID (varint)   |        TRANSFORM 0 DATA ...
+//! +----------------------------------------------------------------+
+//! |        INFO 0 ID (varint)      |       INFO 0  DATA ...
+//! +----------------------------------------------------------------+
+//! |                              PAYLOAD                           |
+//! +----------------------------------------------------------------+
+//! ```
+//!
+//! Varints are unsigned LEB128. Transforms are applied to the payload in
+//! order on encode and reversed on decode, only zlib is supported and only
+//! with the `zlib` feature.
+
+use std::io;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use monoio_codec::{Decoded, Decoder, Encoder};
+use smallvec::SmallVec;
+use smol_str::SmolStr;
+
+use super::header_map::HeaderMap;
+use crate::{CodecError, CodecErrorKind};
+
+pub const THEADER_MAGIC: u16 = 0x0FFF;
+// frame length, magic, flags, seq id and header size
+const THEADER_FIXED_LENGTH: usize = 14;
+/// Default limit of [`THeaderPayloadCodec::with_max_frame_size`].
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Payload protocol ids.
+pub mod protocol {
+    pub const BINARY: u32 = 0;
+    pub const COMPACT: u32 = 2;
+}
+
+/// Transform ids.
+pub mod transform {
+    pub const ZLIB: u32 = 0x01;
+    pub const HMAC: u32 = 0x02;
+    pub const SNAPPY: u32 = 0x03;
+    pub const QLZ: u32 = 0x04;
+    pub const ZSTD: u32 = 0x05;
+}
+
+mod info {
+    pub const PADDING: u32 = 0x00;
+    pub const KEY_VALUE: u32 = 0x01;
+    pub const PERSISTENT_KEY_VALUE: u32 = 0x02;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct THeader {
+    pub flags: u16,
+    pub seq_id: i32,
+    pub protocol_id: u32,
+    pub transforms: SmallVec<[u32; 2]>,
+    /// Headers of this request only.
+    pub headers: HeaderMap,
+    /// Headers a peer keeps for every later frame of the connection.
+    pub persistent_headers: HeaderMap,
+}
+
+impl THeader {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn encode_region(&self, dst: &mut BytesMut) {
+        put_varint(dst, self.protocol_id);
+        put_varint(dst, self.transforms.len() as u32);
+        for id in self.transforms.iter() {
+            put_varint(dst, *id);
+        }
+        for (id, headers) in [
+            (info::KEY_VALUE, &self.headers),
+            (info::PERSISTENT_KEY_VALUE, &self.persistent_headers),
+        ] {
+            if headers.is_empty() {
+                continue;
+            }
+            put_varint(dst, id);
+            put_varint(dst, headers.len() as u32);
+            for (key, val) in headers.iter() {
+                put_varint(dst, key.len() as u32);
+                dst.put_slice(key.as_bytes());
+                put_varint(dst, val.len() as u32);
+                dst.put_slice(val.as_bytes());
+            }
+        }
+    }
+
+    // `region` is the header region, padding included.
+    fn decode_region(&mut self, mut region: &[u8]) -> Result<(), CodecError> {
+        self.protocol_id = get_varint(&mut region)?;
+        let transform_num = get_varint(&mut region)?;
+        for _ in 0..transform_num {
+            self.transforms.push(get_varint(&mut region)?);
+        }
+        while !region.is_empty() {
+            let headers = match get_varint(&mut region)? {
+                info::KEY_VALUE => &mut self.headers,
+                info::PERSISTENT_KEY_VALUE => &mut self.persistent_headers,
+                // Padding, or an info we don't know the layout of and can't
+                // skip, the reference implementation ignores the rest too.
+                _ => break,
+            };
+            let kv_num = get_varint(&mut region)?;
+            for _ in 0..kv_num {
+                let key = get_str(&mut region)?;
+                let val = get_str(&mut region)?;
+                headers.insert(key, val);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[inline]
+fn put_varint(dst: &mut BytesMut, mut n: u32) {
+    while n >= 0x80 {
+        dst.put_u8(n as u8 | 0x80);
+        n >>= 7;
+    }
+    dst.put_u8(n as u8);
+}
+
+#[inline]
+fn get_varint(buf: &mut &[u8]) -> Result<u32, CodecError> {
+    let mut n = 0u32;
+    for shift in (0..35).step_by(7) {
+        if buf.is_empty() {
+            return Err(CodecError::new(
+                CodecErrorKind::InvalidData,
+                "truncated theader",
+            ));
+        }
+        let b = buf.get_u8();
+        n |= ((b & 0x7f) as u32) << shift;
+        if b & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(CodecError::new(
+        CodecErrorKind::InvalidData,
+        "invalid theader varint",
+    ))
+}
+
+#[inline]
+fn get_str(buf: &mut &[u8]) -> Result<SmolStr, CodecError> {
+    let len = get_varint(buf)? as usize;
+    if buf.len() < len {
+        return Err(CodecError::new(
+            CodecErrorKind::InvalidData,
+            "truncated theader",
+        ));
+    }
+    let s = std::str::from_utf8(&buf[..len])
+        .map_err(|_| CodecError::new(CodecErrorKind::InvalidData, "invalid utf8 header"))?;
+    let s = SmolStr::new(s);
+    buf.advance(len);
+    Ok(s)
+}
+
+fn untransform(
+    transforms: &[u32],
+    mut payload: BytesMut,
+    max_len: usize,
+) -> Result<BytesMut, CodecError> {
+    for id in transforms.iter().rev() {
+        payload = BytesMut::from(&apply_transform(*id, &payload, true, max_len)?[..]);
+    }
+    Ok(payload)
+}
+
+fn transform(transforms: &[u32], mut payload: Bytes) -> Result<Bytes, CodecError> {
+    for id in transforms.iter() {
+        payload = Bytes::from(apply_transform(*id, &payload, false, usize::MAX)?);
+    }
+    Ok(payload)
+}
+
+// Apply the transform `id` to `data`, or undo it when `reverse` is set. An
+// undone payload longer than `max_len` is rejected.
+#[cfg_attr(not(feature = "zlib"), allow(unused_variables))]
+fn apply_transform(
+    id: u32,
+    data: &[u8],
+    reverse: bool,
+    max_len: usize,
+) -> Result<Vec<u8>, CodecError> {
+    match id {
+        #[cfg(feature = "zlib")]
+        transform::ZLIB if reverse => {
+            use std::io::Read;
+            let mut out = Vec::with_capacity(data.len().saturating_mul(2).min(max_len));
+            // read one byte past the limit to tell a full payload from a cut one
+            flate2::read::ZlibDecoder::new(data)
+                .take((max_len as u64).saturating_add(1))
+                .read_to_end(&mut out)
+                .map_err(|e| {
+                    CodecError::new(CodecErrorKind::InvalidData, "invalid zlib payload")
+                        .with_source(e)
+                })?;
+            if out.len() > max_len {
+                return Err(CodecError::new(
+                    CodecErrorKind::SizeLimitExceeded,
+                    "inflated theader payload too large",
+                ));
+            }
+            Ok(out)
+        }
+        #[cfg(feature = "zlib")]
+        transform::ZLIB => {
+            use std::io::Write;
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            Ok(encoder.finish()?)
+        }
+        id => Err(unsupported_transform(id)),
+    }
+}
+
+#[inline]
+fn unsupported_transform(id: u32) -> CodecError {
+    CodecError::new(
+        CodecErrorKind::UnsupportedProtocol,
+        format!("unsupported theader transform {id}"),
+    )
+}
+
+#[derive(Clone, Debug)]
+pub struct THeaderPayload<T> {
+    pub theader: THeader,
+    pub payload: T,
+}
+
+/// Codec for a THeader frame whose payload is decoded with `T`.
+pub struct THeaderPayloadCodec<T> {
+    inner: T,
+    max_frame_size: usize,
+}
+
+impl<T> THeaderPayloadCodec<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Limit the size of a frame after its length, and of its payload once
+    /// the transforms are undone. Larger frames are rejected with
+    /// [`CodecErrorKind::SizeLimitExceeded`].
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+}
+
+impl<T: Decoder> Decoder for THeaderPayloadCodec<T>
+where
+    T::Error: From<io::Error>,
+{
+    type Item = THeaderPayload<T::Item>;
+    type Error = T::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        if src.len() < THEADER_FIXED_LENGTH {
+            return Ok(Decoded::InsufficientAtLeast(THEADER_FIXED_LENGTH));
+        }
+        if u16::from_be_bytes([src[4], src[5]]) != THEADER_MAGIC {
+            return Err(io::Error::from(CodecError::new(
+                CodecErrorKind::InvalidData,
+                "illegal theader",
+            ))
+            .into());
+        }
+        let length = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if length > self.max_frame_size {
+            return Err(io::Error::from(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "theader frame too large",
+            ))
+            .into());
+        }
+        if src.len() < length + 4 {
+            return Ok(Decoded::InsufficientAtLeast(length + 4));
+        }
+        let header_length = u16::from_be_bytes([src[12], src[13]]) as usize * 4;
+        if THEADER_FIXED_LENGTH - 4 + header_length > length {
+            return Err(io::Error::from(CodecError::new(
+                CodecErrorKind::InvalidData,
+                "invalid header length",
+            ))
+            .into());
+        }
+
+        let mut frame = src.split_to(length + 4);
+        let mut theader = THeader::new();
+        theader.flags = u16::from_be_bytes([frame[6], frame[7]]);
+        theader.seq_id = i32::from_be_bytes([frame[8], frame[9], frame[10], frame[11]]);
+        frame.advance(THEADER_FIXED_LENGTH);
+        let region = frame.split_to(header_length);
+        theader.decode_region(&region).map_err(io::Error::from)?;
+        let mut payload = untransform(&theader.transforms, frame, self.max_frame_size)
+            .map_err(io::Error::from)?;
+        match self.inner.decode(&mut payload)? {
+            Decoded::Some(payload) => Ok(Decoded::Some(THeaderPayload { theader, payload })),
+            // the whole frame is buffered, so it's err if Insufficient
+            _ => Err(io::Error::from(CodecError::new(
+                CodecErrorKind::InvalidData,
+                "illegal payload",
+            ))
+            .into()),
+        }
+    }
+}
+
+impl<T, E: Encoder<T>> Encoder<THeaderPayload<T>> for THeaderPayloadCodec<E>
+where
+    E::Error: From<io::Error>,
+{
+    type Error = E::Error;
+
+    fn encode(&mut self, item: THeaderPayload<T>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let zero_index = dst.len();
+        if let Err(e) = self.encode_frame(item, dst) {
+            // drop the partial frame
+            dst.truncate(zero_index);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl<E> THeaderPayloadCodec<E> {
+    // Append the frame of `item` to `dst`, the caller rolls `dst` back on error.
+    fn encode_frame<T>(
+        &mut self,
+        item: THeaderPayload<T>,
+        dst: &mut BytesMut,
+    ) -> Result<(), E::Error>
+    where
+        E: Encoder<T>,
+        E::Error: From<io::Error>,
+    {
+        let zero_index = dst.len();
+        dst.put_u32(0);
+        dst.put_u16(THEADER_MAGIC);
+        dst.put_u16(item.theader.flags);
+        dst.put_i32(item.theader.seq_id);
+        let size_index = dst.len();
+        dst.put_u16(0);
+        item.theader.encode_region(dst);
+        let overflow = (dst.len() - size_index - 2) % 4;
+        (0..(4 - overflow) % 4).for_each(|_| dst.put_u8(info::PADDING as u8));
+        let header_size = (dst.len() - size_index - 2) / 4;
+        if header_size > u16::MAX as usize {
+            return Err(io::Error::from(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "theader too large",
+            ))
+            .into());
+        }
+        dst[size_index..size_index + 2].copy_from_slice(&(header_size as u16).to_be_bytes());
+
+        if item.theader.transforms.is_empty() {
+            self.inner.encode(item.payload, dst)?;
+        } else {
+            let mut payload = BytesMut::new();
+            self.inner.encode(item.payload, &mut payload)?;
+            let payload =
+                transform(&item.theader.transforms, payload.freeze()).map_err(io::Error::from)?;
+            dst.put_slice(&payload);
+        }
+        let length = dst.len() - zero_index - 4;
+        if length > i32::MAX as usize {
+            return Err(io::Error::from(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "theader frame too large",
+            ))
+            .into());
+        }
+        dst[zero_index..zero_index + 4].copy_from_slice(&(length as u32).to_be_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::ttheader::RawPayloadCodec;
+
+    fn codec() -> THeaderPayloadCodec<RawPayloadCodec> {
+        THeaderPayloadCodec::new(RawPayloadCodec::new())
+    }
+
+    fn encode(theader: THeader, payload: &[u8]) -> Result<BytesMut, CodecError> {
+        let mut dst = BytesMut::new();
+        let item = THeaderPayload {
+            theader,
+            payload: Bytes::copy_from_slice(payload),
+        };
+        codec().encode(item, &mut dst)?;
+        Ok(dst)
+    }
+
+    fn decode(src: &mut BytesMut) -> THeaderPayload<Bytes> {
+        match codec().decode(src).unwrap() {
+            Decoded::Some(item) => item,
+            _ => panic!("frame not decoded"),
+        }
+    }
+
+    // Header whose varints and strings take more than one byte.
+    fn large_header() -> THeader {
+        let mut theader = THeader::new();
+        theader.flags = 1;
+        theader.seq_id = -3;
+        theader.protocol_id = protocol::COMPACT;
+        for i in 0..200 {
+            theader
+                .headers
+                .insert(format!("key-{i}").into(), "v".repeat(i).into());
+        }
+        theader
+            .persistent_headers
+            .insert("k".repeat(300).into(), "".into());
+        theader
+    }
+
+    fn assert_round_trip(theader: THeader, payload: &[u8]) {
+        let mut src = encode(theader.clone(), payload).unwrap();
+        let decoded = decode(&mut src);
+        assert!(src.is_empty());
+        assert_eq!(decoded.payload, payload);
+        assert_eq!(decoded.theader.flags, theader.flags);
+        assert_eq!(decoded.theader.seq_id, theader.seq_id);
+        assert_eq!(decoded.theader.protocol_id, theader.protocol_id);
+        assert_eq!(decoded.theader.transforms, theader.transforms);
+        assert_eq!(decoded.theader.headers, theader.headers);
+        assert_eq!(
+            decoded.theader.persistent_headers,
+            theader.persistent_headers
+        );
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for n in [0, 1, 127, 128, 16383, 16384, u32::MAX] {
+            let mut buf = BytesMut::new();
+            put_varint(&mut buf, n);
+            let mut region = &buf[..];
+            assert_eq!(get_varint(&mut region).unwrap(), n);
+            assert!(region.is_empty());
+        }
+        let mut region = &[0x80, 0x80][..];
+        assert!(get_varint(&mut region).is_err());
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        assert_round_trip(THeader::new(), b"");
+        assert_round_trip(THeader::new(), b"payload");
+        assert_round_trip(large_header(), &[7; 1000]);
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn zlib_round_trip() {
+        let mut theader = large_header();
+        theader.transforms.push(transform::ZLIB);
+        assert_round_trip(theader.clone(), &[7; 1000]);
+        theader.transforms.push(transform::ZLIB);
+        assert_round_trip(theader, b"twice");
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn zlib_inflated_over_cap() {
+        let mut theader = THeader::new();
+        theader.transforms.push(transform::ZLIB);
+        // compresses to far less than the cap
+        let payload = vec![0; 64 * 1024];
+        let mut src = encode(theader, &payload).unwrap();
+        assert!(src.len() < 1024);
+        let err = codec()
+            .with_max_frame_size(1024)
+            .decode(&mut src)
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind, CodecErrorKind::SizeLimitExceeded));
+
+        // exactly the cap is fine
+        let mut theader = THeader::new();
+        theader.transforms.push(transform::ZLIB);
+        let mut src = encode(theader, &payload[..1024]).unwrap();
+        let decoded = codec().with_max_frame_size(1024).decode(&mut src);
+        assert!(matches!(decoded, Ok(Decoded::Some(_))));
+    }
+
+    #[test]
+    fn failed_encode_leaves_dst() {
+        let mut theader = large_header();
+        theader.transforms.push(transform::SNAPPY);
+        let mut dst = BytesMut::from(&b"prefix"[..]);
+        let item = THeaderPayload {
+            theader,
+            payload: Bytes::from_static(b"payload"),
+        };
+        assert!(codec().encode(item, &mut dst).is_err());
+        assert_eq!(&dst[..], b"prefix");
+    }
+}
@@ -0,0 +1,68 @@
+//! Adapters running the codecs of this crate under `tokio_util::codec`.
+//!
+//! ```ignore
+//! let framed = tokio_util::codec::Framed::new(io, TokioCodec::new(FramedHeader::new(codec)));
+//! ```
+
+use std::io;
+
+use bytes::BytesMut;
+use monoio_codec::Decoded;
+
+/// Wraps a monoio codec to implement the `tokio_util` codec traits.
+#[derive(Clone, Debug, Default)]
+pub struct TokioCodec<C>(C);
+
+impl<C> TokioCodec<C> {
+    #[inline]
+    pub const fn new(codec: C) -> Self {
+        Self(codec)
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &C {
+        &self.0
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.0
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> C {
+        self.0
+    }
+}
+
+impl<C: monoio_codec::Decoder> tokio_util::codec::Decoder for TokioCodec<C>
+where
+    C::Error: From<io::Error>,
+{
+    type Item = C::Item;
+    type Error = C::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.0.decode(src)? {
+            Decoded::Some(item) => Ok(Some(item)),
+            Decoded::Insufficient => Ok(None),
+            Decoded::InsufficientAtLeast(n) => {
+                // tokio_util only reads what fits, make room for the whole frame
+                src.reserve(n.saturating_sub(src.len()));
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<C: monoio_codec::Encoder<Item>, Item> tokio_util::codec::Encoder<Item> for TokioCodec<C>
+where
+    C::Error: From<io::Error>,
+{
+    type Error = C::Error;
+
+    #[inline]
+    fn encode(&mut self, item: Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.0.encode(item, dst)
+    }
+}
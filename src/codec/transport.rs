@@ -0,0 +1,70 @@
+//! [`Framed`] transports with the usual codec stacks.
+//!
+//! Each type derefs to the underlying `Framed`, so reading and writing frames
+//! works as with a hand-built one.
+
+use std::ops::{Deref, DerefMut};
+
+use monoio_codec::Framed;
+
+use super::{
+    framed::FramedHeader,
+    theader::THeaderPayloadCodec,
+    ttheader::{RawPayloadCodec, TTHeaderPayloadCodec},
+};
+
+macro_rules! framed_types {
+    ($($(#[$meta: meta])* $name: ident => $codec: ident,)*) => {
+        $(
+            $(#[$meta])*
+            pub struct $name<IO, P = RawPayloadCodec>(Framed<IO, $codec<P>>);
+
+            impl<IO, P: Default> $name<IO, P> {
+                pub fn new(io: IO) -> Self {
+                    Self::with_codec(io, $codec::new(P::default()))
+                }
+
+                pub fn with_capacity(io: IO, capacity: usize) -> Self {
+                    Self(Framed::with_capacity(io, $codec::new(P::default()), capacity))
+                }
+            }
+
+            impl<IO, P> $name<IO, P> {
+                /// Use a configured codec.
+                pub fn with_codec(io: IO, codec: $codec<P>) -> Self {
+                    Self(Framed::new(io, codec))
+                }
+
+                #[inline]
+                pub fn into_framed(self) -> Framed<IO, $codec<P>> {
+                    self.0
+                }
+            }
+
+            impl<IO, P> Deref for $name<IO, P> {
+                type Target = Framed<IO, $codec<P>>;
+
+                #[inline]
+                fn deref(&self) -> &Self::Target {
+                    &self.0
+                }
+            }
+
+            impl<IO, P> DerefMut for $name<IO, P> {
+                #[inline]
+                fn deref_mut(&mut self) -> &mut Self::Target {
+                    &mut self.0
+                }
+            }
+        )*
+    };
+}
+
+framed_types! {
+    /// TTHeader frames, with payloads handled by `P`.
+    TTHeaderFramed => TTHeaderPayloadCodec,
+    /// Messages behind a 4-byte length, handled by `P`.
+    BinaryFramed => FramedHeader,
+    /// Apache THeader frames, with payloads handled by `P`.
+    THeaderFramed => THeaderPayloadCodec,
+}
@@ -2,32 +2,71 @@
 //!
 //! For more information, please visit https://www.cloudwego.io/docs/kitex/reference/transport_protocol_ttheader/
 
-use std::collections::HashMap;
-use std::{io, ptr::copy_nonoverlapping};
+use std::{io, ops::Range, ptr::copy_nonoverlapping, time::Duration};
 
 use smallvec::SmallVec;
 use smol_str::SmolStr;
 
+use monoio::{io::AsyncReadRent, time::Instant};
 use monoio_codec::{Decoded, Decoder, Encoder};
 
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use num_enum::TryFromPrimitive;
 
-pub type HeaderMap = HashMap<SmolStr, SmolStr>;
+use crate::{
+    binary::{read_more_at_least, read_more_at_least_until},
+    CodecError, CodecErrorKind,
+};
+
+use super::parts::{parts_len, EncodeParts, PartsEncoder};
+
+pub use super::header_map::HeaderMap;
+
+// Getter and setter pairs for int metas holding text.
+macro_rules! str_metas {
+    ($($get: ident, $set: ident => $key: ident,)*) => {
+        $(
+            #[doc = concat!("Value of the [`IntMetaKey::", stringify!($key), "`] meta, if it is text.")]
+            #[inline]
+            pub fn $get(&self) -> Option<&str> {
+                self.int_header(IntMetaKey::$key)?.as_str()
+            }
+
+            #[inline]
+            pub fn $set(&mut self, val: impl Into<SmolStr>) {
+                self.set_int_meta(IntMetaKey::$key, HeaderValue::Str(val.into()));
+            }
+        )*
+    };
+}
 
 #[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "TTHeaderRepr", into = "TTHeaderRepr")
+)]
 pub struct TTHeader {
     pub header_length: u32,
     pub payload_length: u32,
     pub seq_id: i32,
-    pub flags: u16,
+    pub flags: TTHeaderFlags,
     pub protocol_id: ProtocolId,
+    // transforms applied to the payload, e.g. compression
+    pub transform_ids: SmallVec<[u8; 4]>,
     // int key < IntMetaKey::INDEX_TABLE_SIZE
-    pub int_headers: [Option<SmolStr>; IntMetaKey::INDEX_TABLE_SIZE],
+    pub int_headers: [Option<HeaderValue>; IntMetaKey::INDEX_TABLE_SIZE],
     // int key >= IntMetaKey::INDEX_TABLE_SIZE
-    pub int_headers_ext: SmallVec<[(u16, SmolStr); 2]>,
+    pub int_headers_ext: SmallVec<[(u16, HeaderValue); 2]>,
     pub str_headers: HeaderMap,
     pub acl_token: Option<SmolStr>,
+    // info sections with unknown id, kept raw when decoding in passthrough mode
+    pub unknown_infos: SmallVec<[(u8, Bytes); 1]>,
+    // CRC32C of the payload, see `TTHeaderPayloadCodec::checksum`
+    pub payload_crc32c: Option<u32>,
+    // encoded header from the magic to the end of the header region, kept when
+    // decoding with `retain_raw`
+    pub raw: Option<Bytes>,
 }
 
 impl Default for TTHeader {
@@ -36,13 +75,97 @@ impl Default for TTHeader {
             header_length: 0,
             payload_length: 0,
             seq_id: 0,
-            flags: 0,
+            flags: TTHeaderFlags::empty(),
             protocol_id: ProtocolId::Binary,
+            transform_ids: Default::default(),
             int_headers: Default::default(),
             int_headers_ext: Default::default(),
             str_headers: Default::default(),
             acl_token: None,
+            unknown_infos: Default::default(),
+            payload_crc32c: None,
+            raw: None,
+        }
+    }
+}
+
+// Serde form of a `TTHeader`: int metas in one map keyed by id, and the raw
+// header left out.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TTHeaderRepr {
+    #[serde(default)]
+    header_length: u32,
+    #[serde(default)]
+    payload_length: u32,
+    seq_id: i32,
+    #[serde(default)]
+    flags: TTHeaderFlags,
+    #[serde(default)]
+    protocol_id: ProtocolId,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    transform_ids: Vec<u8>,
+    #[serde(default)]
+    int_headers: std::collections::BTreeMap<u16, HeaderValue>,
+    #[serde(default)]
+    str_headers: HeaderMap,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    acl_token: Option<SmolStr>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    unknown_infos: Vec<(u8, Bytes)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload_crc32c: Option<u32>,
+}
+
+#[cfg(feature = "serde")]
+impl From<TTHeader> for TTHeaderRepr {
+    fn from(header: TTHeader) -> Self {
+        let mut int_headers = std::collections::BTreeMap::new();
+        for (key, val) in header.int_headers.into_iter().enumerate() {
+            if let Some(val) = val {
+                int_headers.insert(key as u16, val);
+            }
         }
+        int_headers.extend(header.int_headers_ext);
+        Self {
+            header_length: header.header_length,
+            payload_length: header.payload_length,
+            seq_id: header.seq_id,
+            flags: header.flags,
+            protocol_id: header.protocol_id,
+            transform_ids: header.transform_ids.into_vec(),
+            int_headers,
+            str_headers: header.str_headers,
+            acl_token: header.acl_token,
+            unknown_infos: header.unknown_infos.into_vec(),
+            payload_crc32c: header.payload_crc32c,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<TTHeaderRepr> for TTHeader {
+    fn from(repr: TTHeaderRepr) -> Self {
+        let mut header = Self {
+            header_length: repr.header_length,
+            payload_length: repr.payload_length,
+            seq_id: repr.seq_id,
+            flags: repr.flags,
+            protocol_id: repr.protocol_id,
+            transform_ids: repr.transform_ids.into(),
+            str_headers: repr.str_headers,
+            acl_token: repr.acl_token,
+            unknown_infos: repr.unknown_infos.into(),
+            payload_crc32c: repr.payload_crc32c,
+            ..Default::default()
+        };
+        for (key, val) in repr.int_headers {
+            match header.int_headers.get_mut(key as usize) {
+                Some(slot) => *slot = Some(val),
+                None => header.int_headers_ext.push((key, val)),
+            }
+        }
+        header
     }
 }
 
@@ -53,12 +176,16 @@ impl TTHeader {
             header_length: 0,
             payload_length: payload_length_hint,
             seq_id: 0,
-            flags: 0,
+            flags: TTHeaderFlags::empty(),
             protocol_id: ProtocolId::Binary,
+            transform_ids: Default::default(),
             int_headers: Default::default(),
             int_headers_ext: Default::default(),
             str_headers: Default::default(),
             acl_token: None,
+            unknown_infos: Default::default(),
+            payload_crc32c: None,
+            raw: None,
         }
     }
 
@@ -67,171 +194,1206 @@ impl TTHeader {
         Self::default()
     }
 
-    // TODO: now only supports io::Error
-    fn decode_header(&mut self, total_length: u32, src: &mut bytes::BytesMut) -> io::Result<()> {
-        #[inline]
-        unsafe fn read_u8_unchecked(buf: &[u8], index: &mut usize) -> u8 {
-            let val = *buf.get_unchecked(*index);
-            *index += 1;
-            val
+    #[inline]
+    pub fn builder() -> TTHeaderBuilder {
+        TTHeaderBuilder::new()
+    }
+
+    /// Derive the header of a reply to `request`.
+    ///
+    /// `seq_id`, `protocol_id` and `flags` are copied, as are the int metas
+    /// that are not [request only](IntMetaKey::is_request_only). String
+    /// headers, the acl token and unknown info sections are not copied.
+    pub fn reply_from(request: &TTHeader) -> Self {
+        let mut header = Self {
+            seq_id: request.seq_id,
+            flags: request.flags,
+            protocol_id: request.protocol_id,
+            ..Default::default()
+        };
+        for (key, val) in request.int_headers.iter().enumerate() {
+            let Some(val) = val else { continue };
+            match IntMetaKey::try_from(key as u16) {
+                Ok(key) if key.is_request_only() => {}
+                _ => header.int_headers[key] = Some(val.clone()),
+            }
         }
+        header.int_headers_ext = request.int_headers_ext.clone();
+        header
+    }
 
-        #[inline]
-        unsafe fn read_u16_unchecked(buf: &[u8], index: &mut usize) -> u16 {
-            let val = u16::from_be_bytes(
-                buf.get_unchecked(*index..*index + 2)
-                    .try_into()
-                    .unwrap_unchecked(),
-            );
-            *index += 2;
-            val
-        }
-
-        macro_rules! read_u16_checked {
-            ($buf: ident, $index: ident, $len: expr) => {{
-                if $index + 2 > $len as usize {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid data"));
-                }
-                unsafe { read_u16_unchecked($buf, &mut $index) }
-            }};
+    /// Value of the string header `key`.
+    #[inline]
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.str_headers.get(key).map(SmolStr::as_str)
+    }
+
+    /// Set a string header, returning the previous value if any.
+    #[inline]
+    pub fn insert_header(&mut self, key: &str, val: &str) -> Option<SmolStr> {
+        self.str_headers
+            .insert(SmolStr::new(key), SmolStr::new(val))
+    }
+
+    #[inline]
+    pub fn remove_header(&mut self, key: &str) -> Option<SmolStr> {
+        self.str_headers.remove(key)
+    }
+
+    /// String headers in insertion order.
+    #[inline]
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.str_headers
+            .iter()
+            .map(|(key, val)| (key.as_str(), val.as_str()))
+    }
+
+    #[inline]
+    pub fn int_header(&self, key: IntMetaKey) -> Option<&HeaderValue> {
+        self.int_headers[key as usize].as_ref()
+    }
+
+    #[inline]
+    pub fn set_int_meta(&mut self, key: IntMetaKey, val: impl Into<HeaderValue>) {
+        self.set_int_header(key as u16, val.into());
+    }
+
+    /// Timeout the caller set for the whole RPC.
+    #[inline]
+    pub fn rpc_timeout(&self) -> Option<Duration> {
+        self.millis_meta(IntMetaKey::RPCTimeoutMs)
+    }
+
+    #[inline]
+    pub fn set_rpc_timeout(&mut self, timeout: Duration) {
+        self.set_millis_meta(IntMetaKey::RPCTimeoutMs, timeout);
+    }
+
+    /// Timeout the caller set for establishing the connection.
+    #[inline]
+    pub fn conn_timeout(&self) -> Option<Duration> {
+        self.millis_meta(IntMetaKey::ConnTimeoutMs)
+    }
+
+    #[inline]
+    pub fn set_conn_timeout(&mut self, timeout: Duration) {
+        self.set_millis_meta(IntMetaKey::ConnTimeoutMs, timeout);
+    }
+
+    str_metas! {
+        log_id, set_log_id => LogId,
+        env, set_env => Env,
+        from_service, set_from_service => FromService,
+        from_method, set_from_method => FromMethod,
+        from_cluster, set_from_cluster => FromCluster,
+        from_idc, set_from_idc => FromIdc,
+        to_service, set_to_service => ToService,
+        to_method, set_to_method => ToMethod,
+        to_cluster, set_to_cluster => ToCluster,
+        to_idc, set_to_idc => ToIdc,
+    }
+
+    #[inline]
+    fn millis_meta(&self, key: IntMetaKey) -> Option<Duration> {
+        self.int_header(key)?
+            .as_str()?
+            .parse()
+            .ok()
+            .map(Duration::from_millis)
+    }
+
+    #[inline]
+    fn set_millis_meta(&mut self, key: IntMetaKey, val: Duration) {
+        let millis = u64::try_from(val.as_millis()).unwrap_or(u64::MAX);
+        self.set_int_meta(key, millis.to_string());
+    }
+
+    #[inline]
+    fn set_int_header(&mut self, key: u16, val: HeaderValue) {
+        if (key as usize) < IntMetaKey::INDEX_TABLE_SIZE {
+            self.int_headers[key as usize] = Some(val);
+        } else if let Some(slot) = self.int_headers_ext.iter_mut().find(|(k, _)| *k == key) {
+            slot.1 = val;
+        } else {
+            self.int_headers_ext.push((key, val));
         }
+    }
 
-        #[inline]
-        unsafe fn read_raw_str_unchecked(buf: &[u8], len: usize, index: &mut usize) -> SmolStr {
-            let val = {
-                let str = SmolStr::new(std::str::from_utf8_unchecked(
-                    buf.get_unchecked(*index..(*index + len)),
-                ));
-                str
+    /// Exact number of bytes `TTHeaderEncoder` writes for this header,
+    /// including the 4-byte frame length and padding but not the payload.
+    pub fn encoded_len(&self) -> usize {
+        header_encoded_len(self)
+    }
+
+    fn header_region_len(&self) -> usize {
+        header_region_len(self)
+    }
+
+    fn decode_header(
+        &mut self,
+        total_length: u32,
+        src: &mut BytesMut,
+        opts: &DecodeOptions,
+    ) -> Result<(), CodecError> {
+        let mut head = split_head(total_length, src)?;
+        if opts.retain_raw {
+            self.raw = Some(head.clone());
+        }
+        let prelude = parse_header(total_length, &mut head, opts, self)?;
+        self.header_length = prelude.header_length;
+        self.payload_length = prelude.payload_length;
+        self.seq_id = prelude.seq_id;
+        self.flags = prelude.flags;
+        self.protocol_id = prelude.protocol_id;
+        self.transform_ids = prelude.transform_ids;
+        Ok(())
+    }
+}
+
+impl HeaderSink for TTHeader {
+    #[inline]
+    fn reserve_str_headers(&mut self, additional: usize) {
+        self.str_headers.reserve(additional);
+    }
+
+    #[inline]
+    fn on_str_header(
+        &mut self,
+        buf: &Bytes,
+        key: Range<usize>,
+        val: Range<usize>,
+    ) -> Result<(), CodecError> {
+        self.str_headers
+            .insert(read_str(&buf[key])?, read_str(&buf[val])?);
+        Ok(())
+    }
+
+    #[inline]
+    fn on_int_header(
+        &mut self,
+        buf: &Bytes,
+        key: u16,
+        val: Range<usize>,
+    ) -> Result<(), CodecError> {
+        // Int metas may carry binary data such as span contexts, keep those as bytes.
+        let val = match std::str::from_utf8(&buf[val.clone()]) {
+            Ok(s) => HeaderValue::Str(SmolStr::new(s)),
+            Err(_) => HeaderValue::Binary(buf.slice(val)),
+        };
+        if (key as usize) < IntMetaKey::INDEX_TABLE_SIZE {
+            // It's safe because `if expr`
+            unsafe {
+                *self.int_headers.get_unchecked_mut(key as usize) = Some(val);
+            }
+        } else {
+            self.int_headers_ext.push((key, val));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn on_acl_token(&mut self, buf: &Bytes, val: Range<usize>) -> Result<(), CodecError> {
+        self.acl_token = Some(read_str(&buf[val])?);
+        Ok(())
+    }
+
+    #[inline]
+    fn on_unknown_info(&mut self, info_id: u8, data: Bytes) -> Result<(), CodecError> {
+        self.unknown_infos.push((info_id, data));
+        Ok(())
+    }
+
+    #[inline]
+    fn on_payload_crc32c(&mut self, crc: u32, _at: usize) -> Result<(), CodecError> {
+        self.payload_crc32c = Some(crc);
+        Ok(())
+    }
+}
+
+impl HeaderSource for TTHeader {
+    #[inline]
+    fn prelude(&self) -> HeaderPrelude {
+        HeaderPrelude {
+            header_length: self.header_length,
+            payload_length: self.payload_length,
+            seq_id: self.seq_id,
+            flags: self.flags,
+            protocol_id: self.protocol_id,
+            transform_ids: self.transform_ids.clone(),
+        }
+    }
+
+    #[inline]
+    fn iter_str_headers(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.str_headers
+            .iter()
+            .map(|(key, val)| (key.as_bytes(), val.as_bytes()))
+    }
+
+    #[inline]
+    fn iter_int_headers(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.int_headers
+            .iter()
+            .enumerate()
+            .filter_map(|(key, val)| val.as_ref().map(|val| (key as u16, val.as_bytes())))
+            .chain(
+                self.int_headers_ext
+                    .iter()
+                    .map(|(key, val)| (*key, val.as_bytes())),
+            )
+    }
+
+    #[inline]
+    fn acl_token_bytes(&self) -> Option<&[u8]> {
+        self.acl_token.as_ref().map(|s| s.as_bytes())
+    }
+
+    #[inline]
+    fn unknown_info_sections(&self) -> &[(u8, Bytes)] {
+        &self.unknown_infos
+    }
+
+    #[inline]
+    fn payload_crc32c(&self) -> Option<u32> {
+        self.payload_crc32c
+    }
+}
+
+#[inline]
+fn read_str(buf: &[u8]) -> Result<SmolStr, CodecError> {
+    std::str::from_utf8(buf)
+        .map(SmolStr::new)
+        .map_err(|_| TTHeaderError::InvalidKv("invalid utf8").into())
+}
+
+/// Value of an int header.
+///
+/// Most metas are text, but some (e.g. serialized span contexts) are opaque
+/// bytes. Decoding yields `Str` whenever the value is valid UTF-8.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(untagged)
+)]
+pub enum HeaderValue {
+    Str(SmolStr),
+    Binary(Bytes),
+}
+
+impl HeaderValue {
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            HeaderValue::Str(s) => s.as_bytes(),
+            HeaderValue::Binary(b) => b,
+        }
+    }
+
+    /// The value as text, `None` for binary values.
+    #[inline]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            HeaderValue::Str(s) => Some(s),
+            HeaderValue::Binary(_) => None,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.as_bytes().is_empty()
+    }
+}
+
+impl Default for HeaderValue {
+    fn default() -> Self {
+        HeaderValue::Str(SmolStr::default())
+    }
+}
+
+impl From<SmolStr> for HeaderValue {
+    #[inline]
+    fn from(s: SmolStr) -> Self {
+        HeaderValue::Str(s)
+    }
+}
+
+impl From<&str> for HeaderValue {
+    #[inline]
+    fn from(s: &str) -> Self {
+        HeaderValue::Str(SmolStr::new(s))
+    }
+}
+
+impl From<String> for HeaderValue {
+    #[inline]
+    fn from(s: String) -> Self {
+        HeaderValue::Str(SmolStr::new(s))
+    }
+}
+
+impl From<Bytes> for HeaderValue {
+    #[inline]
+    fn from(b: Bytes) -> Self {
+        HeaderValue::Binary(b)
+    }
+}
+
+impl PartialEq<str> for HeaderValue {
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for HeaderValue {
+    #[inline]
+    fn eq(&self, other: &&str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+/// TTHeader whose keys and values are [`Bytes`] slices of the frame buffer.
+///
+/// Decoding one neither allocates per header nor validates UTF-8, which suits
+/// proxies that only inspect a few headers and forward the rest. Headers are
+/// kept in wire order, duplicates included.
+#[derive(Clone, Default)]
+pub struct RawTTHeader {
+    pub header_length: u32,
+    pub payload_length: u32,
+    pub seq_id: i32,
+    pub flags: TTHeaderFlags,
+    pub protocol_id: ProtocolId,
+    pub transform_ids: SmallVec<[u8; 4]>,
+    pub int_headers: SmallVec<[(u16, Bytes); 8]>,
+    pub str_headers: SmallVec<[(Bytes, Bytes); 4]>,
+    pub acl_token: Option<Bytes>,
+    pub unknown_infos: SmallVec<[(u8, Bytes); 1]>,
+    pub payload_crc32c: Option<u32>,
+}
+
+impl RawTTHeader {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Last value of the string header `key`.
+    #[inline]
+    pub fn str_header(&self, key: &[u8]) -> Option<&Bytes> {
+        self.str_headers
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Last value of the int header `key`.
+    #[inline]
+    pub fn int_header(&self, key: IntMetaKey) -> Option<&Bytes> {
+        self.raw_int_header(key as u16)
+    }
+
+    #[inline]
+    pub fn raw_int_header(&self, key: u16) -> Option<&Bytes> {
+        self.int_headers
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Exact number of bytes `TTHeaderEncoder` writes for this header,
+    /// including the 4-byte frame length and padding but not the payload.
+    pub fn encoded_len(&self) -> usize {
+        header_encoded_len(self)
+    }
+
+    /// Convert into a [`TTHeader`], failing if a string header or the acl
+    /// token is not UTF-8.
+    pub fn into_header(self) -> Result<TTHeader, CodecError> {
+        let mut header = TTHeader {
+            header_length: self.header_length,
+            payload_length: self.payload_length,
+            seq_id: self.seq_id,
+            flags: self.flags,
+            protocol_id: self.protocol_id,
+            transform_ids: self.transform_ids,
+            unknown_infos: self.unknown_infos,
+            payload_crc32c: self.payload_crc32c,
+            ..Default::default()
+        };
+        header.str_headers.reserve(self.str_headers.len());
+        for (key, val) in self.str_headers.iter() {
+            header.str_headers.insert(read_str(key)?, read_str(val)?);
+        }
+        for (key, val) in self.int_headers {
+            let val = match std::str::from_utf8(&val) {
+                Ok(s) => HeaderValue::Str(SmolStr::new(s)),
+                Err(_) => HeaderValue::Binary(val),
             };
-            *index += len;
-            val
+            header.set_int_header(key, val);
+        }
+        if let Some(ref acl_token) = self.acl_token {
+            header.acl_token = Some(read_str(acl_token)?);
         }
+        Ok(header)
+    }
 
-        macro_rules! read_str_checked {
-            ($buf: ident, $index: ident, $len: expr) => {{
-                let val_len = read_u16_checked!($buf, $index, $len);
-                if $index + val_len as usize > $len as usize {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid data"));
-                }
-                unsafe { read_raw_str_unchecked($buf, val_len as usize, &mut $index) }
-            }};
-        }
-
-        src.advance(2); // skip magic
-        self.flags = src.get_u16();
-        self.seq_id = src.get_i32();
-        let header_size = src.get_u16();
-        self.header_length = header_size as u32 * 4;
-        if self.header_length as usize > src.len() || header_size < 1 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "invalid header length",
-            ));
+    fn decode_header(
+        &mut self,
+        total_length: u32,
+        src: &mut BytesMut,
+        opts: &DecodeOptions,
+    ) -> Result<(), CodecError> {
+        let mut head = split_head(total_length, src)?;
+        let prelude = parse_header(total_length, &mut head, opts, self)?;
+        self.header_length = prelude.header_length;
+        self.payload_length = prelude.payload_length;
+        self.seq_id = prelude.seq_id;
+        self.flags = prelude.flags;
+        self.protocol_id = prelude.protocol_id;
+        self.transform_ids = prelude.transform_ids;
+        Ok(())
+    }
+}
+
+impl HeaderSink for RawTTHeader {
+    #[inline]
+    fn reserve_str_headers(&mut self, additional: usize) {
+        self.str_headers.reserve(additional);
+    }
+
+    #[inline]
+    fn on_str_header(
+        &mut self,
+        buf: &Bytes,
+        key: Range<usize>,
+        val: Range<usize>,
+    ) -> Result<(), CodecError> {
+        self.str_headers.push((buf.slice(key), buf.slice(val)));
+        Ok(())
+    }
+
+    #[inline]
+    fn on_int_header(
+        &mut self,
+        buf: &Bytes,
+        key: u16,
+        val: Range<usize>,
+    ) -> Result<(), CodecError> {
+        self.int_headers.push((key, buf.slice(val)));
+        Ok(())
+    }
+
+    #[inline]
+    fn on_acl_token(&mut self, buf: &Bytes, val: Range<usize>) -> Result<(), CodecError> {
+        self.acl_token = Some(buf.slice(val));
+        Ok(())
+    }
+
+    #[inline]
+    fn on_unknown_info(&mut self, info_id: u8, data: Bytes) -> Result<(), CodecError> {
+        self.unknown_infos.push((info_id, data));
+        Ok(())
+    }
+
+    #[inline]
+    fn on_payload_crc32c(&mut self, crc: u32, _at: usize) -> Result<(), CodecError> {
+        self.payload_crc32c = Some(crc);
+        Ok(())
+    }
+}
+
+impl HeaderSource for RawTTHeader {
+    #[inline]
+    fn prelude(&self) -> HeaderPrelude {
+        HeaderPrelude {
+            header_length: self.header_length,
+            payload_length: self.payload_length,
+            seq_id: self.seq_id,
+            flags: self.flags,
+            protocol_id: self.protocol_id,
+            transform_ids: self.transform_ids.clone(),
         }
-        let header_buf = src.split_to(self.header_length as usize);
-        self.payload_length = total_length - self.header_length - 10;
-        let buf = header_buf.as_ref();
-        let mut index = 0;
-        // It's safe when checked header_size >= 1
-        if let Ok(protocol_id) = unsafe { ProtocolId::try_from(read_u8_unchecked(buf, &mut index)) }
-        {
-            self.protocol_id = protocol_id;
+    }
+
+    #[inline]
+    fn iter_str_headers(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.str_headers
+            .iter()
+            .map(|(key, val)| (key.as_ref(), val.as_ref()))
+    }
+
+    #[inline]
+    fn iter_int_headers(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.int_headers
+            .iter()
+            .map(|(key, val)| (*key, val.as_ref()))
+    }
+
+    #[inline]
+    fn acl_token_bytes(&self) -> Option<&[u8]> {
+        self.acl_token.as_deref()
+    }
+
+    #[inline]
+    fn unknown_info_sections(&self) -> &[(u8, Bytes)] {
+        &self.unknown_infos
+    }
+
+    #[inline]
+    fn payload_crc32c(&self) -> Option<u32> {
+        self.payload_crc32c
+    }
+}
+
+// Fixed size fields at the front of a TTHeader.
+struct HeaderPrelude {
+    header_length: u32,
+    payload_length: u32,
+    seq_id: i32,
+    flags: TTHeaderFlags,
+    protocol_id: ProtocolId,
+    transform_ids: SmallVec<[u8; 4]>,
+}
+
+// Receives the variable sections of a TTHeader while it is parsed. Ranges and
+// offsets index into `buf` and are already bounds checked.
+trait HeaderSink {
+    fn reserve_str_headers(&mut self, additional: usize);
+    fn on_str_header(
+        &mut self,
+        buf: &Bytes,
+        key: Range<usize>,
+        val: Range<usize>,
+    ) -> Result<(), CodecError>;
+    fn on_int_header(&mut self, buf: &Bytes, key: u16, val: Range<usize>)
+        -> Result<(), CodecError>;
+    fn on_acl_token(&mut self, buf: &Bytes, val: Range<usize>) -> Result<(), CodecError>;
+    fn on_unknown_info(&mut self, info_id: u8, data: Bytes) -> Result<(), CodecError>;
+    fn on_payload_crc32c(&mut self, crc: u32, at: usize) -> Result<(), CodecError>;
+}
+
+// Sections of a TTHeader in the order `TTHeaderEncoder` writes them.
+trait HeaderSource {
+    fn prelude(&self) -> HeaderPrelude;
+    fn iter_str_headers(&self) -> impl Iterator<Item = (&[u8], &[u8])>;
+    fn iter_int_headers(&self) -> impl Iterator<Item = (u16, &[u8])>;
+    fn acl_token_bytes(&self) -> Option<&[u8]>;
+    fn unknown_info_sections(&self) -> &[(u8, Bytes)];
+    fn payload_crc32c(&self) -> Option<u32>;
+}
+
+#[inline]
+fn header_encoded_len(header: &impl HeaderSource) -> usize {
+    MIN_HEADER_LENGTH + header_region_len(header).next_multiple_of(4)
+}
+
+// Unpadded size of the region covered by the header size field.
+fn header_region_len(header: &impl HeaderSource) -> usize {
+    // protocol id + transform ids num + transform ids
+    let mut len = 2 + header.prelude().transform_ids.len();
+    // string kv
+    len += 3;
+    for (key, val) in header.iter_str_headers() {
+        len += 4 + key.len() + val.len();
+    }
+    // int kv
+    len += 3;
+    for (_, val) in header.iter_int_headers() {
+        len += 4 + val.len();
+    }
+    if let Some(acl_token) = header.acl_token_bytes() {
+        len += 3 + acl_token.len();
+    }
+    if header.payload_crc32c().is_some() {
+        len += 5;
+    }
+    for (_, data) in header.unknown_info_sections() {
+        len += 1 + data.len();
+    }
+    len
+}
+
+// Knobs shared by the TTHeader decoders.
+#[derive(Clone, Copy, Default)]
+struct DecodeOptions {
+    config: TTHeaderDecoderConfig,
+    passthrough_unknown: bool,
+    retain_raw: bool,
+    strict: bool,
+}
+
+impl DecodeOptions {
+    const fn new(config: TTHeaderDecoderConfig) -> Self {
+        Self {
+            config,
+            passthrough_unknown: false,
+            retain_raw: false,
+            strict: false,
         }
-        index += 1; // TODO: support transform
+    }
+}
 
-        let mut _padding_num = 0usize;
+// Split off the header, from the magic to the end of the header region, of a
+// frame of `total_length` bytes. `src` must hold at least the 10 fixed bytes
+// following the frame length.
+fn split_head(total_length: u32, src: &mut BytesMut) -> Result<Bytes, CodecError> {
+    let header_length = u16::from_be_bytes([src[8], src[9]]) as usize * 4;
+    if 10 + header_length > total_length as usize || 10 + header_length > src.len() {
+        return Err(TTHeaderError::InvalidHeaderLength.into());
+    }
+    Ok(src.split_to(10 + header_length).freeze())
+}
 
-        while index < self.header_length as usize {
-            // It's safe because while expr
-            let info_id = unsafe { read_u8_unchecked(buf, &mut index) };
-            match info_id {
-                info::INFO_PADDING => {
-                    _padding_num += 1;
-                    continue;
-                }
-                info::INFO_KEY_VALUE => {
-                    let kv_size = read_u16_checked!(buf, index, self.header_length);
-                    // TODO: reserve
-                    for _ in 0..kv_size {
-                        let key = read_str_checked!(buf, index, self.header_length);
-                        let val = read_str_checked!(buf, index, self.header_length);
-                        self.str_headers.insert(key, val);
-                    }
+fn parse_header(
+    total_length: u32,
+    src: &mut Bytes,
+    opts: &DecodeOptions,
+    sink: &mut impl HeaderSink,
+) -> Result<HeaderPrelude, CodecError> {
+    let config = &opts.config;
+    #[inline]
+    unsafe fn read_u8_unchecked(buf: &[u8], index: &mut usize) -> u8 {
+        let val = *buf.get_unchecked(*index);
+        *index += 1;
+        val
+    }
+
+    #[inline]
+    unsafe fn read_u16_unchecked(buf: &[u8], index: &mut usize) -> u16 {
+        let val = u16::from_be_bytes(
+            buf.get_unchecked(*index..*index + 2)
+                .try_into()
+                .unwrap_unchecked(),
+        );
+        *index += 2;
+        val
+    }
+
+    macro_rules! read_u16_checked {
+        ($buf: ident, $index: ident, $len: expr) => {{
+            if $index + 2 > $len as usize {
+                return Err(TTHeaderError::Truncated.into());
+            }
+            unsafe { read_u16_unchecked($buf, &mut $index) }
+        }};
+    }
+
+    // Evaluates to the range of the string, without touching its bytes.
+    macro_rules! read_str_checked {
+        ($buf: ident, $index: ident, $len: expr) => {{
+            let val_len = read_u16_checked!($buf, $index, $len) as usize;
+            if val_len > config.max_string_len {
+                return Err(TTHeaderError::InvalidKv("string too long").into());
+            }
+            if $index + val_len > $len as usize {
+                return Err(TTHeaderError::Truncated.into());
+            }
+            $index += val_len;
+            $index - val_len..$index
+        }};
+    }
+
+    src.advance(2); // skip magic
+    let flags = TTHeaderFlags::from_bits_retain(src.get_u16());
+    let seq_id = src.get_i32();
+    let header_size = src.get_u16();
+    let header_length = header_size as u32 * 4;
+    if header_size < 1 || header_length + 10 > total_length {
+        return Err(TTHeaderError::InvalidHeaderLength.into());
+    }
+    if header_length as usize > config.max_header_bytes {
+        return Err(TTHeaderError::HeaderTooLarge {
+            len: header_length as usize,
+            max: config.max_header_bytes,
+        }
+        .into());
+    }
+    let header_buf = src.split_to(header_length as usize);
+    let payload_length = total_length - header_length - 10;
+    let buf = header_buf.as_ref();
+    let mut index = 0;
+    // It's safe when checked header_size >= 1
+    let protocol_id = ProtocolId::from(unsafe { read_u8_unchecked(buf, &mut index) });
+    let transform_num = unsafe { read_u8_unchecked(buf, &mut index) } as usize;
+    if index + transform_num > header_length as usize {
+        return Err(TTHeaderError::Truncated.into());
+    }
+    let transform_ids = SmallVec::from_slice(&buf[index..index + transform_num]);
+    index += transform_num;
+
+    let mut padding_num = 0usize;
+    let mut num_headers = 0usize;
+    macro_rules! count_headers {
+        ($kv_size: expr) => {{
+            num_headers += $kv_size as usize;
+            if num_headers > config.max_headers {
+                return Err(TTHeaderError::TooManyHeaders {
+                    max: config.max_headers,
                 }
-                info::INFO_INT_KEY_VALUE => {
-                    let kv_size = read_u16_checked!(buf, index, self.header_length);
-                    for _ in 0..kv_size {
-                        let key = read_u16_checked!(buf, index, self.header_length);
-                        let val = read_str_checked!(buf, index, self.header_length);
-
-                        if (key as usize) < IntMetaKey::INDEX_TABLE_SIZE {
-                            // It's safe because `if expr`
-                            unsafe {
-                                *self.int_headers.get_unchecked_mut(key as usize) = Some(val);
-                            }
-                        } else {
-                            self.int_headers_ext.push((key, val));
-                        }
-                    }
+                .into());
+            }
+        }};
+    }
+
+    while index < header_length as usize {
+        // It's safe because while expr
+        let info_id = unsafe { read_u8_unchecked(buf, &mut index) };
+        match info_id {
+            info::INFO_PADDING => {
+                padding_num += 1;
+                continue;
+            }
+            _ if opts.strict && padding_num > 0 => {
+                // Padding only ever trails the header.
+                return Err(TTHeaderError::InvalidPadding.into());
+            }
+            info::INFO_KEY_VALUE => {
+                let kv_size = read_u16_checked!(buf, index, header_length);
+                count_headers!(kv_size);
+                // each entry takes at least its two 2-byte lengths, don't trust
+                // the count further than the bytes left
+                let left = header_length as usize - index;
+                sink.reserve_str_headers((kv_size as usize).min(left / 4));
+                for _ in 0..kv_size {
+                    let key = read_str_checked!(buf, index, header_length);
+                    let val = read_str_checked!(buf, index, header_length);
+                    sink.on_str_header(&header_buf, key, val)?;
                 }
-                info::ACL_TOKEN_KEY_VALUE => {
-                    self.acl_token = Some(read_str_checked!(buf, index, self.header_length));
+            }
+            info::INFO_INT_KEY_VALUE => {
+                let kv_size = read_u16_checked!(buf, index, header_length);
+                count_headers!(kv_size);
+                for _ in 0..kv_size {
+                    let key = read_u16_checked!(buf, index, header_length);
+                    let val = read_str_checked!(buf, index, header_length);
+                    sink.on_int_header(&header_buf, key, val)?;
                 }
-                _ => {
-                    // We are not able to decode the protocol anymore, since we don't know the
-                    // layout
-                    let msg = format!("unexpected info id in ttheader: {info_id}");
-                    tracing::error!("{}", msg);
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+            info::ACL_TOKEN_KEY_VALUE => {
+                let val = read_str_checked!(buf, index, header_length);
+                sink.on_acl_token(&header_buf, val)?;
+            }
+            info::PAYLOAD_CRC32C => {
+                if index + 4 > header_length as usize {
+                    return Err(TTHeaderError::Truncated.into());
                 }
+                let crc = u32::from_be_bytes(buf[index..index + 4].try_into().unwrap());
+                sink.on_payload_crc32c(crc, index)?;
+                index += 4;
+            }
+            _ if opts.passthrough_unknown => {
+                // We don't know the layout, so keep the rest of the header as is and
+                // re-emit it on encode.
+                sink.on_unknown_info(info_id, header_buf.slice(index..header_length as usize))?;
+                break;
+            }
+            _ => {
+                // We are not able to decode the protocol anymore, since we don't know the
+                // layout
+                tracing::error!("unexpected info id in ttheader: {info_id}");
+                return Err(TTHeaderError::BadInfoId(info_id).into());
             }
         }
-        Ok(())
+    }
+    // The header region is padded to a multiple of 4 bytes, more padding means
+    // the header size covers bytes we never looked at.
+    if opts.strict && padding_num >= 4 {
+        return Err(TTHeaderError::InvalidPadding.into());
+    }
+    Ok(HeaderPrelude {
+        header_length,
+        payload_length,
+        seq_id,
+        flags,
+        protocol_id,
+        transform_ids,
+    })
+}
+
+/// Builder for [`TTHeader`] with typed setters.
+///
+/// `build` checks that every string and header count fits the u16 length
+/// fields of the wire format.
+#[derive(Default)]
+pub struct TTHeaderBuilder {
+    header: TTHeader,
+}
+
+impl TTHeaderBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn seq_id(mut self, seq_id: i32) -> Self {
+        self.header.seq_id = seq_id;
+        self
+    }
+
+    #[inline]
+    pub fn flags(mut self, flags: TTHeaderFlags) -> Self {
+        self.header.flags = flags;
+        self
+    }
+
+    #[inline]
+    pub fn protocol_id(mut self, protocol_id: ProtocolId) -> Self {
+        self.header.protocol_id = protocol_id;
+        self
+    }
+
+    #[inline]
+    pub fn payload_length_hint(mut self, payload_length: u32) -> Self {
+        self.header.payload_length = payload_length;
+        self
+    }
+
+    #[inline]
+    pub fn str_header(mut self, key: impl Into<SmolStr>, val: impl Into<SmolStr>) -> Self {
+        self.header.str_headers.insert(key.into(), val.into());
+        self
+    }
+
+    #[inline]
+    pub fn int_header(mut self, key: IntMetaKey, val: impl Into<HeaderValue>) -> Self {
+        self.header.set_int_header(key as u16, val.into());
+        self
+    }
+
+    /// Set an int header by raw key, for keys not covered by [`IntMetaKey`].
+    #[inline]
+    pub fn raw_int_header(mut self, key: u16, val: impl Into<HeaderValue>) -> Self {
+        self.header.set_int_header(key, val.into());
+        self
+    }
+
+    #[inline]
+    pub fn transform_id(mut self, transform_id: u8) -> Self {
+        self.header.transform_ids.push(transform_id);
+        self
+    }
+
+    #[inline]
+    pub fn acl_token(mut self, acl_token: impl Into<SmolStr>) -> Self {
+        self.header.acl_token = Some(acl_token.into());
+        self
+    }
+
+    pub fn build(self) -> Result<TTHeader, CodecError> {
+        #[inline]
+        fn check_len(s: &[u8]) -> Result<(), CodecError> {
+            if s.len() > u16::MAX as usize {
+                return Err(CodecError::new(
+                    CodecErrorKind::SizeLimitExceeded,
+                    format!("ttheader string too long: {} bytes", s.len()),
+                ));
+            }
+            Ok(())
+        }
+
+        let header = self.header;
+        if header.transform_ids.len() > u8::MAX as usize {
+            return Err(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "too many ttheader transform ids",
+            ));
+        }
+        let int_kv_len = header.int_headers.iter().flatten().count() + header.int_headers_ext.len();
+        if header.str_headers.len() > u16::MAX as usize || int_kv_len > u16::MAX as usize {
+            return Err(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "too many ttheader headers",
+            ));
+        }
+        for (key, val) in header.str_headers.iter() {
+            check_len(key.as_bytes())?;
+            check_len(val.as_bytes())?;
+        }
+        for val in header.int_headers.iter().flatten() {
+            check_len(val.as_bytes())?;
+        }
+        for (_, val) in header.int_headers_ext.iter() {
+            check_len(val.as_bytes())?;
+        }
+        if let Some(ref acl_token) = header.acl_token {
+            check_len(acl_token.as_bytes())?;
+        }
+        if header.header_region_len() > u16::MAX as usize * 4 {
+            return Err(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "ttheader too large",
+            ));
+        }
+        Ok(header)
+    }
+}
+
+/// Reason a TTHeader failed to decode.
+///
+/// Decoders report it as the source of a [`CodecError`] of the matching kind,
+/// use [`TTHeaderError::from_codec_error`] to get it back. Functions returning
+/// an [`io::Error`] wrap it in it, see [`TTHeaderError::from_io_error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TTHeaderError {
+    /// The frame does not carry the TTHeader magic.
+    BadMagic,
+    /// The header size field is zero or overruns the frame.
+    InvalidHeaderLength,
+    /// The header region is larger than the configured limit.
+    HeaderTooLarge { len: usize, max: usize },
+    /// An info section has an id we can't parse.
+    BadInfoId(u8),
+    /// A header key or value is malformed.
+    InvalidKv(&'static str),
+    /// More key-value headers than the configured limit.
+    TooManyHeaders { max: usize },
+    /// A section overruns the header region.
+    Truncated,
+    /// Strict mode only: the header region ends with non-zero or more than 3
+    /// padding bytes.
+    InvalidPadding,
+    /// The payload does not match the checksum carried by the header.
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+impl TTHeaderError {
+    #[inline]
+    pub fn from_io_error(err: &io::Error) -> Option<&TTHeaderError> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    #[inline]
+    pub fn from_codec_error(err: &CodecError) -> Option<&TTHeaderError> {
+        std::error::Error::source(err)?.downcast_ref()
+    }
+}
+
+impl std::fmt::Display for TTHeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TTHeaderError::BadMagic => write!(f, "illegal ttheader"),
+            TTHeaderError::InvalidHeaderLength => write!(f, "invalid header length"),
+            TTHeaderError::HeaderTooLarge { len, max } => {
+                write!(f, "ttheader too large: {len} bytes, max {max}")
+            }
+            TTHeaderError::BadInfoId(id) => write!(f, "unexpected info id in ttheader: {id}"),
+            TTHeaderError::InvalidKv(reason) => write!(f, "invalid ttheader kv: {reason}"),
+            TTHeaderError::TooManyHeaders { max } => {
+                write!(f, "too many ttheader headers, max {max}")
+            }
+            TTHeaderError::Truncated => write!(f, "truncated ttheader"),
+            TTHeaderError::InvalidPadding => write!(f, "invalid ttheader padding"),
+            TTHeaderError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "payload crc32c mismatch: expected {expected:#010x}, got {actual:#010x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TTHeaderError {}
+
+impl From<TTHeaderError> for io::Error {
+    #[inline]
+    fn from(err: TTHeaderError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+impl From<TTHeaderError> for CodecError {
+    #[inline]
+    fn from(err: TTHeaderError) -> Self {
+        let kind = match err {
+            TTHeaderError::HeaderTooLarge { .. } | TTHeaderError::TooManyHeaders { .. } => {
+                CodecErrorKind::SizeLimitExceeded
+            }
+            TTHeaderError::ChecksumMismatch { .. } => CodecErrorKind::ChecksumMismatch,
+            _ => CodecErrorKind::InvalidData,
+        };
+        CodecError::new(kind, err.to_string()).with_source(err)
+    }
+}
+
+/// Limits applied when decoding a TTHeader.
+///
+/// The defaults are the maximums the wire format can express.
+#[derive(Clone, Copy, Debug)]
+pub struct TTHeaderDecoderConfig {
+    /// Max size of the header region in bytes.
+    pub max_header_bytes: usize,
+    /// Max number of string and int key-value headers in total.
+    pub max_headers: usize,
+    /// Max length of a single header key or value in bytes.
+    pub max_string_len: usize,
+}
+
+impl TTHeaderDecoderConfig {
+    pub const DEFAULT: Self = Self {
+        max_header_bytes: u16::MAX as usize * 4,
+        max_headers: u16::MAX as usize * 2,
+        max_string_len: u16::MAX as usize,
+    };
+}
+
+impl Default for TTHeaderDecoderConfig {
+    fn default() -> Self {
+        Self::DEFAULT
     }
 }
 
 #[derive(Default)]
-pub struct TTHeaderDecoder;
+pub struct TTHeaderDecoder {
+    opts: DecodeOptions,
+}
 
 impl TTHeaderDecoder {
     pub const fn new() -> Self {
-        Self
+        Self::with_config(TTHeaderDecoderConfig::DEFAULT)
+    }
+
+    pub const fn with_config(config: TTHeaderDecoderConfig) -> Self {
+        Self {
+            opts: DecodeOptions::new(config),
+        }
+    }
+
+    /// Keep info sections with unknown ids in [`TTHeader::unknown_infos`]
+    /// instead of failing the decode.
+    pub const fn passthrough_unknown(mut self, enabled: bool) -> Self {
+        self.opts.passthrough_unknown = enabled;
+        self
+    }
+
+    /// Reject headers whose padding is non-zero or longer than needed to
+    /// align the header region, see [`TTHeaderError::InvalidPadding`].
+    pub const fn strict(mut self, enabled: bool) -> Self {
+        self.opts.strict = enabled;
+        self
+    }
+
+    /// Keep the encoded header in [`TTHeader::raw`] so it can be forwarded
+    /// with [`TTHeaderEncoder::encode_retained`].
+    pub const fn retain_raw(mut self, enabled: bool) -> Self {
+        self.opts.retain_raw = enabled;
+        self
     }
 }
 
 impl Decoder for TTHeaderDecoder {
     type Item = TTHeader;
-    type Error = io::Error;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        let length = match decode_frame_length(src, &self.opts.config)? {
+            Decoded::Some(length) => length,
+            Decoded::Insufficient => return Ok(Decoded::Insufficient),
+            Decoded::InsufficientAtLeast(n) => return Ok(Decoded::InsufficientAtLeast(n)),
+        };
+        // decode ttheader
+        let mut ttheader = TTHeader::new();
+        ttheader.decode_header(length, src, &self.opts)?;
+        Ok(Decoded::Some(ttheader))
+    }
+}
 
-    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
-        if src.len() < MIN_HEADER_LENGTH {
-            return Ok(Decoded::InsufficientAtLeast(MIN_HEADER_LENGTH));
+/// Decoder for [`RawTTHeader`], the allocation free counterpart of
+/// [`TTHeaderDecoder`].
+#[derive(Default)]
+pub struct RawTTHeaderDecoder {
+    opts: DecodeOptions,
+}
+
+impl RawTTHeaderDecoder {
+    pub const fn new() -> Self {
+        Self::with_config(TTHeaderDecoderConfig::DEFAULT)
+    }
+
+    pub const fn with_config(config: TTHeaderDecoderConfig) -> Self {
+        Self {
+            opts: DecodeOptions::new(config),
         }
+    }
 
-        if src[4..HEADER_DETECT_LENGTH] == [0x10, 0x00] {
-            let mut header_length = [0; 2];
-            unsafe { copy_nonoverlapping(src.as_ptr().add(12), header_length.as_mut_ptr(), 2) };
-            let header_length = u16::from_be_bytes(header_length) as usize * 4;
-            if src.len() < header_length + MIN_HEADER_LENGTH {
-                return Ok(Decoded::InsufficientAtLeast(
-                    header_length + MIN_HEADER_LENGTH,
-                ));
-            }
+    /// Keep info sections with unknown ids in [`RawTTHeader::unknown_infos`]
+    /// instead of failing the decode.
+    pub const fn passthrough_unknown(mut self, enabled: bool) -> Self {
+        self.opts.passthrough_unknown = enabled;
+        self
+    }
 
-            let mut length = [0; 4];
-            unsafe { copy_nonoverlapping(src.as_ptr(), length.as_mut_ptr(), 4) };
-            let length = u32::from_be_bytes(length);
+    /// Reject headers whose padding is non-zero or longer than needed to
+    /// align the header region, see [`TTHeaderError::InvalidPadding`].
+    pub const fn strict(mut self, enabled: bool) -> Self {
+        self.opts.strict = enabled;
+        self
+    }
+}
 
-            src.advance(4);
+impl Decoder for RawTTHeaderDecoder {
+    type Item = RawTTHeader;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        let length = match decode_frame_length(src, &self.opts.config)? {
+            Decoded::Some(length) => length,
+            Decoded::Insufficient => return Ok(Decoded::Insufficient),
+            Decoded::InsufficientAtLeast(n) => return Ok(Decoded::InsufficientAtLeast(n)),
+        };
+        let mut ttheader = RawTTHeader::new();
+        ttheader.decode_header(length, src, &self.opts)?;
+        Ok(Decoded::Some(ttheader))
+    }
+}
 
-            // decode ttheader
-            let mut ttheader = TTHeader::new();
-            ttheader.decode_header(length, src)?; // TODO: which error type?
-            Ok(Decoded::Some(ttheader))
-        } else {
-            Err(io::Error::new(io::ErrorKind::Other, "illegal ttheader"))
+// Wait until the whole header is buffered, then consume the 4-byte frame
+// length and return it.
+fn decode_frame_length(
+    src: &mut BytesMut,
+    config: &TTHeaderDecoderConfig,
+) -> Result<Decoded<u32>, CodecError> {
+    if src.len() < MIN_HEADER_LENGTH {
+        return Ok(Decoded::InsufficientAtLeast(MIN_HEADER_LENGTH));
+    }
+
+    if src[4..HEADER_DETECT_LENGTH] == [0x10, 0x00] {
+        let mut header_length = [0; 2];
+        unsafe { copy_nonoverlapping(src.as_ptr().add(12), header_length.as_mut_ptr(), 2) };
+        let header_length = u16::from_be_bytes(header_length) as usize * 4;
+        if header_length > config.max_header_bytes {
+            return Err(TTHeaderError::HeaderTooLarge {
+                len: header_length,
+                max: config.max_header_bytes,
+            }
+            .into());
         }
+        let mut length = [0; 4];
+        unsafe { copy_nonoverlapping(src.as_ptr(), length.as_mut_ptr(), 4) };
+        let length = u32::from_be_bytes(length);
+        // the header must fit in the frame, checked before waiting for it
+        if header_length + 10 > length as usize {
+            return Err(TTHeaderError::InvalidHeaderLength.into());
+        }
+        if src.len() < header_length + MIN_HEADER_LENGTH {
+            return Ok(Decoded::InsufficientAtLeast(
+                header_length + MIN_HEADER_LENGTH,
+            ));
+        }
+
+        src.advance(4);
+        Ok(Decoded::Some(length))
+    } else {
+        Err(TTHeaderError::BadMagic.into())
     }
 }
 
@@ -242,103 +1404,278 @@ impl TTHeaderEncoder {
     pub const fn new() -> Self {
         Self
     }
+
+    /// Encode a frame as two buffers, the header and the untouched `payload`,
+    /// so they can be sent with a single vectored write without copying the
+    /// payload.
+    ///
+    /// `header.payload_length` is set to the length of `payload`.
+    pub fn encode_vectored(
+        &mut self,
+        mut header: TTHeader,
+        payload: Bytes,
+    ) -> Result<(BytesMut, Bytes), CodecError> {
+        let encoded_len = header.encoded_len();
+        if encoded_len - 4 + payload.len() > u32::MAX as usize {
+            return Err(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "ttheader frame too large",
+            ));
+        }
+        header.payload_length = payload.len() as u32;
+        let mut dst = BytesMut::with_capacity(encoded_len);
+        encode_header(&header, &mut dst);
+        Ok((dst, payload))
+    }
+
+    /// Write the header bytes retained on decode as is, without re-encoding.
+    ///
+    /// Only the frame length, derived from `header.payload_length`, and
+    /// optionally the seq id are rewritten. Fails if `header` was decoded
+    /// without `retain_raw`.
+    pub fn encode_retained(
+        &mut self,
+        header: &TTHeader,
+        seq_id: Option<i32>,
+        dst: &mut BytesMut,
+    ) -> Result<(), CodecError> {
+        let Some(ref raw) = header.raw else {
+            return Err(CodecError::new(
+                CodecErrorKind::InvalidData,
+                "ttheader has no retained bytes",
+            ));
+        };
+        dst.reserve(4 + raw.len());
+        dst.put_u32((raw.len() + header.payload_length as usize) as u32);
+        let zero_index = dst.len();
+        dst.put_slice(raw);
+        if let Some(seq_id) = seq_id {
+            // magic + flags
+            let mut buf = &mut dst[zero_index + 4..zero_index + 8];
+            buf.put_i32(seq_id);
+        }
+        Ok(())
+    }
 }
 
 impl Encoder<TTHeader> for TTHeaderEncoder {
-    type Error = io::Error;
+    type Error = CodecError;
+
+    #[inline]
+    fn encode(&mut self, item: TTHeader, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        encode_header(&item, dst);
+        Ok(())
+    }
+}
+
+impl Encoder<RawTTHeader> for TTHeaderEncoder {
+    type Error = CodecError;
+
+    #[inline]
+    fn encode(&mut self, item: RawTTHeader, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        encode_header(&item, dst);
+        Ok(())
+    }
+}
+
+// Returns the offset of the payload checksum in `dst`, if one was written.
+fn encode_header(item: &impl HeaderSource, dst: &mut BytesMut) -> Option<usize> {
+    let (zero_index, crc_index) = encode_header_unsized(item, dst);
+    fill_frame_length(
+        dst,
+        zero_index,
+        dst.len() + item.prelude().payload_length as usize,
+    );
+    crc_index
+}
+
+// Encode the header leaving the frame length unfilled. Returns the offset of
+// the frame and of the payload checksum, if one was written.
+fn encode_header_unsized(item: &impl HeaderSource, dst: &mut BytesMut) -> (usize, Option<usize>) {
+    #[inline]
+    fn put_str(s: &[u8], dst: &mut BytesMut) {
+        dst.put_u16(s.len() as u16);
+        dst.put_slice(s);
+    }
+
+    let prelude = item.prelude();
+    dst.reserve(header_encoded_len(item));
+    let zero_index = dst.len();
+    unsafe {
+        dst.advance_mut(4);
+    }
+
+    // tt header magic
+    dst.put_u16(TT_HEADER_MAGIC);
+    // flags
+    dst.put_u16(prelude.flags.bits());
+    dst.put_i32(prelude.seq_id);
+
+    // Alloc 2-byte space as header length
+    unsafe {
+        dst.advance_mut(2);
+    }
+
+    dst.put_u8(prelude.protocol_id.into());
+    dst.put_u8(prelude.transform_ids.len() as u8);
+    dst.put_slice(&prelude.transform_ids);
+
+    // Write string KV start.
+    dst.put_u8(info::INFO_KEY_VALUE);
+    let str_kv_index = dst.len();
+    let mut str_kv_len = 0_u16;
+    unsafe {
+        dst.advance_mut(2);
+    }
+
+    for (key, val) in item.iter_str_headers() {
+        put_str(key, dst);
+        put_str(val, dst);
+        str_kv_len += 1;
+    }
 
-    fn encode(&mut self, item: TTHeader, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
-        #[inline]
-        fn put_str(s: &SmolStr, dst: &mut bytes::BytesMut) {
-            dst.put_u16(s.len() as u16);
-            dst.put_slice(s.as_bytes());
-        }
+    // fill string kv length
+    let mut buf = &mut dst[str_kv_index..str_kv_index + 2];
+    buf.put_u16(str_kv_len);
 
-        dst.reserve(4 * 1024); // cap 4k
-        let zero_index = dst.len();
-        unsafe {
-            dst.advance_mut(4);
-        }
+    // Write int KV start.
+    dst.put_u8(info::INFO_INT_KEY_VALUE);
+    let int_kv_index = dst.len();
+    let mut int_kv_len = 0_u16;
+    unsafe {
+        dst.advance_mut(2);
+    }
 
-        // tt header magic
-        dst.put_u16(TT_HEADER_MAGIC);
-        // flags
-        dst.put_u16(item.flags);
-        dst.put_i32(item.seq_id);
+    for (key, val) in item.iter_int_headers() {
+        dst.put_u16(key);
+        put_str(val, dst);
+        int_kv_len += 1;
+    }
 
-        // Alloc 2-byte space as header length
-        unsafe {
-            dst.advance_mut(2);
-        }
+    // fill int kv length
+    let mut buf = &mut dst[int_kv_index..int_kv_index + 2];
+    buf.put_u16(int_kv_len);
 
-        dst.put_u8(item.protocol_id as u8);
-        dst.put_u8(0); // TODO: transform_ids_num
+    // fill acl_token
+    if let Some(acl_token) = item.acl_token_bytes() {
+        dst.put_u8(info::ACL_TOKEN_KEY_VALUE);
+        put_str(acl_token, dst);
+    }
 
-        // Write string KV start.
-        dst.put_u8(info::INFO_KEY_VALUE);
-        dst.put_u16(item.str_headers.len() as u16);
+    // fill payload checksum
+    let crc_index = item.payload_crc32c().map(|crc| {
+        dst.put_u8(info::PAYLOAD_CRC32C);
+        dst.put_u32(crc);
+        dst.len() - 4
+    });
+
+    // re-emit unknown info sections
+    for (info_id, data) in item.unknown_info_sections() {
+        dst.put_u8(*info_id);
+        dst.put_slice(data);
+    }
 
-        for (key, val) in item.str_headers.iter() {
-            put_str(key, dst);
-            put_str(val, dst);
-        }
+    // write padding
+    let overflow = (dst.len() - 14 - zero_index) % 4;
+    let padding = (4 - overflow) % 4;
+    (0..padding).for_each(|_| dst.put_u8(0));
+
+    // fill header length
+    let header_size = dst.len() - zero_index;
+    let mut buf = &mut dst[zero_index + 12..zero_index + 12 + 2];
+    buf.put_u16(((header_size - 14) / 4).try_into().unwrap());
+    tracing::trace!(
+        "encode ttheader write header size: {}",
+        (header_size - 14) / 4
+    );
+
+    (zero_index, crc_index)
+}
 
-        // Write int KV start.
-        dst.put_u8(info::INFO_INT_KEY_VALUE);
-        let int_kv_index = dst.len();
-        let mut int_kv_len = 0_u16;
-        unsafe {
-            dst.advance_mut(2);
-        }
+// Fill the length of the frame starting at `zero_index` and ending at `end`.
+#[inline]
+fn fill_frame_length(dst: &mut BytesMut, zero_index: usize, end: usize) {
+    let size = end - zero_index;
+    let mut buf = &mut dst[zero_index..zero_index + 4];
+    buf.put_u32((size - 4) as u32);
+    tracing::trace!("encode ttheader write length size: {}", size - 4);
+}
 
-        for (key, val) in item.int_headers.iter().enumerate() {
-            if let Some(val) = val {
-                dst.put_u16(key as u16);
-                put_str(val, dst);
-                int_kv_len += 1;
-            }
-        }
+// Finds the payload checksum while a header is parsed, ignoring the rest.
+#[derive(Default)]
+struct Crc32cLocator {
+    at: Option<usize>,
+}
 
-        for (key, val) in item.int_headers_ext.iter() {
-            dst.put_u16(*key);
-            dst.put_slice(val.as_bytes());
-            int_kv_len += 1;
-        }
+impl HeaderSink for Crc32cLocator {
+    #[inline]
+    fn reserve_str_headers(&mut self, _additional: usize) {}
 
-        // fill int kv length
-        let mut buf = &mut dst[int_kv_index..int_kv_index + 2];
-        buf.put_u16(int_kv_len);
+    #[inline]
+    fn on_str_header(
+        &mut self,
+        _buf: &Bytes,
+        _key: Range<usize>,
+        _val: Range<usize>,
+    ) -> Result<(), CodecError> {
+        Ok(())
+    }
 
-        // fill acl_token
-        if let Some(ref acl_token) = item.acl_token {
-            dst.put_u8(info::ACL_TOKEN_KEY_VALUE);
-            dst.put_u16(acl_token.len() as u16);
-            dst.put_slice(acl_token.as_bytes());
-        }
+    #[inline]
+    fn on_int_header(
+        &mut self,
+        _buf: &Bytes,
+        _key: u16,
+        _val: Range<usize>,
+    ) -> Result<(), CodecError> {
+        Ok(())
+    }
 
-        // write padding
-        let overflow = (dst.len() - 14 - zero_index) % 4;
-        let padding = (4 - overflow) % 4;
-        (0..padding).for_each(|_| dst.put_u8(0));
+    #[inline]
+    fn on_acl_token(&mut self, _buf: &Bytes, _val: Range<usize>) -> Result<(), CodecError> {
+        Ok(())
+    }
 
-        // fill header length
-        let header_size = dst.len() - zero_index;
-        let mut buf = &mut dst[zero_index + 12..zero_index + 12 + 2];
-        buf.put_u16(((header_size - 14) / 4).try_into().unwrap());
-        tracing::trace!(
-            "encode ttheader write header size: {}",
-            (header_size - 14) / 4
-        );
+    #[inline]
+    fn on_unknown_info(&mut self, _info_id: u8, _data: Bytes) -> Result<(), CodecError> {
+        Ok(())
+    }
 
-        // fill length
-        let size = dst.len() + item.payload_length as usize - zero_index;
-        let mut buf = &mut dst[zero_index..zero_index + 4];
-        buf.put_u32((size - 4) as u32);
-        tracing::trace!("encode ttheader write length size: {}", size - 4);
+    #[inline]
+    fn on_payload_crc32c(&mut self, _crc: u32, at: usize) -> Result<(), CodecError> {
+        self.at = Some(at);
         Ok(())
     }
 }
 
+/// Recompute the payload checksum of `frame`, a whole TTHeader frame with its
+/// length, if it carries one. For encoders that change the payload in place
+/// after the header is written.
+pub(crate) fn refresh_payload_crc32c(frame: &mut [u8]) -> Result<(), CodecError> {
+    if frame.len() < MIN_HEADER_LENGTH {
+        return Err(TTHeaderError::Truncated.into());
+    }
+    let total_length = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+    let header_length = u16::from_be_bytes([frame[12], frame[13]]) as usize * 4;
+    let payload_index = MIN_HEADER_LENGTH + header_length;
+    if payload_index > frame.len() {
+        return Err(TTHeaderError::InvalidHeaderLength.into());
+    }
+    let mut head = Bytes::copy_from_slice(&frame[4..payload_index]);
+    let opts = DecodeOptions {
+        passthrough_unknown: true,
+        ..DecodeOptions::new(TTHeaderDecoderConfig::DEFAULT)
+    };
+    let mut locator = Crc32cLocator::default();
+    parse_header(total_length, &mut head, &opts, &mut locator)?;
+    if let Some(at) = locator.at {
+        let crc = crc32c::crc32c(&frame[payload_index..]);
+        let crc_index = MIN_HEADER_LENGTH + at;
+        frame[crc_index..crc_index + 4].copy_from_slice(&crc.to_be_bytes());
+    }
+    Ok(())
+}
+
 pub struct TTHeaderPayload<T> {
     pub ttheader: TTHeader,
     pub payload: Option<T>,
@@ -376,11 +1713,72 @@ impl<T> TTHeaderPayload<T> {
 
 pub struct TTHeaderPayloadCodec<T> {
     inner: T,
+    opts: DecodeOptions,
+    checksum: bool,
 }
 
 impl<T> TTHeaderPayloadCodec<T> {
     pub fn new(inner: T) -> Self {
-        Self { inner }
+        Self::with_config(inner, TTHeaderDecoderConfig::DEFAULT)
+    }
+
+    pub fn with_config(inner: T, config: TTHeaderDecoderConfig) -> Self {
+        Self {
+            inner,
+            opts: DecodeOptions::new(config),
+            checksum: false,
+        }
+    }
+
+    /// Keep info sections with unknown ids in [`TTHeader::unknown_infos`]
+    /// instead of failing the decode.
+    pub fn passthrough_unknown(mut self, enabled: bool) -> Self {
+        self.opts.passthrough_unknown = enabled;
+        self
+    }
+
+    /// Reject headers whose padding is non-zero or longer than needed to
+    /// align the header region, see [`TTHeaderError::InvalidPadding`].
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.opts.strict = enabled;
+        self
+    }
+
+    /// Keep the encoded header in [`TTHeader::raw`] so it can be forwarded
+    /// with [`TTHeaderEncoder::encode_retained`].
+    pub fn retain_raw(mut self, enabled: bool) -> Self {
+        self.opts.retain_raw = enabled;
+        self
+    }
+
+    /// Append a CRC32C of the payload to encoded headers and verify it on
+    /// decode when present.
+    ///
+    /// The checksum lives in an info section that is not part of the
+    /// CloudWeGo spec, so both peers must enable it. A mismatch fails the
+    /// decode with [`TTHeaderError::ChecksumMismatch`]. When disabled, encoded
+    /// headers carry no checksum, even one kept from a decoded header.
+    pub fn checksum(mut self, enabled: bool) -> Self {
+        self.checksum = enabled;
+        self
+    }
+}
+
+impl<T: Decoder> TTHeaderPayloadCodec<T>
+where
+    T::Error: From<io::Error>,
+{
+    /// Decode every complete frame buffered in `src`, leaving a trailing
+    /// partial frame in place.
+    pub fn decode_all(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Vec<TTHeaderPayload<T::Item>>, T::Error> {
+        let mut items = Vec::new();
+        while let Decoded::Some(item) = self.decode(src)? {
+            items.push(item);
+        }
+        Ok(items)
     }
 }
 
@@ -406,16 +1804,36 @@ where
             src.advance(4);
 
             let mut item = Self::Item::new();
-            item.ttheader.decode_header(length, src)?;
-            match self.inner.decode(src) {
+            item.ttheader
+                .decode_header(length, src, &self.opts)
+                .map_err(io::Error::from)?;
+            // Bound the inner codec to this frame, pipelined frames may follow.
+            let mut payload = src.split_to(item.ttheader.payload_length as usize);
+            if let (true, Some(expected)) = (self.checksum, item.ttheader.payload_crc32c) {
+                let actual = crc32c::crc32c(&payload);
+                if actual != expected {
+                    return Err(io::Error::from(TTHeaderError::ChecksumMismatch {
+                        expected,
+                        actual,
+                    })
+                    .into());
+                }
+            }
+            match self.inner.decode(&mut payload) {
                 Ok(Decoded::Some(payload)) => item.payload = Some(payload),
                 Err(e) => return Err(e),
                 // we have already checked sufficient size, so it's err if Insufficient
-                _ => return Err(io::Error::new(io::ErrorKind::Other, "illegal payload").into()),
+                _ => {
+                    return Err(io::Error::from(CodecError::new(
+                        CodecErrorKind::InvalidData,
+                        "illegal payload",
+                    ))
+                    .into())
+                }
             };
             Ok(Decoded::Some(item))
         } else {
-            Err(io::Error::new(io::ErrorKind::Other, "illegal ttheader").into())
+            Err(io::Error::from(TTHeaderError::BadMagic).into())
         }
     }
 }
@@ -428,49 +1846,500 @@ impl<T, E: Encoder<T>> Encoder<TTHeaderPayload<T>> for TTHeaderPayloadCodec<E> {
         item: TTHeaderPayload<T>,
         dst: &mut bytes::BytesMut,
     ) -> Result<(), Self::Error> {
-        let zero_index = dst.len();
-        let mut ttheader_encoder = TTHeaderEncoder {};
-        ttheader_encoder.encode(item.ttheader, dst)?;
-        self.inner
-            .encode(item.payload.expect("payload must some"), dst)?;
-        // fill length
-        let size = dst.len() - zero_index;
-        let mut buf = &mut dst[zero_index..zero_index + 4];
-        buf.put_u32((size - 4) as u32);
-        tracing::trace!("encode ttheader write length size: {}", size - 4);
+        let payload = item.payload.ok_or_else(|| {
+            io::Error::from(CodecError::new(
+                CodecErrorKind::InvalidData,
+                "ttheader payload is missing",
+            ))
+        })?;
+        self.encode((item.ttheader, payload), dst)
+    }
+}
+
+/// Encode a header and a payload that is always present.
+impl<T, E: Encoder<T>> Encoder<(TTHeader, T)> for TTHeaderPayloadCodec<E> {
+    type Error = E::Error;
+
+    fn encode(
+        &mut self,
+        (mut ttheader, payload): (TTHeader, T),
+        dst: &mut bytes::BytesMut,
+    ) -> Result<(), Self::Error> {
+        if self.checksum {
+            // placeholder, filled once the payload is encoded
+            ttheader.payload_crc32c = Some(0);
+        } else {
+            // a decoded header may carry the checksum of another payload
+            ttheader.payload_crc32c = None;
+        }
+        // The frame length is only known once the payload is encoded, so it's
+        // filled once at the end.
+        let (zero_index, crc_index) = encode_header_unsized(&ttheader, dst);
+        let payload_index = dst.len();
+        self.inner.encode(payload, dst)?;
+        if let (true, Some(crc_index)) = (self.checksum, crc_index) {
+            let crc = crc32c::crc32c(&dst[payload_index..]);
+            let mut buf = &mut dst[crc_index..crc_index + 4];
+            buf.put_u32(crc);
+        }
+        fill_frame_length(dst, zero_index, dst.len());
         Ok(())
     }
 }
 
-#[derive(Default)]
-pub struct RawPayloadCodec;
+impl<T, E: PartsEncoder<T>> PartsEncoder<TTHeaderPayload<T>> for TTHeaderPayloadCodec<E>
+where
+    E::Error: From<io::Error>,
+{
+    fn encode_parts(
+        &mut self,
+        item: TTHeaderPayload<T>,
+        parts: &mut EncodeParts,
+    ) -> Result<(), Self::Error> {
+        let payload = item.payload.ok_or_else(|| {
+            io::Error::from(CodecError::new(
+                CodecErrorKind::InvalidData,
+                "ttheader payload is missing",
+            ))
+        })?;
+        self.encode_parts((item.ttheader, payload), parts)
+    }
+}
+
+/// The payload parts are emitted as is after a part holding the header.
+impl<T, E: PartsEncoder<T>> PartsEncoder<(TTHeader, T)> for TTHeaderPayloadCodec<E>
+where
+    E::Error: From<io::Error>,
+{
+    fn encode_parts(
+        &mut self,
+        (mut ttheader, payload): (TTHeader, T),
+        parts: &mut EncodeParts,
+    ) -> Result<(), Self::Error> {
+        // placeholder, replaced by the header once the payload size is known
+        let header_index = parts.len();
+        parts.push(Bytes::new());
+        self.inner.encode_parts(payload, parts)?;
+        let payload_length = parts_len(parts, header_index + 1);
+        if self.checksum {
+            // placeholder, so the crc section is counted
+            ttheader.payload_crc32c = Some(0);
+        } else {
+            // a decoded header may carry the checksum of another payload
+            ttheader.payload_crc32c = None;
+        }
+        let encoded_len = ttheader.encoded_len();
+        if encoded_len - 4 + payload_length > u32::MAX as usize {
+            return Err(io::Error::from(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                "ttheader frame too large",
+            ))
+            .into());
+        }
+        ttheader.payload_length = payload_length as u32;
+        if self.checksum {
+            let crc = parts[header_index + 1..]
+                .iter()
+                .fold(0, |crc, part| crc32c::crc32c_append(crc, part));
+            ttheader.payload_crc32c = Some(crc);
+        }
+        let mut dst = BytesMut::with_capacity(encoded_len);
+        encode_header(&ttheader, &mut dst);
+        parts[header_index] = dst.freeze();
+        Ok(())
+    }
+}
+
+/// One step of [`TTHeaderStagedDecoder`].
+pub enum TTHeaderStage<T> {
+    /// The header is parsed, its `payload_length` bytes of payload follow.
+    Header(Box<TTHeader>),
+    /// The payload of the preceding header.
+    Payload(T),
+}
+
+/// Decodes a TTHeader frame in two steps, yielding the header as soon as the
+/// header region is buffered and the payload once it is complete.
+///
+/// This lets a router pick a destination before the payload arrives. Every
+/// [`TTHeaderStage::Header`] is followed by exactly one
+/// [`TTHeaderStage::Payload`].
+pub struct TTHeaderStagedDecoder<T> {
+    inner: T,
+    opts: DecodeOptions,
+    // payload length of the header yielded last, until its payload is decoded
+    pending_payload: Option<usize>,
+}
+
+impl<T> TTHeaderStagedDecoder<T> {
+    pub fn new(inner: T) -> Self {
+        Self::with_config(inner, TTHeaderDecoderConfig::DEFAULT)
+    }
+
+    pub fn with_config(inner: T, config: TTHeaderDecoderConfig) -> Self {
+        Self {
+            inner,
+            opts: DecodeOptions::new(config),
+            pending_payload: None,
+        }
+    }
+
+    /// Keep info sections with unknown ids in [`TTHeader::unknown_infos`]
+    /// instead of failing the decode.
+    pub fn passthrough_unknown(mut self, enabled: bool) -> Self {
+        self.opts.passthrough_unknown = enabled;
+        self
+    }
+
+    /// Reject headers whose padding is non-zero or longer than needed to
+    /// align the header region, see [`TTHeaderError::InvalidPadding`].
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.opts.strict = enabled;
+        self
+    }
+
+    /// Keep the encoded header in [`TTHeader::raw`] so it can be forwarded
+    /// with [`TTHeaderEncoder::encode_retained`].
+    pub fn retain_raw(mut self, enabled: bool) -> Self {
+        self.opts.retain_raw = enabled;
+        self
+    }
+
+    /// Payload length still expected after a yielded header.
+    #[inline]
+    pub fn pending_payload(&self) -> Option<usize> {
+        self.pending_payload
+    }
+}
+
+impl<T: Decoder> Decoder for TTHeaderStagedDecoder<T>
+where
+    T::Error: From<io::Error>,
+{
+    type Item = TTHeaderStage<T::Item>;
+    type Error = T::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        if let Some(payload_length) = self.pending_payload {
+            if src.len() < payload_length {
+                return Ok(Decoded::InsufficientAtLeast(payload_length));
+            }
+            let mut payload = src.split_to(payload_length);
+            self.pending_payload = None;
+            return match self.inner.decode(&mut payload)? {
+                Decoded::Some(payload) => Ok(Decoded::Some(TTHeaderStage::Payload(payload))),
+                // the whole payload is buffered, so it's err if Insufficient
+                _ => Err(io::Error::from(CodecError::new(
+                    CodecErrorKind::InvalidData,
+                    "illegal payload",
+                ))
+                .into()),
+            };
+        }
+
+        let length = match decode_frame_length(src, &self.opts.config).map_err(io::Error::from)? {
+            Decoded::Some(length) => length,
+            Decoded::Insufficient => return Ok(Decoded::Insufficient),
+            Decoded::InsufficientAtLeast(n) => return Ok(Decoded::InsufficientAtLeast(n)),
+        };
+        let mut ttheader = TTHeader::new();
+        ttheader
+            .decode_header(length, src, &self.opts)
+            .map_err(io::Error::from)?;
+        self.pending_payload = Some(ttheader.payload_length as usize);
+        Ok(Decoded::Some(TTHeaderStage::Header(Box::new(ttheader))))
+    }
+}
+
+/// Item of [`TTHeaderStreamingDecoder`].
+pub enum TTHeaderChunk<T> {
+    /// A frame within the threshold, decoded whole.
+    Frame(Box<TTHeaderPayload<T>>),
+    /// Header of a frame above the threshold, its `total_len` payload bytes
+    /// follow as [`TTHeaderChunk::Chunk`]s.
+    FrameStart {
+        header: Box<TTHeader>,
+        total_len: usize,
+    },
+    /// Raw bytes of the payload announced by the last `FrameStart`, `last` is
+    /// set on the chunk completing it.
+    Chunk { data: Bytes, last: bool },
+}
+
+/// Decoder bounding the memory used by oversized frames.
+///
+/// Frames up to `threshold` bytes are decoded whole by the wrapped codec. For
+/// larger ones the header is yielded as soon as it is buffered, and the payload
+/// is handed out in chunks as it arrives, undecoded. When the codec checks
+/// checksums, the one of a streamed payload is verified on its last chunk.
+pub struct TTHeaderStreamingDecoder<T> {
+    codec: TTHeaderPayloadCodec<T>,
+    threshold: usize,
+    // payload bytes of the streamed frame not yielded yet
+    remaining: usize,
+    // expected and running crc of the streamed payload
+    crc: Option<(u32, u32)>,
+}
+
+impl<T> TTHeaderStreamingDecoder<T> {
+    pub fn new(codec: TTHeaderPayloadCodec<T>, threshold: usize) -> Self {
+        Self {
+            codec,
+            threshold,
+            remaining: 0,
+            crc: None,
+        }
+    }
+
+    /// Payload bytes of the frame being streamed that are still to come.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+impl<T: Decoder> Decoder for TTHeaderStreamingDecoder<T>
+where
+    T::Error: From<io::Error>,
+{
+    type Item = TTHeaderChunk<T::Item>;
+    type Error = T::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
+        if self.remaining > 0 {
+            if src.is_empty() {
+                return Ok(Decoded::InsufficientAtLeast(1));
+            }
+            let data = src.split_to(src.len().min(self.remaining)).freeze();
+            self.remaining -= data.len();
+            let last = self.remaining == 0;
+            if let Some((expected, crc)) = self.crc.as_mut() {
+                *crc = crc32c::crc32c_append(*crc, &data);
+                if last && *crc != *expected {
+                    let (expected, actual) = (*expected, *crc);
+                    self.crc = None;
+                    return Err(io::Error::from(TTHeaderError::ChecksumMismatch {
+                        expected,
+                        actual,
+                    })
+                    .into());
+                }
+            }
+            return Ok(Decoded::Some(TTHeaderChunk::Chunk { data, last }));
+        }
+
+        if src.len() >= 4
+            && u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize > self.threshold
+        {
+            let decoded =
+                decode_frame_length(src, &self.codec.opts.config).map_err(io::Error::from)?;
+            let length = match decoded {
+                Decoded::Some(length) => length,
+                Decoded::Insufficient => return Ok(Decoded::Insufficient),
+                Decoded::InsufficientAtLeast(n) => return Ok(Decoded::InsufficientAtLeast(n)),
+            };
+            let mut header = TTHeader::new();
+            header
+                .decode_header(length, src, &self.codec.opts)
+                .map_err(io::Error::from)?;
+            let total_len = header.payload_length as usize;
+            self.remaining = total_len;
+            self.crc = match (self.codec.checksum, header.payload_crc32c) {
+                (true, Some(expected)) => Some((expected, 0)),
+                _ => None,
+            };
+            return Ok(Decoded::Some(TTHeaderChunk::FrameStart {
+                header: Box::new(header),
+                total_len,
+            }));
+        }
+
+        match self.codec.decode(src)? {
+            Decoded::Some(item) => Ok(Decoded::Some(TTHeaderChunk::Frame(Box::new(item)))),
+            Decoded::Insufficient => Ok(Decoded::Insufficient),
+            Decoded::InsufficientAtLeast(n) => Ok(Decoded::InsufficientAtLeast(n)),
+        }
+    }
+}
+
+/// Read one TTHeader from `io`, for users not driving a `Framed`.
+///
+/// `buf` carries bytes over between calls, whatever follows the header (the
+/// payload first) is left in it.
+pub async fn read_ttheader<T: AsyncReadRent>(
+    io: T,
+    buf: &mut BytesMut,
+) -> Result<TTHeader, CodecError> {
+    read_decoded(io, buf, &mut TTHeaderDecoder::new(), None).await
+}
+
+/// [`read_ttheader`] failing with [`io::ErrorKind::TimedOut`] if the header is
+/// not read by `deadline`, so a peer that sent a partial header doesn't hold
+/// the connection forever. See [`read_more_at_least_until`].
+pub async fn read_ttheader_until<T: AsyncReadRent>(
+    io: T,
+    buf: &mut BytesMut,
+    deadline: Instant,
+) -> Result<TTHeader, CodecError> {
+    read_decoded(io, buf, &mut TTHeaderDecoder::new(), Some(deadline)).await
+}
+
+/// Read one TTHeader frame from `io` and decode it with `codec`.
+///
+/// `buf` carries bytes over between calls, pipelined frames are left in it.
+pub async fn read_ttheader_payload<T: AsyncReadRent, D: Decoder>(
+    io: T,
+    buf: &mut BytesMut,
+    codec: &mut TTHeaderPayloadCodec<D>,
+) -> Result<TTHeaderPayload<D::Item>, D::Error>
+where
+    D::Error: From<io::Error>,
+{
+    read_decoded(io, buf, codec, None).await
+}
+
+async fn read_decoded<T: AsyncReadRent, D: Decoder>(
+    mut io: T,
+    buf: &mut BytesMut,
+    decoder: &mut D,
+    deadline: Option<Instant>,
+) -> Result<D::Item, D::Error>
+where
+    D::Error: From<io::Error>,
+{
+    loop {
+        let to_read = match decoder.decode(buf)? {
+            Decoded::Some(item) => return Ok(item),
+            Decoded::Insufficient => 1,
+            Decoded::InsufficientAtLeast(n) => n.saturating_sub(buf.len()).max(1),
+        };
+        match deadline {
+            Some(deadline) => read_more_at_least_until(&mut io, buf, to_read, deadline).await?,
+            None => read_more_at_least(&mut io, buf, to_read).await?,
+        }
+    }
+}
+
+/// Codec passing payloads through as raw bytes.
+///
+/// By default a decode takes everything buffered, which is only right when the
+/// buffer is already bounded to one payload, as the frame codecs do.
+/// [`RawPayloadCodec::exact`] takes a fixed number of bytes instead, and
+/// [`RawPayloadCodec::max_len`] rejects payloads above a size.
+#[derive(Clone, Copy, Debug)]
+pub struct RawPayloadCodec {
+    exact_len: Option<usize>,
+    max_len: usize,
+}
+
+impl Default for RawPayloadCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl RawPayloadCodec {
     pub const fn new() -> Self {
-        Self
+        Self {
+            exact_len: None,
+            max_len: usize::MAX,
+        }
+    }
+
+    /// Consume exactly `len` bytes per payload, waiting until they are
+    /// buffered.
+    pub const fn exact(len: usize) -> Self {
+        Self {
+            exact_len: Some(len),
+            max_len: usize::MAX,
+        }
+    }
+
+    /// Fail payloads longer than `max_len` bytes, on decode and encode.
+    pub const fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Change the length of the next payloads, e.g. once a header declared it.
+    #[inline]
+    pub fn set_exact_len(&mut self, exact_len: Option<usize>) {
+        self.exact_len = exact_len;
+    }
+
+    #[inline]
+    fn check_len(&self, len: usize) -> Result<(), CodecError> {
+        if len > self.max_len {
+            return Err(CodecError::new(
+                CodecErrorKind::SizeLimitExceeded,
+                format!("payload too large: {len} bytes, max {}", self.max_len),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_encode(&self, item: &[u8]) -> Result<(), CodecError> {
+        if let Some(len) = self.exact_len {
+            if item.len() != len {
+                return Err(CodecError::new(
+                    CodecErrorKind::LengthMismatch {
+                        expected: len,
+                        actual: item.len(),
+                    },
+                    format!("payload must be {len} bytes, got {}", item.len()),
+                ));
+            }
+        }
+        self.check_len(item.len())
     }
 }
 
 impl Decoder for RawPayloadCodec {
     type Item = bytes::Bytes;
 
-    type Error = io::Error;
+    type Error = CodecError;
 
     fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Decoded<Self::Item>, Self::Error> {
-        Ok(Decoded::Some(bytes::Bytes::from(src.split())))
+        match self.exact_len {
+            Some(len) => {
+                self.check_len(len)?;
+                if src.len() < len {
+                    return Ok(Decoded::InsufficientAtLeast(len));
+                }
+                Ok(Decoded::Some(src.split_to(len).freeze()))
+            }
+            None => {
+                self.check_len(src.len())?;
+                Ok(Decoded::Some(bytes::Bytes::from(src.split())))
+            }
+        }
     }
 }
 
 impl Encoder<bytes::Bytes> for RawPayloadCodec {
-    type Error = io::Error;
+    type Error = CodecError;
 
     fn encode(&mut self, item: bytes::Bytes, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
+        self.check_encode(&item)?;
         dst.reserve(item.len());
         dst.extend_from_slice(&item);
         Ok(())
     }
 }
 
+impl PartsEncoder<bytes::Bytes> for RawPayloadCodec {
+    #[inline]
+    fn encode_parts(
+        &mut self,
+        item: bytes::Bytes,
+        parts: &mut EncodeParts,
+    ) -> Result<(), CodecError> {
+        self.check_encode(&item)?;
+        parts.push(item);
+        Ok(())
+    }
+}
+
 /// 4-bytes length + 2-bytes magic
 /// https://www.cloudwego.io/docs/kitex/reference/transport_protocol_ttheader/
 const HEADER_DETECT_LENGTH: usize = 6;
@@ -483,16 +2352,83 @@ mod info {
     pub const INFO_KEY_VALUE: u8 = 0x01;
     pub const INFO_INT_KEY_VALUE: u8 = 0x10;
     pub const ACL_TOKEN_KEY_VALUE: u8 = 0x11;
+    // Not part of the CloudWeGo spec, peers must opt in to understand it.
+    pub const PAYLOAD_CRC32C: u8 = 0x20;
+}
+
+bitflags::bitflags! {
+    /// Flags field of a TTHeader.
+    ///
+    /// Unknown bits are kept as is, so a decoded header re-encodes to the same
+    /// value.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct TTHeaderFlags: u16 {
+        const SUPPORT_OUT_OF_ORDER = 0x01;
+        const STREAMING = 0x02;
+        const DUPLEX_REVERSE = 0x08;
+        const SASL = 0x10;
+
+        const _ = !0;
+    }
+}
+
+impl From<u16> for TTHeaderFlags {
+    #[inline]
+    fn from(bits: u16) -> Self {
+        Self::from_bits_retain(bits)
+    }
+}
+
+impl From<TTHeaderFlags> for u16 {
+    #[inline]
+    fn from(flags: TTHeaderFlags) -> Self {
+        flags.bits()
+    }
 }
 
-#[derive(TryFromPrimitive, Clone, Copy, Default)]
-#[repr(u8)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ProtocolId {
     #[default]
-    Binary = 0,
-    Compact = 2,   // Apache Thrift compact protocol
-    CompactV2 = 3, // fbthrift compact protocol
-    Protobuf = 4,
+    Binary,
+    Compact,   // Apache Thrift compact protocol
+    CompactV2, // fbthrift compact protocol
+    Protobuf,
+    ThriftStruct,   // TTHeader streaming, thrift struct without message header
+    ProtobufStruct, // TTHeader streaming, protobuf struct without message header
+    // id this crate doesn't know, kept so that it survives re-encoding
+    Unknown(u8),
+}
+
+impl From<u8> for ProtocolId {
+    #[inline]
+    fn from(id: u8) -> Self {
+        match id {
+            0x00 => ProtocolId::Binary,
+            0x02 => ProtocolId::Compact,
+            0x03 => ProtocolId::CompactV2,
+            0x04 => ProtocolId::Protobuf,
+            0x10 => ProtocolId::ThriftStruct,
+            0x11 => ProtocolId::ProtobufStruct,
+            id => ProtocolId::Unknown(id),
+        }
+    }
+}
+
+impl From<ProtocolId> for u8 {
+    #[inline]
+    fn from(id: ProtocolId) -> Self {
+        match id {
+            ProtocolId::Binary => 0x00,
+            ProtocolId::Compact => 0x02,
+            ProtocolId::CompactV2 => 0x03,
+            ProtocolId::Protobuf => 0x04,
+            ProtocolId::ThriftStruct => 0x10,
+            ProtocolId::ProtobufStruct => 0x11,
+            ProtocolId::Unknown(id) => id,
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy, TryFromPrimitive, Debug)]
@@ -527,4 +2463,119 @@ pub enum IntMetaKey {
 
 impl IntMetaKey {
     const INDEX_TABLE_SIZE: usize = Self::ClusterShardId as usize + 1;
+
+    /// Whether the meta only makes sense on a request, such as routing and
+    /// timeout metas, and must not be echoed back in a reply.
+    pub const fn is_request_only(self) -> bool {
+        matches!(
+            self,
+            IntMetaKey::FromService
+                | IntMetaKey::FromCluster
+                | IntMetaKey::FromIdc
+                | IntMetaKey::FromMethod
+                | IntMetaKey::ToService
+                | IntMetaKey::ToMethod
+                | IntMetaKey::ToCluster
+                | IntMetaKey::ToIdc
+                | IntMetaKey::DestAddress
+                | IntMetaKey::RPCTimeoutMs
+                | IntMetaKey::ConnTimeoutMs
+                | IntMetaKey::RingHashKey
+                | IntMetaKey::RawRingHashKey
+                | IntMetaKey::LBType
+                | IntMetaKey::ClusterShardId
+                | IntMetaKey::MsgType
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frame length 10, too short for its 4-byte header region, followed by
+    // more data.
+    const SHORT_FRAME: &[u8] = &[
+        0, 0, 0, 10, 0x10, 0x00, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8,
+    ];
+
+    fn assert_invalid_header_length(err: CodecError) {
+        assert_eq!(
+            TTHeaderError::from_codec_error(&err),
+            Some(&TTHeaderError::InvalidHeaderLength)
+        );
+    }
+
+    #[test]
+    fn header_overrunning_frame() {
+        let mut src = BytesMut::from(SHORT_FRAME);
+        let mut codec = TTHeaderPayloadCodec::new(RawPayloadCodec::new());
+        assert_invalid_header_length(codec.decode(&mut src).err().unwrap());
+
+        let mut src = BytesMut::from(SHORT_FRAME);
+        assert_invalid_header_length(TTHeaderDecoder::new().decode(&mut src).err().unwrap());
+
+        let mut src = BytesMut::from(SHORT_FRAME);
+        let mut staged = TTHeaderStagedDecoder::new(RawPayloadCodec::new());
+        assert_invalid_header_length(staged.decode(&mut src).err().unwrap());
+
+        let mut src = BytesMut::from(SHORT_FRAME);
+        let codec = TTHeaderPayloadCodec::new(RawPayloadCodec::new());
+        let mut streaming = TTHeaderStreamingDecoder::new(codec, 0);
+        assert_invalid_header_length(streaming.decode(&mut src).err().unwrap());
+    }
+
+    #[test]
+    fn ext_and_empty_headers_round_trip() {
+        let mut header = TTHeader::new();
+        header.seq_id = 7;
+        header.set_int_header(0x1000, HeaderValue::Str("ext".into()));
+        header.set_int_header(0x1001, HeaderValue::Str("".into()));
+        header.str_headers.insert("key".into(), "".into());
+        header.str_headers.insert("other".into(), "value".into());
+
+        let mut dst = BytesMut::new();
+        TTHeaderEncoder::new()
+            .encode(header.clone(), &mut dst)
+            .unwrap();
+        assert_eq!(dst.len(), header.encoded_len());
+
+        let decoded = match TTHeaderDecoder::new().decode(&mut dst).unwrap() {
+            Decoded::Some(decoded) => decoded,
+            _ => panic!("frame is complete"),
+        };
+        assert!(dst.is_empty());
+        assert_eq!(decoded.seq_id, 7);
+        assert_eq!(decoded.int_headers_ext, header.int_headers_ext);
+        assert_eq!(decoded.str_headers, header.str_headers);
+    }
+
+    #[test]
+    fn reencoded_header_drops_stale_checksum() {
+        let mut checked = TTHeaderPayloadCodec::new(RawPayloadCodec::new()).checksum(true);
+        let mut plain = TTHeaderPayloadCodec::new(RawPayloadCodec::new());
+        let mut dst = BytesMut::new();
+        let item = (TTHeader::new(), Bytes::from_static(b"original"));
+        checked.encode(item, &mut dst).unwrap();
+        let header = match checked.decode(&mut dst).unwrap() {
+            Decoded::Some(decoded) => decoded.ttheader,
+            _ => panic!("frame is complete"),
+        };
+        assert!(header.payload_crc32c.is_some());
+
+        let item = (header.clone(), Bytes::from_static(b"rewritten"));
+        plain.encode(item, &mut dst).unwrap();
+        let decoded = match checked.decode(&mut dst).unwrap() {
+            Decoded::Some(decoded) => decoded,
+            _ => panic!("frame is complete"),
+        };
+        assert_eq!(decoded.ttheader.payload_crc32c, None);
+        assert_eq!(decoded.payload.unwrap(), "rewritten");
+
+        let mut parts = EncodeParts::new();
+        let item = (header, Bytes::from_static(b"rewritten"));
+        plain.encode_parts(item, &mut parts).unwrap();
+        let mut dst = BytesMut::from(&parts.concat()[..]);
+        assert!(checked.decode(&mut dst).is_ok());
+    }
 }
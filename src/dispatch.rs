@@ -0,0 +1,260 @@
+//! Protocol selected at runtime.
+//!
+//! [`ProtocolDispatch`] implements the protocol traits by matching on the
+//! protocol it holds, so a handler written against the traits serves every
+//! protocol a TTHeader `protocol_id` may select.
+
+use std::io::Cursor;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{
+    binary::{TBinaryProtocol, TBinaryReader, TBinaryWriter},
+    codec::ttheader::ProtocolId,
+    protocol::{
+        ProtocolConfig, TAsyncInputProtocol, TAsyncOutputProtocol, TAsyncSkipProtocol,
+        TInputProtocol, TOutputProtocol,
+    },
+    thrift::{
+        TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TSetIdentifier,
+        TStructIdentifier, TType,
+    },
+    CodecError, CodecErrorKind,
+};
+
+/// Reader or writer of one of the supported protocols.
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ProtocolDispatch<B> {
+    Binary(B),
+}
+
+#[inline]
+fn unsupported(protocol_id: ProtocolId) -> CodecError {
+    CodecError::new(
+        CodecErrorKind::UnsupportedProtocol,
+        format!("unsupported protocol {protocol_id:?}"),
+    )
+}
+
+impl<'x> ProtocolDispatch<TBinaryReader<'x>> {
+    /// Reader of `data` encoded with `protocol_id`.
+    pub fn reader(protocol_id: ProtocolId, data: &'x [u8]) -> Result<Self, CodecError> {
+        match protocol_id {
+            ProtocolId::Binary => Ok(Self::Binary(TBinaryReader::new(Cursor::new(data)))),
+            _ => Err(unsupported(protocol_id)),
+        }
+    }
+}
+
+impl<'a> ProtocolDispatch<TBinaryWriter<'a>> {
+    /// Writer of `protocol_id` appending to `buf`.
+    pub fn writer(protocol_id: ProtocolId, buf: &'a mut BytesMut) -> Result<Self, CodecError> {
+        match protocol_id {
+            ProtocolId::Binary => Ok(Self::Binary(TBinaryWriter::new(buf))),
+            _ => Err(unsupported(protocol_id)),
+        }
+    }
+}
+
+impl<T, A> ProtocolDispatch<TBinaryProtocol<T, A>> {
+    /// Apply the limits and checks of `config` to the held protocol.
+    #[inline]
+    pub fn with_config(self, config: ProtocolConfig) -> Self {
+        match self {
+            Self::Binary(p) => Self::Binary(p.with_config(config)),
+        }
+    }
+}
+
+impl<B> ProtocolDispatch<B> {
+    /// The TTHeader `protocol_id` of the held protocol.
+    #[inline]
+    pub fn protocol_id(&self) -> ProtocolId {
+        match self {
+            Self::Binary(_) => ProtocolId::Binary,
+        }
+    }
+}
+
+macro_rules! impl_dispatch_fn {
+    ($($(#[$meta: meta])* $fname: ident($($arg: ident: $arg_type: ty),*) -> $out: ty;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            fn $fname(&mut self $(,$arg: $arg_type)*) -> $out {
+                match self {
+                    Self::Binary(p) => p.$fname($($arg),*),
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_dispatch_async_fn {
+    ($($(#[$meta: meta])* $fname: ident($($arg: ident: $arg_type: ty),*) -> $out: ty;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            async fn $fname(&mut self $(,$arg: $arg_type)*) -> Result<$out, CodecError> {
+                match self {
+                    Self::Binary(p) => p.$fname($($arg),*).await,
+                }
+            }
+        )*
+    };
+}
+
+impl<'x, B: TInputProtocol<'x>> TInputProtocol<'x> for ProtocolDispatch<B> {
+    type Buf<'b> = B::Buf<'b>
+    where
+        Self: 'b;
+
+    impl_dispatch_fn! {
+        read_message_begin() -> Result<TMessageIdentifier<'_>, CodecError>;
+        read_message_end() -> Result<(), CodecError>;
+        read_struct_begin() -> Result<TStructIdentifier, CodecError>;
+        read_struct_end() -> Result<(), CodecError>;
+        read_field_begin() -> Result<TFieldIdentifier, CodecError>;
+        read_field_end() -> Result<(), CodecError>;
+        read_list_begin() -> Result<TListIdentifier, CodecError>;
+        read_list_end() -> Result<(), CodecError>;
+        read_set_begin() -> Result<TSetIdentifier, CodecError>;
+        read_set_end() -> Result<(), CodecError>;
+        read_map_begin() -> Result<TMapIdentifier, CodecError>;
+        read_map_end() -> Result<(), CodecError>;
+        read_byte() -> Result<u8, CodecError>;
+        read_bool() -> Result<bool, CodecError>;
+        read_i8() -> Result<i8, CodecError>;
+        read_i16() -> Result<i16, CodecError>;
+        read_i32() -> Result<i32, CodecError>;
+        read_i64() -> Result<i64, CodecError>;
+        read_double() -> Result<f64, CodecError>;
+        #[cfg(feature = "float")]
+        read_float() -> Result<f32, CodecError>;
+        read_uuid() -> Result<[u8; 16], CodecError>;
+        read_bytes() -> Result<&'x [u8], CodecError>;
+        read_string() -> Result<&'x str, CodecError>;
+        skip_field(ttype: TType) -> Result<(), CodecError>;
+        skip_message() -> Result<(), CodecError>;
+        read_bytes_owned() -> Result<Bytes, CodecError>;
+    }
+
+    #[inline]
+    fn buf<'a>(&'a mut self) -> &'a mut Self::Buf<'x>
+    where
+        'x: 'a,
+    {
+        match self {
+            Self::Binary(p) => p.buf(),
+        }
+    }
+}
+
+impl<B: TOutputProtocol> TOutputProtocol for ProtocolDispatch<B> {
+    type Buf = B::Buf;
+
+    impl_dispatch_fn! {
+        write_message_begin(identifier: &TMessageIdentifier) -> ();
+        write_message_end() -> ();
+        write_struct_begin(identifier: &TStructIdentifier) -> ();
+        write_struct_end() -> ();
+        write_field_begin(field_type: TType, id: i16) -> ();
+        write_field_end() -> ();
+        write_field_stop() -> ();
+        write_list_begin(identifier: &TListIdentifier) -> ();
+        write_list_end(len: usize) -> ();
+        write_set_begin(identifier: &TSetIdentifier) -> ();
+        write_set_end(len: usize) -> ();
+        write_map_begin(identifier: &TMapIdentifier) -> ();
+        write_map_end(len: usize) -> ();
+        write_byte(b: u8) -> ();
+        write_bool(b: bool) -> ();
+        write_i8(i: i8) -> ();
+        write_i16(i: i16) -> ();
+        write_i32(i: i32) -> ();
+        write_i64(i: i64) -> ();
+        write_double(d: f64) -> ();
+        #[cfg(feature = "float")]
+        write_float(f: f32) -> ();
+        write_uuid(u: [u8; 16]) -> ();
+        write_bytes(b: &[u8]) -> ();
+        write_string(s: &str) -> ();
+        write_bytes_owned(b: Bytes) -> ();
+        flush() -> ();
+        buf() -> &mut Self::Buf;
+    }
+}
+
+impl<B: TAsyncInputProtocol> TAsyncInputProtocol for ProtocolDispatch<B> {
+    impl_dispatch_async_fn! {
+        read_message_begin() -> TMessageIdentifier<'static>;
+        read_message_end() -> ();
+        read_struct_begin() -> TStructIdentifier;
+        read_struct_end() -> ();
+        read_field_begin() -> TFieldIdentifier;
+        read_field_end() -> ();
+        read_list_begin() -> TListIdentifier;
+        read_list_end() -> ();
+        read_set_begin() -> TSetIdentifier;
+        read_set_end() -> ();
+        read_map_begin() -> TMapIdentifier;
+        read_map_end() -> ();
+        read_byte() -> u8;
+        read_bool() -> bool;
+        read_i8() -> i8;
+        read_i16() -> i16;
+        read_i32() -> i32;
+        read_i64() -> i64;
+        read_double() -> f64;
+        #[cfg(feature = "float")]
+        read_float() -> f32;
+        read_uuid() -> [u8; 16];
+        read_bytes() -> Bytes;
+        read_string() -> Bytes;
+    }
+}
+
+impl<B: TAsyncSkipProtocol> TAsyncSkipProtocol for ProtocolDispatch<B> {
+    impl_dispatch_async_fn! {
+        skip_message() -> ();
+        skip_field(ttype: TType) -> ();
+        skip_until_field(id: i16) -> Option<TType>;
+        skip_remaining() -> ();
+    }
+}
+
+impl<B: TAsyncOutputProtocol> TAsyncOutputProtocol for ProtocolDispatch<B> {
+    impl_dispatch_fn! {
+        write_message_begin(identifier: &TMessageIdentifier) -> ();
+        write_message_end() -> ();
+        write_struct_begin(identifier: &TStructIdentifier) -> ();
+        write_struct_end() -> ();
+        write_field_begin(field_type: TType, id: i16) -> ();
+        write_field_end() -> ();
+        write_field_stop() -> ();
+        write_list_begin(identifier: &TListIdentifier) -> ();
+        write_list_end(len: usize) -> ();
+        write_set_begin(identifier: &TSetIdentifier) -> ();
+        write_set_end(len: usize) -> ();
+        write_map_begin(identifier: &TMapIdentifier) -> ();
+        write_map_end(len: usize) -> ();
+        write_byte(b: u8) -> ();
+        write_bool(b: bool) -> ();
+        write_i8(i: i8) -> ();
+        write_i16(i: i16) -> ();
+        write_i32(i: i32) -> ();
+        write_i64(i: i64) -> ();
+        write_double(d: f64) -> ();
+        #[cfg(feature = "float")]
+        write_float(f: f32) -> ();
+        write_uuid(u: [u8; 16]) -> ();
+        write_bytes(b: &[u8]) -> ();
+        write_string(s: &str) -> ();
+        write_bytes_owned(b: Bytes) -> ();
+    }
+
+    impl_dispatch_async_fn! {
+        flush() -> ();
+    }
+}
@@ -0,0 +1,144 @@
+//! Schema-less transcoding of binary encoded thrift into JSON.
+//!
+//! Without an IDL only wire types are known, so structs become objects keyed by
+//! field id and binaries become strings when they are valid UTF-8 (an array of
+//! bytes otherwise). Maps with scalar keys become objects, other maps become
+//! arrays of `[key, value]` pairs.
+
+use std::io::Cursor;
+
+use serde_json::{Map, Value};
+
+use crate::{
+    binary::TBinaryReader,
+    protocol::TInputProtocol,
+    thrift::{TMessageType, TType},
+    CodecError, CodecErrorKind,
+};
+
+const MAX_DEPTH: usize = 64;
+
+/// Dump a binary encoded message (without framing) as JSON.
+pub fn dump_message(data: &[u8]) -> Result<Value, CodecError> {
+    let mut prot = TBinaryReader::new(Cursor::new(data));
+    let ident = prot.read_message_begin()?;
+    let message_type = match ident.message_type {
+        TMessageType::Call => "call",
+        TMessageType::Reply => "reply",
+        TMessageType::Exception => "exception",
+        TMessageType::OneWay => "oneway",
+    };
+    let mut out = Map::new();
+    out.insert("name".into(), ident.name.as_str().into());
+    out.insert("type".into(), message_type.into());
+    out.insert("seq_id".into(), ident.sequence_number.into());
+    out.insert("body".into(), read_value(&mut prot, TType::Struct, 0)?);
+    prot.read_message_end()?;
+    Ok(Value::Object(out))
+}
+
+/// Dump a binary encoded struct as JSON.
+pub fn dump_struct(data: &[u8]) -> Result<Value, CodecError> {
+    let mut prot = TBinaryReader::new(Cursor::new(data));
+    read_value(&mut prot, TType::Struct, 0)
+}
+
+fn read_value<'x, P: TInputProtocol<'x>>(
+    prot: &mut P,
+    ttype: TType,
+    depth: usize,
+) -> Result<Value, CodecError> {
+    if depth >= MAX_DEPTH {
+        return Err(CodecError::new(
+            CodecErrorKind::DepthLimit,
+            "exceeded max depth while dumping",
+        ));
+    }
+    let value = match ttype {
+        TType::Bool => prot.read_bool()?.into(),
+        TType::I8 => prot.read_i8()?.into(),
+        TType::I16 => prot.read_i16()?.into(),
+        TType::I32 => prot.read_i32()?.into(),
+        TType::I64 => prot.read_i64()?.into(),
+        TType::Double => prot.read_double()?.into(),
+        #[cfg(feature = "float")]
+        TType::Float => prot.read_float()?.into(),
+        TType::Binary => {
+            let data = prot.read_bytes()?;
+            match std::str::from_utf8(data) {
+                Ok(s) => s.into(),
+                Err(_) => data.into(),
+            }
+        }
+        TType::Uuid => prot.read_tuuid()?.hyphenated().into(),
+        TType::Struct => {
+            let mut out = Map::new();
+            prot.read_struct_begin()?;
+            loop {
+                let field = prot.read_field_begin()?;
+                if field.field_type == TType::Stop {
+                    break;
+                }
+                let value = read_value(prot, field.field_type, depth + 1)?;
+                out.insert(field.id.unwrap_or_default().to_string(), value);
+                prot.read_field_end()?;
+            }
+            prot.read_struct_end()?;
+            Value::Object(out)
+        }
+        TType::List | TType::Set => {
+            let (element_type, size) = if ttype == TType::List {
+                let ident = prot.read_list_begin()?;
+                (ident.element_type, ident.size)
+            } else {
+                let ident = prot.read_set_begin()?;
+                (ident.element_type, ident.size)
+            };
+            let mut out = Vec::new();
+            for _ in 0..size {
+                out.push(read_value(prot, element_type, depth + 1)?);
+            }
+            if ttype == TType::List {
+                prot.read_list_end()?;
+            } else {
+                prot.read_set_end()?;
+            }
+            Value::Array(out)
+        }
+        TType::Map => {
+            let ident = prot.read_map_begin()?;
+            let scalar_key = !matches!(
+                ident.key_type,
+                TType::Struct | TType::Map | TType::Set | TType::List
+            );
+            let mut object = Map::new();
+            let mut pairs = Vec::new();
+            for _ in 0..ident.size {
+                let key = read_value(prot, ident.key_type, depth + 1)?;
+                let value = read_value(prot, ident.value_type, depth + 1)?;
+                if scalar_key {
+                    let key = match key {
+                        Value::String(s) => s,
+                        key => key.to_string(),
+                    };
+                    object.insert(key, value);
+                } else {
+                    pairs.push(Value::Array(vec![key, value]));
+                }
+            }
+            prot.read_map_end()?;
+            if scalar_key {
+                Value::Object(object)
+            } else {
+                Value::Array(pairs)
+            }
+        }
+        _ => {
+            return Err(CodecError::new(
+                CodecErrorKind::InvalidData,
+                format!("invalid ttype {}, normal type is expected", ttype as u8),
+            ));
+        }
+    };
+    Ok(value)
+}
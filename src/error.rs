@@ -1,12 +1,24 @@
+#[cfg(feature = "backtrace")]
+use std::backtrace::Backtrace;
 use std::{
     borrow::Cow,
+    error::Error,
     fmt::{self, Display, Formatter},
 };
 
+use crate::{codec::ttheader::TTHeaderError, thrift::TType};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
 #[derive(Debug)]
 pub struct CodecError {
     pub kind: CodecErrorKind,
     pub message: Cow<'static, str>,
+    /// Where the error happened, when the decoder tracks it.
+    pub context: Option<Box<ErrorContext>>,
+    source: Option<BoxError>,
+    #[cfg(feature = "backtrace")]
+    backtrace: Backtrace,
 }
 
 impl CodecError {
@@ -14,25 +26,166 @@ impl CodecError {
         CodecError {
             message: message.into(),
             kind,
+            context: None,
+            source: None,
+            #[cfg(feature = "backtrace")]
+            backtrace: Backtrace::capture(),
         }
     }
 
+    /// Const constructors don't capture a backtrace.
     pub const fn invalid_data() -> CodecError {
         CodecError {
             message: Cow::Borrowed("invalid data"),
             kind: CodecErrorKind::InvalidData,
+            context: None,
+            source: None,
+            #[cfg(feature = "backtrace")]
+            backtrace: Backtrace::disabled(),
+        }
+    }
+
+    /// Const constructors don't capture a backtrace.
+    pub const fn depth_limit() -> CodecError {
+        CodecError {
+            message: Cow::Borrowed("depth limit exceeded"),
+            kind: CodecErrorKind::DepthLimit,
+            context: None,
+            source: None,
+            #[cfg(feature = "backtrace")]
+            backtrace: Backtrace::disabled(),
         }
     }
+
+    /// A value of type `actual` was read where `expected` was, in the field
+    /// `field_id` if any.
+    pub fn type_mismatch(expected: TType, actual: TType, field_id: Option<i16>) -> CodecError {
+        let message = match field_id {
+            Some(id) => format!("expected {expected:?} at field {id}, got {actual:?}"),
+            None => format!("expected {expected:?}, got {actual:?}"),
+        };
+        CodecError::new(
+            CodecErrorKind::TypeMismatch {
+                expected,
+                actual,
+                field_id,
+            },
+            message,
+        )
+    }
+
+    /// Attach the error this one was caused by, returned by
+    /// [`Error::source`].
+    #[inline]
+    pub fn with_source<E: Into<BoxError>>(mut self, source: E) -> CodecError {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Backtrace of where the error was created, captured as configured by
+    /// the `RUST_BACKTRACE` and `RUST_LIB_BACKTRACE` environment variables.
+    #[cfg(feature = "backtrace")]
+    #[inline]
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+
+    /// Whether the same request may succeed on a retry, over a new
+    /// connection: the failure came from the transport, not from the content
+    /// of the data.
+    #[inline]
+    pub fn is_retryable(&self) -> bool {
+        use CodecErrorKind::*;
+
+        matches!(self.kind, IOError(_) | Truncated | ChecksumMismatch)
+    }
+
+    /// Whether the peer sent data this end can't accept. A server can still
+    /// reply with an exception, the connection isn't broken.
+    #[inline]
+    pub fn is_client_fault(&self) -> bool {
+        use CodecErrorKind::*;
+
+        matches!(
+            self.kind,
+            InvalidData
+                | NegativeSize
+                | BadVersion
+                | DepthLimit
+                | UnknownMethod
+                | SizeLimitExceeded
+                | UnsupportedProtocol
+                | TypeMismatch { .. }
+                | LengthMismatch { .. }
+        )
+    }
+
+    /// Attach `context`, replacing any context already attached.
+    #[inline]
+    pub fn with_context(mut self, context: ErrorContext) -> CodecError {
+        self.context = Some(Box::new(context));
+        self
+    }
+}
+
+/// Position of a decode error: the offset in the frame, the fields being read
+/// and the protocol operation that failed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ErrorContext {
+    /// Offset in the frame where the failed read started.
+    pub offset: Option<usize>,
+    /// Id and type of the field being read in each enclosing struct,
+    /// outermost first.
+    pub field_path: Vec<(i16, TType)>,
+    /// The protocol method that failed, e.g. `"read_i32"`.
+    pub operation: Option<&'static str>,
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        if let Some(offset) = self.offset {
+            write!(f, "at offset {offset}")?;
+            sep = " ";
+        }
+        if !self.field_path.is_empty() {
+            write!(f, "{sep}in field ")?;
+            for (i, (id, field_type)) in self.field_path.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(".")?;
+                }
+                write!(f, "{id}({field_type:?})")?;
+            }
+            sep = " ";
+        }
+        if let Some(operation) = self.operation {
+            write!(f, "{sep}during {operation}")?;
+        }
+        Ok(())
+    }
 }
 
 impl Display for CodecError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         use CodecErrorKind::*;
 
-        write!(f, "{}", self.message)?;
+        match (&self.context, self.message.is_empty()) {
+            (Some(context), true) => write!(f, "decode error {context}")?,
+            (Some(context), false) => write!(f, "{} {context}", self.message)?,
+            (None, _) => write!(f, "{}", self.message)?,
+        }
         if !matches!(
             self.kind,
-            BadVersion | InvalidData | NegativeSize | NotImplemented | UnknownMethod
+            BadVersion
+                | InvalidData
+                | NegativeSize
+                | NotImplemented
+                | UnknownMethod
+                | Truncated
+                | SizeLimitExceeded
+                | UnsupportedProtocol
+                | TypeMismatch { .. }
+                | ChecksumMismatch
         ) {
             write!(f, ", caused by {}", self.kind)?;
         }
@@ -40,11 +193,51 @@ impl Display for CodecError {
     }
 }
 
-impl std::error::Error for CodecError {}
+impl Error for CodecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            CodecErrorKind::IOError(e) => Some(e),
+            _ => self.source.as_deref().map(|e| e as _),
+        }
+    }
+}
 
 impl From<std::io::Error> for CodecError {
     fn from(value: std::io::Error) -> Self {
-        CodecError::new(CodecErrorKind::IOError(value), "")
+        if let Some(err) = TTHeaderError::from_io_error(&value) {
+            return err.clone().into();
+        }
+        // converted with the `From` below, get it back
+        let value = match value.downcast::<CodecError>() {
+            Ok(err) => return err,
+            Err(value) => value,
+        };
+        match value.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                CodecError::new(CodecErrorKind::Truncated, "unexpected end of data")
+            }
+            // raised by the codec helpers on malformed data, not by the io
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput => {
+                CodecError::new(CodecErrorKind::InvalidData, value.to_string()).with_source(value)
+            }
+            _ => CodecError::new(CodecErrorKind::IOError(value), ""),
+        }
+    }
+}
+
+impl From<CodecError> for std::io::Error {
+    fn from(value: CodecError) -> Self {
+        use std::io::ErrorKind;
+
+        let kind = match value.kind {
+            CodecErrorKind::IOError(e) => return e,
+            CodecErrorKind::Truncated => ErrorKind::UnexpectedEof,
+            CodecErrorKind::NotImplemented | CodecErrorKind::UnsupportedProtocol => {
+                ErrorKind::Unsupported
+            }
+            _ => ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, value)
     }
 }
 
@@ -56,6 +249,27 @@ pub enum CodecErrorKind {
     NotImplemented,
     DepthLimit,
     UnknownMethod,
+    /// The transport ended in the middle of a value. A buffered frame too
+    /// short for its content is [`CodecErrorKind::InvalidData`].
+    Truncated,
+    /// A length is over the configured limit.
+    SizeLimitExceeded,
+    /// The protocol of the data isn't supported.
+    UnsupportedProtocol,
+    /// A value has another type than the one expected.
+    TypeMismatch {
+        expected: TType,
+        actual: TType,
+        /// Id of the field holding the value, if it is a field.
+        field_id: Option<i16>,
+    },
+    /// The checksum of the data doesn't match the one sent with it.
+    ChecksumMismatch,
+    /// A message didn't take exactly the length of its frame.
+    LengthMismatch {
+        expected: usize,
+        actual: usize,
+    },
     IOError(std::io::Error),
 }
 
@@ -69,6 +283,16 @@ impl Display for CodecErrorKind {
             CodecErrorKind::NotImplemented => write!(f, "NotImplemented"),
             CodecErrorKind::DepthLimit => write!(f, "DepthLimit"),
             CodecErrorKind::UnknownMethod => write!(f, "UnknownMethod"),
+            CodecErrorKind::Truncated => write!(f, "Truncated"),
+            CodecErrorKind::SizeLimitExceeded => write!(f, "SizeLimitExceeded"),
+            CodecErrorKind::UnsupportedProtocol => write!(f, "UnsupportedProtocol"),
+            CodecErrorKind::TypeMismatch {
+                expected, actual, ..
+            } => write!(f, "TypeMismatch: expected {expected:?}, actual {actual:?}"),
+            CodecErrorKind::ChecksumMismatch => write!(f, "ChecksumMismatch"),
+            CodecErrorKind::LengthMismatch { expected, actual } => {
+                write!(f, "LengthMismatch: expected {expected}, actual {actual}")
+            }
         }
     }
 }
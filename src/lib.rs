@@ -2,10 +2,22 @@ pub mod codec;
 
 mod error;
 
-pub use error::{CodecError, CodecErrorKind};
+pub use error::{CodecError, CodecErrorKind, ErrorContext};
 
 pub mod protocol;
 
 pub mod thrift;
 
 pub mod binary;
+
+pub mod blocking;
+
+pub mod dispatch;
+
+pub mod value;
+
+#[cfg(feature = "serde")]
+pub mod serde;
+
+#[cfg(feature = "json")]
+pub mod dump;
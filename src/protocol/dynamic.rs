@@ -0,0 +1,323 @@
+//! Object safe variants of the protocol traits.
+//!
+//! The protocol traits use GATs and `impl Future`, so they can not be used as
+//! `dyn`. The traits here are implemented for every protocol, so middleware
+//! can hold `&mut dyn DynInputProtocol` and friends. They live apart from the
+//! main traits since their methods share names.
+
+use std::{future::Future, pin::Pin};
+
+use bytes::Bytes;
+
+use super::{TAsyncInputProtocol, TAsyncOutputProtocol, TInputProtocol, TOutputProtocol};
+use crate::{
+    thrift::{
+        TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TSetIdentifier,
+        TStructIdentifier, TType,
+    },
+    CodecError,
+};
+
+/// Boxed future returned by the dyn async protocols.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, CodecError>> + 'a>>;
+
+macro_rules! impl_dyn_fn {
+    ($tr: ident, $($(#[$meta: meta])* $fname: ident($($arg: ident: $arg_type: ty),*) -> $out: ty;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            fn $fname(&mut self $(,$arg: $arg_type)*) -> $out {
+                $tr::$fname(self $(,$arg)*)
+            }
+        )*
+    };
+}
+
+macro_rules! impl_dyn_async_fn {
+    ($tr: ident, $($(#[$meta: meta])* $fname: ident($($arg: ident: $arg_type: ty),*) -> $out: ty;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            fn $fname(&mut self $(,$arg: $arg_type)*) -> BoxFuture<'_, $out> {
+                Box::pin($tr::$fname(self $(,$arg)*))
+            }
+        )*
+    };
+}
+
+macro_rules! dyn_write_fns {
+    ($m: ident, $tr: ident) => {
+        $m! {
+            $tr,
+            write_message_begin(identifier: &TMessageIdentifier) -> ();
+            write_message_end() -> ();
+            write_struct_begin(identifier: &TStructIdentifier) -> ();
+            write_struct_end() -> ();
+            write_field_begin(field_type: TType, id: i16) -> ();
+            write_field_end() -> ();
+            write_field_stop() -> ();
+            write_list_begin(identifier: &TListIdentifier) -> ();
+            write_list_end(len: usize) -> ();
+            write_set_begin(identifier: &TSetIdentifier) -> ();
+            write_set_end(len: usize) -> ();
+            write_map_begin(identifier: &TMapIdentifier) -> ();
+            write_map_end(len: usize) -> ();
+            write_byte(b: u8) -> ();
+            write_bool(b: bool) -> ();
+            write_i8(i: i8) -> ();
+            write_i16(i: i16) -> ();
+            write_i32(i: i32) -> ();
+            write_i64(i: i64) -> ();
+            write_double(d: f64) -> ();
+            #[cfg(feature = "float")]
+            write_float(f: f32) -> ();
+            write_uuid(u: [u8; 16]) -> ();
+            write_bytes(b: &[u8]) -> ();
+            write_string(s: &str) -> ();
+            write_bytes_owned(b: Bytes) -> ();
+        }
+    };
+}
+
+/// Object safe form of [`TInputProtocol`], without `buf`.
+/// Every input protocol implements it.
+pub trait DynInputProtocol<'x> {
+    fn read_message_begin(&mut self) -> Result<TMessageIdentifier<'_>, CodecError>;
+    fn read_message_end(&mut self) -> Result<(), CodecError>;
+    fn read_struct_begin(&mut self) -> Result<TStructIdentifier, CodecError>;
+    fn read_struct_end(&mut self) -> Result<(), CodecError>;
+    fn read_field_begin(&mut self) -> Result<TFieldIdentifier, CodecError>;
+    fn read_field_end(&mut self) -> Result<(), CodecError>;
+    fn read_list_begin(&mut self) -> Result<TListIdentifier, CodecError>;
+    fn read_list_end(&mut self) -> Result<(), CodecError>;
+    fn read_set_begin(&mut self) -> Result<TSetIdentifier, CodecError>;
+    fn read_set_end(&mut self) -> Result<(), CodecError>;
+    fn read_map_begin(&mut self) -> Result<TMapIdentifier, CodecError>;
+    fn read_map_end(&mut self) -> Result<(), CodecError>;
+    fn read_byte(&mut self) -> Result<u8, CodecError>;
+    fn read_bool(&mut self) -> Result<bool, CodecError>;
+    fn read_i8(&mut self) -> Result<i8, CodecError>;
+    fn read_i16(&mut self) -> Result<i16, CodecError>;
+    fn read_i32(&mut self) -> Result<i32, CodecError>;
+    fn read_i64(&mut self) -> Result<i64, CodecError>;
+    fn read_double(&mut self) -> Result<f64, CodecError>;
+    #[cfg(feature = "float")]
+    fn read_float(&mut self) -> Result<f32, CodecError>;
+    fn read_uuid(&mut self) -> Result<[u8; 16], CodecError>;
+    fn read_bytes(&mut self) -> Result<&'x [u8], CodecError>;
+    fn read_string(&mut self) -> Result<&'x str, CodecError>;
+    fn skip_field(&mut self, ttype: TType) -> Result<(), CodecError>;
+    fn skip_message(&mut self) -> Result<(), CodecError>;
+    fn skip_until_field(&mut self, id: i16) -> Result<Option<TType>, CodecError>;
+    fn skip_remaining(&mut self) -> Result<(), CodecError>;
+    fn read_bytes_owned(&mut self) -> Result<Bytes, CodecError>;
+}
+
+impl<'x, P: TInputProtocol<'x>> DynInputProtocol<'x> for P {
+    impl_dyn_fn! {
+        TInputProtocol,
+        read_message_begin() -> Result<TMessageIdentifier<'_>, CodecError>;
+        read_message_end() -> Result<(), CodecError>;
+        read_struct_begin() -> Result<TStructIdentifier, CodecError>;
+        read_struct_end() -> Result<(), CodecError>;
+        read_field_begin() -> Result<TFieldIdentifier, CodecError>;
+        read_field_end() -> Result<(), CodecError>;
+        read_list_begin() -> Result<TListIdentifier, CodecError>;
+        read_list_end() -> Result<(), CodecError>;
+        read_set_begin() -> Result<TSetIdentifier, CodecError>;
+        read_set_end() -> Result<(), CodecError>;
+        read_map_begin() -> Result<TMapIdentifier, CodecError>;
+        read_map_end() -> Result<(), CodecError>;
+        read_byte() -> Result<u8, CodecError>;
+        read_bool() -> Result<bool, CodecError>;
+        read_i8() -> Result<i8, CodecError>;
+        read_i16() -> Result<i16, CodecError>;
+        read_i32() -> Result<i32, CodecError>;
+        read_i64() -> Result<i64, CodecError>;
+        read_double() -> Result<f64, CodecError>;
+        #[cfg(feature = "float")]
+        read_float() -> Result<f32, CodecError>;
+        read_uuid() -> Result<[u8; 16], CodecError>;
+        read_bytes() -> Result<&'x [u8], CodecError>;
+        read_string() -> Result<&'x str, CodecError>;
+        skip_field(ttype: TType) -> Result<(), CodecError>;
+        skip_message() -> Result<(), CodecError>;
+        skip_until_field(id: i16) -> Result<Option<TType>, CodecError>;
+        skip_remaining() -> Result<(), CodecError>;
+        read_bytes_owned() -> Result<Bytes, CodecError>;
+    }
+}
+
+/// Object safe form of [`TOutputProtocol`], without `buf`.
+/// Every output protocol implements it.
+pub trait DynOutputProtocol {
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier);
+    fn write_message_end(&mut self);
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier);
+    fn write_struct_end(&mut self);
+    fn write_field_begin(&mut self, field_type: TType, id: i16);
+    fn write_field_end(&mut self);
+    fn write_field_stop(&mut self);
+    fn write_list_begin(&mut self, identifier: &TListIdentifier);
+    fn write_list_end(&mut self, len: usize);
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier);
+    fn write_set_end(&mut self, len: usize);
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier);
+    fn write_map_end(&mut self, len: usize);
+    fn write_byte(&mut self, b: u8);
+    fn write_bool(&mut self, b: bool);
+    fn write_i8(&mut self, i: i8);
+    fn write_i16(&mut self, i: i16);
+    fn write_i32(&mut self, i: i32);
+    fn write_i64(&mut self, i: i64);
+    fn write_double(&mut self, d: f64);
+    #[cfg(feature = "float")]
+    fn write_float(&mut self, f: f32);
+    fn write_uuid(&mut self, u: [u8; 16]);
+    fn write_bytes(&mut self, b: &[u8]);
+    fn write_string(&mut self, s: &str);
+    fn write_bytes_owned(&mut self, b: Bytes);
+    fn flush(&mut self);
+}
+
+impl<P: TOutputProtocol> DynOutputProtocol for P {
+    dyn_write_fns!(impl_dyn_fn, TOutputProtocol);
+
+    #[inline]
+    fn flush(&mut self) {
+        TOutputProtocol::flush(self)
+    }
+}
+
+/// Object safe form of [`TAsyncInputProtocol`], returning boxed futures.
+/// Every async input protocol implements it, and `dyn DynAsyncInputProtocol`
+/// implements [`TAsyncInputProtocol`] back.
+pub trait DynAsyncInputProtocol {
+    fn read_message_begin(&mut self) -> BoxFuture<'_, TMessageIdentifier<'static>>;
+    fn read_message_end(&mut self) -> BoxFuture<'_, ()>;
+    fn read_struct_begin(&mut self) -> BoxFuture<'_, TStructIdentifier>;
+    fn read_struct_end(&mut self) -> BoxFuture<'_, ()>;
+    fn read_field_begin(&mut self) -> BoxFuture<'_, TFieldIdentifier>;
+    fn read_field_end(&mut self) -> BoxFuture<'_, ()>;
+    fn read_list_begin(&mut self) -> BoxFuture<'_, TListIdentifier>;
+    fn read_list_end(&mut self) -> BoxFuture<'_, ()>;
+    fn read_set_begin(&mut self) -> BoxFuture<'_, TSetIdentifier>;
+    fn read_set_end(&mut self) -> BoxFuture<'_, ()>;
+    fn read_map_begin(&mut self) -> BoxFuture<'_, TMapIdentifier>;
+    fn read_map_end(&mut self) -> BoxFuture<'_, ()>;
+    fn read_byte(&mut self) -> BoxFuture<'_, u8>;
+    fn read_bool(&mut self) -> BoxFuture<'_, bool>;
+    fn read_i8(&mut self) -> BoxFuture<'_, i8>;
+    fn read_i16(&mut self) -> BoxFuture<'_, i16>;
+    fn read_i32(&mut self) -> BoxFuture<'_, i32>;
+    fn read_i64(&mut self) -> BoxFuture<'_, i64>;
+    fn read_double(&mut self) -> BoxFuture<'_, f64>;
+    #[cfg(feature = "float")]
+    fn read_float(&mut self) -> BoxFuture<'_, f32>;
+    fn read_uuid(&mut self) -> BoxFuture<'_, [u8; 16]>;
+    fn read_bytes(&mut self) -> BoxFuture<'_, Bytes>;
+    fn read_string(&mut self) -> BoxFuture<'_, Bytes>;
+}
+
+macro_rules! dyn_async_input_fns {
+    ($m: ident, $tr: ident) => {
+        $m! {
+            $tr,
+            read_message_begin() -> TMessageIdentifier<'static>;
+            read_message_end() -> ();
+            read_struct_begin() -> TStructIdentifier;
+            read_struct_end() -> ();
+            read_field_begin() -> TFieldIdentifier;
+            read_field_end() -> ();
+            read_list_begin() -> TListIdentifier;
+            read_list_end() -> ();
+            read_set_begin() -> TSetIdentifier;
+            read_set_end() -> ();
+            read_map_begin() -> TMapIdentifier;
+            read_map_end() -> ();
+            read_byte() -> u8;
+            read_bool() -> bool;
+            read_i8() -> i8;
+            read_i16() -> i16;
+            read_i32() -> i32;
+            read_i64() -> i64;
+            read_double() -> f64;
+            #[cfg(feature = "float")]
+            read_float() -> f32;
+            read_uuid() -> [u8; 16];
+            read_bytes() -> Bytes;
+            read_string() -> Bytes;
+        }
+    };
+}
+
+macro_rules! impl_boxed_async_fn {
+    ($tr: ident, $($(#[$meta: meta])* $fname: ident($($arg: ident: $arg_type: ty),*) -> $out: ty;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            fn $fname(&mut self $(,$arg: $arg_type)*) -> impl Future<Output = Result<$out, CodecError>> {
+                $tr::$fname(self $(,$arg)*)
+            }
+        )*
+    };
+}
+
+impl<P: TAsyncInputProtocol> DynAsyncInputProtocol for P {
+    dyn_async_input_fns!(impl_dyn_async_fn, TAsyncInputProtocol);
+}
+
+impl TAsyncInputProtocol for dyn DynAsyncInputProtocol + '_ {
+    dyn_async_input_fns!(impl_boxed_async_fn, DynAsyncInputProtocol);
+}
+
+/// Object safe form of [`TAsyncOutputProtocol`], returning a boxed future
+/// from `flush`. Every async output protocol implements it, and
+/// `dyn DynAsyncOutputProtocol` implements [`TAsyncOutputProtocol`] back.
+pub trait DynAsyncOutputProtocol {
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier);
+    fn write_message_end(&mut self);
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier);
+    fn write_struct_end(&mut self);
+    fn write_field_begin(&mut self, field_type: TType, id: i16);
+    fn write_field_end(&mut self);
+    fn write_field_stop(&mut self);
+    fn write_list_begin(&mut self, identifier: &TListIdentifier);
+    fn write_list_end(&mut self, len: usize);
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier);
+    fn write_set_end(&mut self, len: usize);
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier);
+    fn write_map_end(&mut self, len: usize);
+    fn write_byte(&mut self, b: u8);
+    fn write_bool(&mut self, b: bool);
+    fn write_i8(&mut self, i: i8);
+    fn write_i16(&mut self, i: i16);
+    fn write_i32(&mut self, i: i32);
+    fn write_i64(&mut self, i: i64);
+    fn write_double(&mut self, d: f64);
+    #[cfg(feature = "float")]
+    fn write_float(&mut self, f: f32);
+    fn write_uuid(&mut self, u: [u8; 16]);
+    fn write_bytes(&mut self, b: &[u8]);
+    fn write_string(&mut self, s: &str);
+    fn write_bytes_owned(&mut self, b: Bytes);
+    fn flush(&mut self) -> BoxFuture<'_, ()>;
+}
+
+impl<P: TAsyncOutputProtocol> DynAsyncOutputProtocol for P {
+    dyn_write_fns!(impl_dyn_fn, TAsyncOutputProtocol);
+
+    #[inline]
+    fn flush(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(TAsyncOutputProtocol::flush(self))
+    }
+}
+
+impl TAsyncOutputProtocol for dyn DynAsyncOutputProtocol + '_ {
+    dyn_write_fns!(impl_dyn_fn, DynAsyncOutputProtocol);
+
+    #[inline]
+    fn flush(&mut self) -> impl Future<Output = Result<(), CodecError>> {
+        DynAsyncOutputProtocol::flush(self)
+    }
+}
@@ -0,0 +1,894 @@
+use std::collections::HashSet;
+
+use bytes::Bytes;
+
+use crate::binary::ReadBufPolicy;
+use crate::codec::ttheader::ProtocolId;
+use crate::thrift::{
+    TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TMessageType,
+    TSetIdentifier, TStructIdentifier, TType, TUuid,
+};
+use crate::CodecError;
+
+pub mod dynamic;
+
+/// Limits and checks applied by the protocols when reading.
+///
+/// The defaults accept anything the wire format can express, nested up to
+/// [`DEFAULT_MAX_DEPTH`](crate::binary::DEFAULT_MAX_DEPTH) levels.
+#[derive(Clone, Copy, Debug)]
+pub struct ProtocolConfig {
+    /// Reject messages without a version in their header, instead of reading
+    /// them in the old unversioned format.
+    pub strict: bool,
+    /// Max length of a binary or string in bytes.
+    pub max_string_len: usize,
+    /// Max number of elements in a list, set or map.
+    pub max_container_len: usize,
+    /// Max nesting of structs and containers.
+    pub max_depth: usize,
+    /// Check that strings read as [`Bytes`] are utf8. Strings read as `&str`
+    /// are always checked.
+    pub validate_utf8: bool,
+    /// Room the async protocols reserve in their buffer when reading from
+    /// the io.
+    pub read_buf: ReadBufPolicy,
+}
+
+impl ProtocolConfig {
+    pub const DEFAULT: Self = Self {
+        strict: true,
+        max_string_len: usize::MAX,
+        max_container_len: usize::MAX,
+        max_depth: crate::binary::DEFAULT_MAX_DEPTH,
+        validate_utf8: true,
+        read_buf: ReadBufPolicy::DEFAULT,
+    };
+}
+
+impl Default for ProtocolConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Cache of the message names a reader has seen.
+///
+/// A connection usually calls a handful of methods, so a reader holding one
+/// hands out the cached [`Bytes`] of a known name instead of allocating, or
+/// holding on to its read buffer, for every message. Known names are not
+/// validated again. The cache stops growing at its capacity, other names are
+/// read as without it.
+#[derive(Clone, Debug)]
+pub struct NameInterner {
+    names: HashSet<Bytes>,
+    capacity: usize,
+}
+
+impl NameInterner {
+    pub const DEFAULT_CAPACITY: usize = 64;
+
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_capacity(Self::DEFAULT_CAPACITY)
+    }
+
+    /// Interner caching at most `capacity` names.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            names: HashSet::new(),
+            capacity,
+        }
+    }
+
+    /// The cached name equal to `name`.
+    #[inline]
+    pub fn get(&self, name: &[u8]) -> Option<Bytes> {
+        self.names.get(name).cloned()
+    }
+
+    /// The cached `name`, cached first if there is room. Servers may intern
+    /// the names of their methods up front.
+    pub fn intern(&mut self, name: &str) -> Bytes {
+        if let Some(cached) = self.get(name.as_bytes()) {
+            return cached;
+        }
+        let name = Bytes::copy_from_slice(name.as_bytes());
+        if self.names.len() < self.capacity {
+            self.names.insert(name.clone());
+        }
+        name
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl Default for NameInterner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// TInputProtocol is for the protocol that the total payload length
+/// can be known with low cost. For example, message with FramedHeader
+/// TTHeader or MeshHeader.
+/// All output types are Copy or borrow of raw bytes.
+pub trait TInputProtocol<'x> {
+    type Buf<'b>
+    where
+        Self: 'b;
+    /// Read the beginning of a Thrift message.
+    fn read_message_begin(&mut self) -> Result<TMessageIdentifier, CodecError>;
+    /// Read the end of a Thrift message.
+    fn read_message_end(&mut self) -> Result<(), CodecError>;
+    /// Read the beginning of a Thrift struct.
+    fn read_struct_begin(&mut self) -> Result<TStructIdentifier, CodecError>;
+    /// Read the end of a Thrift struct.
+    fn read_struct_end(&mut self) -> Result<(), CodecError>;
+    /// Read the beginning of a Thrift struct field.
+    fn read_field_begin(&mut self) -> Result<TFieldIdentifier, CodecError>;
+    /// Read the end of a Thrift struct field.
+    fn read_field_end(&mut self) -> Result<(), CodecError>;
+    /// Read the beginning of a list.
+    fn read_list_begin(&mut self) -> Result<TListIdentifier, CodecError>;
+    /// Read the end of a list.
+    fn read_list_end(&mut self) -> Result<(), CodecError>;
+    /// Read the beginning of a set.
+    fn read_set_begin(&mut self) -> Result<TSetIdentifier, CodecError>;
+    /// Read the end of a set.
+    fn read_set_end(&mut self) -> Result<(), CodecError>;
+    /// Read the beginning of a map.
+    fn read_map_begin(&mut self) -> Result<TMapIdentifier, CodecError>;
+    /// Read the end of a map.
+    fn read_map_end(&mut self) -> Result<(), CodecError>;
+    /// Read an unsigned byte.
+    fn read_byte(&mut self) -> Result<u8, CodecError>;
+    /// Read a bool.
+    fn read_bool(&mut self) -> Result<bool, CodecError>;
+    /// Read a word.
+    fn read_i8(&mut self) -> Result<i8, CodecError>;
+    /// Read a 16-bit signed integer.
+    fn read_i16(&mut self) -> Result<i16, CodecError>;
+    /// Read a 32-bit signed integer.
+    fn read_i32(&mut self) -> Result<i32, CodecError>;
+    /// Read a 64-bit signed integer.
+    fn read_i64(&mut self) -> Result<i64, CodecError>;
+    /// Read a 64-bit float.
+    fn read_double(&mut self) -> Result<f64, CodecError>;
+    /// Read a uuid.
+    fn read_uuid(&mut self) -> Result<[u8; 16], CodecError>;
+    /// Read a binary.
+    fn read_bytes(&mut self) -> Result<&'x [u8], CodecError>;
+    /// Read a fixed-length string.
+    fn read_string(&mut self) -> Result<&'x str, CodecError>;
+    /// Skip a field.
+    fn skip_field(&mut self, ttype: TType) -> Result<(), CodecError>;
+
+    /// Skip a whole message.
+    #[inline]
+    fn skip_message(&mut self) -> Result<(), CodecError> {
+        self.read_message_begin()?;
+        self.skip_field(TType::Struct)?;
+        self.read_message_end()
+    }
+
+    /// Skip the fields of the current struct until the one with `id`, and
+    /// return its type with its value left to read. Returns `None` after
+    /// reading the STOP field if there is no such field.
+    fn skip_until_field(&mut self, id: i16) -> Result<Option<TType>, CodecError> {
+        loop {
+            let field = self.read_field_begin()?;
+            if field.field_type == TType::Stop {
+                return Ok(None);
+            }
+            if field.id == Some(id) {
+                return Ok(Some(field.field_type));
+            }
+            self.skip_field(field.field_type)?;
+            self.read_field_end()?;
+        }
+    }
+
+    /// Skip the remaining fields of the current struct, up to and including
+    /// the STOP field.
+    fn skip_remaining(&mut self) -> Result<(), CodecError> {
+        loop {
+            let field = self.read_field_begin()?;
+            if field.field_type == TType::Stop {
+                return Ok(());
+            }
+            self.skip_field(field.field_type)?;
+            self.read_field_end()?;
+        }
+    }
+
+    /// Read a struct, calling `f` for each field until the STOP field.
+    /// Fields for which `f` returns `Ok(false)` are skipped.
+    fn read_struct_fields<F>(&mut self, mut f: F) -> Result<(), CodecError>
+    where
+        Self: Sized,
+        F: FnMut(&mut Self, TFieldIdentifier) -> Result<bool, CodecError>,
+    {
+        self.read_struct_begin()?;
+        loop {
+            let field = self.read_field_begin()?;
+            let field_type = field.field_type;
+            if field_type == TType::Stop {
+                break;
+            }
+            if !f(self, field)? {
+                self.skip_field(field_type)?;
+            }
+            self.read_field_end()?;
+        }
+        self.read_struct_end()
+    }
+
+    /// Read a list of `element_type` elements, each read by `f`.
+    fn read_list_of<C, T, F>(&mut self, element_type: TType, f: F) -> Result<C, CodecError>
+    where
+        Self: Sized,
+        C: FromIterator<T>,
+        F: FnMut(&mut Self) -> Result<T, CodecError>,
+    {
+        let list = self.read_list_begin()?;
+        check_element_type(element_type, list.element_type, list.size)?;
+        let out = read_elements(self, list.size, f)?;
+        self.read_list_end()?;
+        Ok(out)
+    }
+
+    /// Read a set of `element_type` elements, each read by `f`.
+    fn read_set_of<C, T, F>(&mut self, element_type: TType, f: F) -> Result<C, CodecError>
+    where
+        Self: Sized,
+        C: FromIterator<T>,
+        F: FnMut(&mut Self) -> Result<T, CodecError>,
+    {
+        let set = self.read_set_begin()?;
+        check_element_type(element_type, set.element_type, set.size)?;
+        let out = read_elements(self, set.size, f)?;
+        self.read_set_end()?;
+        Ok(out)
+    }
+
+    /// Read a map of `key_type -> value_type` entries, each read by `fk`
+    /// and `fv`.
+    fn read_map_of<C, K, V, FK, FV>(
+        &mut self,
+        key_type: TType,
+        value_type: TType,
+        mut fk: FK,
+        mut fv: FV,
+    ) -> Result<C, CodecError>
+    where
+        Self: Sized,
+        C: FromIterator<(K, V)>,
+        FK: FnMut(&mut Self) -> Result<K, CodecError>,
+        FV: FnMut(&mut Self) -> Result<V, CodecError>,
+    {
+        let map = self.read_map_begin()?;
+        check_element_type(key_type, map.key_type, map.size)?;
+        check_element_type(value_type, map.value_type, map.size)?;
+        let out = read_elements(self, map.size, |p| Ok((fk(p)?, fv(p)?)))?;
+        self.read_map_end()?;
+        Ok(out)
+    }
+
+    /// Read a binary that outlives the input, copying it unless the
+    /// protocol is backed by [`Bytes`].
+    #[inline]
+    fn read_bytes_owned(&mut self) -> Result<Bytes, CodecError> {
+        self.read_bytes().map(Bytes::copy_from_slice)
+    }
+
+    /// Read a 32-bit float, fails unless the protocol supports it.
+    #[cfg(feature = "float")]
+    #[inline]
+    fn read_float(&mut self) -> Result<f32, CodecError> {
+        Err(float_unsupported())
+    }
+
+    /// Read a uuid as [`TUuid`].
+    #[inline]
+    fn read_tuuid(&mut self) -> Result<TUuid, CodecError> {
+        self.read_uuid().map(TUuid::from_be_bytes)
+    }
+
+    /// Read a uuid as [`uuid::Uuid`].
+    #[cfg(feature = "uuid")]
+    #[inline]
+    fn read_uuid_typed(&mut self) -> Result<uuid::Uuid, CodecError> {
+        self.read_uuid().map(uuid::Uuid::from_bytes)
+    }
+
+    fn buf<'a>(&'a mut self) -> &'a mut Self::Buf<'x>
+    where
+        'x: 'a;
+}
+
+#[cfg(feature = "float")]
+#[cold]
+fn float_unsupported() -> CodecError {
+    CodecError::new(
+        crate::CodecErrorKind::NotImplemented,
+        "float is not supported by the protocol",
+    )
+}
+
+// Containers reserve at most this many elements up front, as their length
+// comes from the input.
+const PREALLOC_LIMIT: usize = 1024;
+
+#[inline]
+fn check_element_type(expected: TType, actual: TType, len: usize) -> Result<(), CodecError> {
+    // empty containers may carry any element type
+    if len != 0 && expected != actual {
+        return Err(CodecError::type_mismatch(expected, actual, None));
+    }
+    Ok(())
+}
+
+// Yields `remaining` elements read by `read`, stopping at the first error.
+struct Elements<'a, P, F> {
+    protocol: &'a mut P,
+    read: F,
+    remaining: usize,
+    error: Option<CodecError>,
+}
+
+impl<P, T, F: FnMut(&mut P) -> Result<T, CodecError>> Iterator for Elements<'_, P, F> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        match (self.read)(self.protocol) {
+            Ok(item) => Some(item),
+            Err(e) => {
+                self.remaining = 0;
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining.min(PREALLOC_LIMIT), Some(self.remaining))
+    }
+}
+
+#[inline]
+fn read_elements<P, T, C, F>(protocol: &mut P, len: usize, read: F) -> Result<C, CodecError>
+where
+    C: FromIterator<T>,
+    F: FnMut(&mut P) -> Result<T, CodecError>,
+{
+    let mut elements = Elements {
+        protocol,
+        read,
+        remaining: len,
+        error: None,
+    };
+    let out = elements.by_ref().collect();
+    match elements.error {
+        Some(e) => Err(e),
+        None => Ok(out),
+    }
+}
+
+macro_rules! async_fn {
+    (async fn $fname:ident(&mut self $(,$arg:ident: $arg_type:ty)*) -> Result<$futname:ident($out:ty)>) => {
+        fn $fname(&mut self $(,$arg : $arg_type)*) -> impl std::future::Future<Output = Result<$out, CodecError>>;
+    };
+    ($(async fn $fname:ident(&mut self $(,$arg:ident: $arg_type:ty)*) -> Result<$futname:ident($out:ty)>;)*) => {
+        $(async_fn!(async fn $fname(&mut self $(,$arg : $arg_type)*) -> Result<$futname($out)>);)*
+    };
+}
+
+pub trait TAsyncSkipProtocol {
+    async_fn! {
+        async fn skip_message(&mut self) -> Result<SkipMessage(())>;
+        async fn skip_field(&mut self, ttype: TType) -> Result<SkipField(())>;
+        async fn skip_until_field(&mut self, id: i16) -> Result<SkipUntilField(Option<TType>)>;
+        async fn skip_remaining(&mut self) -> Result<SkipRemaining(())>;
+    }
+}
+
+/// TAsyncInputProtocol is for the protocol that the total payload length
+/// cannot be known with low cost. For example, raw binary message.
+/// If the data is insufficient, the impl will read with the io inside itself.
+/// To archive best performance, the impl may use Bytes to avoid memory copy.
+pub trait TAsyncInputProtocol {
+    async_fn! {
+        async fn read_message_begin(&mut self) -> Result<ReadMessageBegin(TMessageIdentifier<'static>)>;
+        async fn read_message_end(&mut self) -> Result<ReadMessageEnd(())>;
+        async fn read_struct_begin(&mut self) -> Result<ReadStructBegin(TStructIdentifier)>;
+        async fn read_struct_end(&mut self) -> Result<ReadStructEnd(())>;
+        async fn read_field_begin(&mut self) -> Result<ReadFieldBegin(TFieldIdentifier)>;
+        async fn read_field_end(&mut self) -> Result<ReadFieldEnd(())>;
+        async fn read_list_begin(&mut self) -> Result<ReadListBegin(TListIdentifier)>;
+        async fn read_list_end(&mut self) -> Result<ReadListEnd(())>;
+        async fn read_set_begin(&mut self) -> Result<ReadSetBegin(TSetIdentifier)>;
+        async fn read_set_end(&mut self) -> Result<ReadSetEnd(())>;
+        async fn read_map_begin(&mut self) -> Result<ReadMapBegin(TMapIdentifier)>;
+        async fn read_map_end(&mut self) -> Result<ReadMapEnd(())>;
+        async fn read_byte(&mut self) -> Result<ReadByte(u8)>;
+        async fn read_bool(&mut self) -> Result<ReadBool(bool)>;
+        async fn read_i8(&mut self) -> Result<ReadI8(i8)>;
+        async fn read_i16(&mut self) -> Result<ReadI16(i16)>;
+        async fn read_i32(&mut self) -> Result<ReadI32(i32)>;
+        async fn read_i64(&mut self) -> Result<ReadI64(i64)>;
+        async fn read_double(&mut self) -> Result<ReadDouble(f64)>;
+        async fn read_uuid(&mut self) -> Result<ReadUuid([u8; 16])>;
+        async fn read_bytes(&mut self) -> Result<ReadBytes(Bytes)>;
+        async fn read_string(&mut self) -> Result<ReadString(Bytes)>;
+    }
+
+    /// Read a 32-bit float, fails unless the protocol supports it.
+    #[cfg(feature = "float")]
+    #[inline]
+    fn read_float(&mut self) -> impl std::future::Future<Output = Result<f32, CodecError>> {
+        async { Err(float_unsupported()) }
+    }
+
+    /// Read a uuid as [`TUuid`].
+    #[inline]
+    fn read_tuuid(&mut self) -> impl std::future::Future<Output = Result<TUuid, CodecError>> {
+        async { self.read_uuid().await.map(TUuid::from_be_bytes) }
+    }
+
+    /// Read a uuid as [`uuid::Uuid`].
+    #[cfg(feature = "uuid")]
+    #[inline]
+    fn read_uuid_typed(
+        &mut self,
+    ) -> impl std::future::Future<Output = Result<uuid::Uuid, CodecError>> {
+        async { self.read_uuid().await.map(uuid::Uuid::from_bytes) }
+    }
+}
+
+pub trait TOutputProtocol {
+    type Buf;
+
+    /// Write the beginning of a Thrift message.
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier);
+    /// Write the end of a Thrift message.
+    fn write_message_end(&mut self);
+    /// Write the beginning of a Thrift struct.
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier);
+    /// Write the end of a Thrift struct.
+    fn write_struct_end(&mut self);
+    /// Write the beginning of a Thrift field.
+    fn write_field_begin(&mut self, field_type: TType, id: i16);
+    /// Write the end of a Thrift field.
+    fn write_field_end(&mut self);
+    /// Write a STOP field indicating that all the fields in a struct have been
+    /// written.
+    fn write_field_stop(&mut self);
+    /// Write the beginning of a list.
+    fn write_list_begin(&mut self, identifier: &TListIdentifier);
+    /// Write the end of a list.
+    fn write_list_end(&mut self, len: usize);
+    /// Write the beginning of a set.
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier);
+    /// Write the end of a set.
+    fn write_set_end(&mut self, len: usize);
+    /// Write the beginning of a map.
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier);
+    /// Write the end of a map.
+    fn write_map_end(&mut self, len: usize);
+    /// Write a byte.
+    fn write_byte(&mut self, b: u8);
+    /// Write a bool.
+    fn write_bool(&mut self, b: bool);
+    /// Write an 8-bit signed integer.
+    fn write_i8(&mut self, i: i8);
+    /// Write a 16-bit signed integer.
+    fn write_i16(&mut self, i: i16);
+    /// Write a 32-bit signed integer.
+    fn write_i32(&mut self, i: i32);
+    /// Write a 64-bit signed integer.
+    fn write_i64(&mut self, i: i64);
+    /// Write a 64-bit float.
+    fn write_double(&mut self, d: f64);
+    /// Write a uuid.
+    fn write_uuid(&mut self, u: [u8; 16]);
+    /// Write a [`TUuid`].
+    #[inline]
+    fn write_tuuid(&mut self, u: TUuid) {
+        self.write_uuid(u.to_be_bytes())
+    }
+    /// Write a [`uuid::Uuid`].
+    #[cfg(feature = "uuid")]
+    #[inline]
+    fn write_uuid_typed(&mut self, u: uuid::Uuid) {
+        self.write_uuid(u.into_bytes())
+    }
+    /// Write a 32-bit float. Writes can't fail, so every protocol has to
+    /// support it when the `float` feature is on.
+    #[cfg(feature = "float")]
+    fn write_float(&mut self, f: f32);
+    /// Write a fixed-length byte array.
+    fn write_bytes(&mut self, b: &[u8]);
+    /// Write a fixed-length string.
+    fn write_string(&mut self, s: &str);
+    /// Write a byte array held in [`Bytes`]. Writers that can send it without
+    /// copying keep a reference to it, the others copy it as `write_bytes`.
+    #[inline]
+    fn write_bytes_owned(&mut self, b: Bytes) {
+        self.write_bytes(&b)
+    }
+
+    /// Write the begin of a call of a multiplexed service, its name prefixed
+    /// with `service_name`, see [`TMessageIdentifier::multiplexed`].
+    #[inline]
+    fn write_multiplexed_message_begin(
+        &mut self,
+        service_name: &str,
+        identifier: &TMessageIdentifier,
+    ) {
+        self.write_message_begin(&TMessageIdentifier::multiplexed(
+            service_name,
+            identifier.name.as_str(),
+            identifier.message_type,
+            identifier.sequence_number,
+        ));
+    }
+
+    /// Write a message: the message begin, what `f` writes and the message
+    /// end.
+    #[inline]
+    fn write_message_scope<R>(
+        &mut self,
+        identifier: &TMessageIdentifier,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R
+    where
+        Self: Sized,
+    {
+        self.write_message_begin(identifier);
+        let out = f(self);
+        self.write_message_end();
+        out
+    }
+    /// Write a struct: the struct begin, the fields `f` writes, the STOP
+    /// field and the struct end.
+    #[inline]
+    fn write_struct_scope<R>(
+        &mut self,
+        identifier: &TStructIdentifier,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R
+    where
+        Self: Sized,
+    {
+        self.write_struct_begin(identifier);
+        let out = f(self);
+        self.write_field_stop();
+        self.write_struct_end();
+        out
+    }
+    /// Write a field: the field begin, the value `f` writes and the field
+    /// end.
+    #[inline]
+    fn write_field_scope<R>(
+        &mut self,
+        field_type: TType,
+        id: i16,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R
+    where
+        Self: Sized,
+    {
+        self.write_field_begin(field_type, id);
+        let out = f(self);
+        self.write_field_end();
+        out
+    }
+
+    /// Flush buffered bytes to the underlying transport.
+    fn flush(&mut self);
+    fn buf(&mut self) -> &mut Self::Buf;
+}
+
+impl<T: TOutputProtocol> TOutputProtocol for &mut T {
+    type Buf = T::Buf;
+
+    #[inline]
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) {
+        (**self).write_message_begin(identifier)
+    }
+    #[inline(always)]
+    fn write_message_end(&mut self) {
+        (**self).write_message_end()
+    }
+    #[inline]
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) {
+        (**self).write_struct_begin(identifier)
+    }
+    #[inline(always)]
+    fn write_struct_end(&mut self) {
+        (**self).write_struct_end()
+    }
+    #[inline]
+    fn write_field_begin(&mut self, field_type: TType, id: i16) {
+        (**self).write_field_begin(field_type, id)
+    }
+    #[inline(always)]
+    fn write_field_end(&mut self) {
+        (**self).write_field_end()
+    }
+    #[inline]
+    fn write_field_stop(&mut self) {
+        (**self).write_field_stop()
+    }
+    #[inline]
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) {
+        (**self).write_list_begin(identifier)
+    }
+    #[inline]
+    fn write_list_end(&mut self, len: usize) {
+        (**self).write_list_end(len)
+    }
+    #[inline]
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) {
+        (**self).write_set_begin(identifier)
+    }
+    #[inline]
+    fn write_set_end(&mut self, len: usize) {
+        (**self).write_set_end(len)
+    }
+    #[inline]
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) {
+        (**self).write_map_begin(identifier)
+    }
+    #[inline]
+    fn write_map_end(&mut self, len: usize) {
+        (**self).write_map_end(len)
+    }
+    #[inline]
+    fn write_byte(&mut self, b: u8) {
+        (**self).write_byte(b)
+    }
+    #[inline]
+    fn write_bool(&mut self, b: bool) {
+        (**self).write_bool(b)
+    }
+
+    #[inline]
+    fn write_i8(&mut self, i: i8) {
+        (**self).write_i8(i)
+    }
+    #[inline]
+    fn write_i16(&mut self, i: i16) {
+        (**self).write_i16(i)
+    }
+    #[inline]
+    fn write_i32(&mut self, i: i32) {
+        (**self).write_i32(i)
+    }
+    #[inline]
+    fn write_i64(&mut self, i: i64) {
+        (**self).write_i64(i)
+    }
+    #[inline]
+    fn write_double(&mut self, d: f64) {
+        (**self).write_double(d)
+    }
+    #[inline]
+    fn write_uuid(&mut self, u: [u8; 16]) {
+        (**self).write_uuid(u)
+    }
+    #[cfg(feature = "float")]
+    #[inline]
+    fn write_float(&mut self, f: f32) {
+        (**self).write_float(f)
+    }
+    #[inline]
+    fn write_bytes(&mut self, b: &[u8]) {
+        (**self).write_bytes(b)
+    }
+    #[inline]
+    fn write_string(&mut self, s: &str) {
+        (**self).write_string(s)
+    }
+    #[inline]
+    fn write_bytes_owned(&mut self, b: Bytes) {
+        (**self).write_bytes_owned(b)
+    }
+    #[inline(always)]
+    fn flush(&mut self) {
+        (**self).flush()
+    }
+    #[inline]
+    fn buf(&mut self) -> &mut Self::Buf {
+        (**self).buf()
+    }
+}
+
+/// TAsyncOutputProtocol buffers what is written like [`TOutputProtocol`],
+/// `flush` sends the buffered bytes to the io.
+pub trait TAsyncOutputProtocol {
+    /// Write the beginning of a Thrift message.
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier);
+    /// Write the end of a Thrift message.
+    fn write_message_end(&mut self);
+    /// Write the beginning of a Thrift struct.
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier);
+    /// Write the end of a Thrift struct.
+    fn write_struct_end(&mut self);
+    /// Write the beginning of a Thrift field.
+    fn write_field_begin(&mut self, field_type: TType, id: i16);
+    /// Write the end of a Thrift field.
+    fn write_field_end(&mut self);
+    /// Write a STOP field indicating that all the fields in a struct have been
+    /// written.
+    fn write_field_stop(&mut self);
+    /// Write the beginning of a list.
+    fn write_list_begin(&mut self, identifier: &TListIdentifier);
+    /// Write the end of a list.
+    fn write_list_end(&mut self, len: usize);
+    /// Write the beginning of a set.
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier);
+    /// Write the end of a set.
+    fn write_set_end(&mut self, len: usize);
+    /// Write the beginning of a map.
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier);
+    /// Write the end of a map.
+    fn write_map_end(&mut self, len: usize);
+    /// Write a byte.
+    fn write_byte(&mut self, b: u8);
+    /// Write a bool.
+    fn write_bool(&mut self, b: bool);
+    /// Write an 8-bit signed integer.
+    fn write_i8(&mut self, i: i8);
+    /// Write a 16-bit signed integer.
+    fn write_i16(&mut self, i: i16);
+    /// Write a 32-bit signed integer.
+    fn write_i32(&mut self, i: i32);
+    /// Write a 64-bit signed integer.
+    fn write_i64(&mut self, i: i64);
+    /// Write a 64-bit float.
+    fn write_double(&mut self, d: f64);
+    /// Write a uuid.
+    fn write_uuid(&mut self, u: [u8; 16]);
+    /// Write a [`TUuid`].
+    #[inline]
+    fn write_tuuid(&mut self, u: TUuid) {
+        self.write_uuid(u.to_be_bytes())
+    }
+    /// Write a 32-bit float. Writes can't fail, so every protocol has to
+    /// support it when the `float` feature is on.
+    #[cfg(feature = "float")]
+    fn write_float(&mut self, f: f32);
+    /// Write a fixed-length byte array.
+    fn write_bytes(&mut self, b: &[u8]);
+    /// Write a fixed-length string.
+    fn write_string(&mut self, s: &str);
+    /// Write a byte array held in [`Bytes`]. Writers that can send it without
+    /// copying keep a reference to it, the others copy it as `write_bytes`.
+    #[inline]
+    fn write_bytes_owned(&mut self, b: Bytes) {
+        self.write_bytes(&b)
+    }
+
+    /// Write the begin of a call of a multiplexed service, its name prefixed
+    /// with `service_name`, see [`TMessageIdentifier::multiplexed`].
+    #[inline]
+    fn write_multiplexed_message_begin(
+        &mut self,
+        service_name: &str,
+        identifier: &TMessageIdentifier,
+    ) {
+        self.write_message_begin(&TMessageIdentifier::multiplexed(
+            service_name,
+            identifier.name.as_str(),
+            identifier.message_type,
+            identifier.sequence_number,
+        ));
+    }
+
+    /// Write a message: the message begin, what `f` writes and the message
+    /// end.
+    #[inline]
+    fn write_message_scope<R>(
+        &mut self,
+        identifier: &TMessageIdentifier,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R
+    where
+        Self: Sized,
+    {
+        self.write_message_begin(identifier);
+        let out = f(self);
+        self.write_message_end();
+        out
+    }
+    /// Write a struct: the struct begin, the fields `f` writes, the STOP
+    /// field and the struct end.
+    #[inline]
+    fn write_struct_scope<R>(
+        &mut self,
+        identifier: &TStructIdentifier,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R
+    where
+        Self: Sized,
+    {
+        self.write_struct_begin(identifier);
+        let out = f(self);
+        self.write_field_stop();
+        self.write_struct_end();
+        out
+    }
+    /// Write a field: the field begin, the value `f` writes and the field
+    /// end.
+    #[inline]
+    fn write_field_scope<R>(
+        &mut self,
+        field_type: TType,
+        id: i16,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R
+    where
+        Self: Sized,
+    {
+        self.write_field_begin(field_type, id);
+        let out = f(self);
+        self.write_field_end();
+        out
+    }
+
+    async_fn! {
+        async fn flush(&mut self) -> Result<Flush(())>;
+    }
+}
+
+/// TSerializable is implemented by generated and hand-written types to be
+/// read from and written to the sync protocols.
+pub trait TSerializable: Sized {
+    /// Read a value.
+    fn read<'x, P: TInputProtocol<'x>>(prot: &mut P) -> Result<Self, CodecError>;
+    /// Write a value.
+    fn write<P: TOutputProtocol>(&self, prot: &mut P);
+}
+
+/// TAsyncSerializable is the counterpart of [`TSerializable`] for the async
+/// protocols. Its methods have their own names, so a type can implement both.
+pub trait TAsyncSerializable: Sized {
+    /// Read a value.
+    fn read_async<P: TAsyncInputProtocol>(
+        prot: &mut P,
+    ) -> impl std::future::Future<Output = Result<Self, CodecError>>;
+    /// Write a value, it is sent on the next flush.
+    fn write_async<P: TAsyncOutputProtocol>(&self, prot: &mut P);
+}
+
+/// Metadata of a message, borrowed from its encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageMeta<'a> {
+    pub name: &'a str,
+    pub msg_type: TMessageType,
+    pub seq_id: i32,
+    pub protocol: ProtocolId,
+}
+
+/// TMetaPeek reads the metadata of an encoded message without decoding the
+/// rest of it, and without allocating.
+pub trait TMetaPeek {
+    fn peek_meta(data: &[u8]) -> Result<MessageMeta<'_>, CodecError>;
+}
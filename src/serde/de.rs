@@ -0,0 +1,553 @@
+use std::io::Cursor;
+
+use serde::de::{self, Deserialize, DeserializeSeed, IntoDeserializer, Visitor};
+
+use super::field_id;
+use crate::{
+    binary::TBinaryReader, protocol::TInputProtocol, thrift::TType, CodecError, CodecErrorKind,
+};
+
+/// Deserialize a `T` from the given input protocol.
+pub fn from_protocol<'de, T, P>(prot: &mut P) -> Result<T, CodecError>
+where
+    T: Deserialize<'de>,
+    P: TInputProtocol<'de>,
+{
+    T::deserialize(Deserializer::new(prot))
+}
+
+/// Deserialize a `T` from binary protocol encoded `data`.
+///
+/// `&str` and `&[u8]` fields of `T` borrow from `data` without copying.
+pub fn from_binary<'de, T>(data: &'de [u8]) -> Result<T, CodecError>
+where
+    T: Deserialize<'de>,
+{
+    from_protocol(&mut TBinaryReader::new(Cursor::new(data)))
+}
+
+/// Serde deserializer reading from a `TInputProtocol`.
+///
+/// `ttype` is the wire type of the value when known from the enclosing field,
+/// list, set or map header; otherwise the type hint of the visitor is trusted.
+pub struct Deserializer<'a, P> {
+    prot: &'a mut P,
+    ttype: Option<TType>,
+}
+
+impl<'a, P> Deserializer<'a, P> {
+    #[inline]
+    pub fn new(prot: &'a mut P) -> Self {
+        Self { prot, ttype: None }
+    }
+
+    #[inline]
+    fn typed(prot: &'a mut P, ttype: TType) -> Self {
+        Self {
+            prot,
+            ttype: Some(ttype),
+        }
+    }
+
+    /// Whether the wire type is known and differs from `expected`.
+    #[inline]
+    fn mismatch(&self, expected: TType) -> bool {
+        matches!(self.ttype, Some(t) if t != expected)
+    }
+}
+
+macro_rules! deserialize_primitive {
+    ($($method:ident => $ttype:ident, $read:ident, $visit:ident;)*) => {$(
+        #[inline]
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+            if self.mismatch(TType::$ttype) {
+                return self.deserialize_any(visitor);
+            }
+            visitor.$visit(self.prot.$read()?)
+        }
+    )*};
+}
+
+impl<'de, 'a, P: TInputProtocol<'de>> de::Deserializer<'de> for Deserializer<'a, P> {
+    type Error = CodecError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        match self.ttype {
+            Some(TType::Bool) => visitor.visit_bool(self.prot.read_bool()?),
+            Some(TType::I8) => visitor.visit_i8(self.prot.read_i8()?),
+            Some(TType::I16) => visitor.visit_i16(self.prot.read_i16()?),
+            Some(TType::I32) => visitor.visit_i32(self.prot.read_i32()?),
+            Some(TType::I64) => visitor.visit_i64(self.prot.read_i64()?),
+            Some(TType::Double) => visitor.visit_f64(self.prot.read_double()?),
+            #[cfg(feature = "float")]
+            Some(TType::Float) => visitor.visit_f32(self.prot.read_float()?),
+            Some(TType::Uuid) => visitor.visit_bytes(&self.prot.read_uuid()?),
+            Some(TType::Binary) => {
+                let data = self.prot.read_bytes()?;
+                match std::str::from_utf8(data) {
+                    Ok(s) => visitor.visit_borrowed_str(s),
+                    Err(_) => visitor.visit_borrowed_bytes(data),
+                }
+            }
+            Some(TType::Struct) => {
+                self.prot.read_struct_begin()?;
+                let value = visitor.visit_map(StructAccess {
+                    prot: &mut *self.prot,
+                    fields: None,
+                    pending: TType::Stop,
+                })?;
+                self.prot.read_struct_end()?;
+                Ok(value)
+            }
+            Some(TType::List | TType::Set) => self.deserialize_seq(visitor),
+            Some(TType::Map) => self.deserialize_map(visitor),
+            Some(ttype) => Err(CodecError::new(
+                CodecErrorKind::InvalidData,
+                format!("invalid ttype {}, normal type is expected", ttype as u8),
+            )),
+            None => Err(CodecError::new(
+                CodecErrorKind::NotImplemented,
+                "thrift values of unknown type cannot be deserialized",
+            )),
+        }
+    }
+
+    deserialize_primitive! {
+        deserialize_bool => Bool, read_bool, visit_bool;
+        deserialize_i8 => I8, read_i8, visit_i8;
+        deserialize_i16 => I16, read_i16, visit_i16;
+        deserialize_i32 => I32, read_i32, visit_i32;
+        deserialize_i64 => I64, read_i64, visit_i64;
+        deserialize_u16 => I16, read_i16, visit_i16;
+        deserialize_u32 => I32, read_i32, visit_i32;
+        deserialize_u64 => I64, read_i64, visit_i64;
+        deserialize_f32 => Double, read_double, visit_f64;
+        deserialize_f64 => Double, read_double, visit_f64;
+        deserialize_char => Binary, read_string, visit_borrowed_str;
+        deserialize_str => Binary, read_string, visit_borrowed_str;
+        deserialize_string => Binary, read_string, visit_borrowed_str;
+        deserialize_bytes => Binary, read_bytes, visit_borrowed_bytes;
+        deserialize_byte_buf => Binary, read_bytes, visit_borrowed_bytes;
+    }
+
+    #[inline]
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        if self.mismatch(TType::I8) {
+            return self.deserialize_any(visitor);
+        }
+        // thrift byte: keep the bit pattern
+        visitor.visit_u8(self.prot.read_i8()? as u8)
+    }
+
+    #[inline]
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        // absent fields never reach the deserializer
+        visitor.visit_some(self)
+    }
+
+    #[inline]
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        if self.mismatch(TType::Struct) {
+            return self.deserialize_any(visitor);
+        }
+        self.prot.skip_field(TType::Struct)?;
+        visitor.visit_unit()
+    }
+
+    #[inline]
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        self.deserialize_unit(visitor)
+    }
+
+    #[inline]
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        let (element_type, size) = match self.ttype {
+            Some(TType::Set) => {
+                let ident = self.prot.read_set_begin()?;
+                (ident.element_type, ident.size)
+            }
+            None | Some(TType::List) => {
+                let ident = self.prot.read_list_begin()?;
+                (ident.element_type, ident.size)
+            }
+            Some(_) => return self.deserialize_any(visitor),
+        };
+        let mut access = SeqAccess {
+            prot: &mut *self.prot,
+            element_type,
+            remaining: size,
+        };
+        let value = visitor.visit_seq(&mut access)?;
+        for _ in 0..access.remaining {
+            self.prot.skip_field(element_type)?;
+        }
+        match self.ttype {
+            Some(TType::Set) => self.prot.read_set_end()?,
+            _ => self.prot.read_list_end()?,
+        }
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        if self.mismatch(TType::Struct) {
+            return self.deserialize_any(visitor);
+        }
+        self.prot.read_struct_begin()?;
+        let mut access = PositionalAccess {
+            prot: &mut *self.prot,
+            done: false,
+        };
+        let value = visitor.visit_seq(&mut access)?;
+        while !access.done {
+            let field = access.prot.read_field_begin()?;
+            if field.field_type == TType::Stop {
+                break;
+            }
+            access.prot.skip_field(field.field_type)?;
+            access.prot.read_field_end()?;
+        }
+        self.prot.read_struct_end()?;
+        Ok(value)
+    }
+
+    #[inline]
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        if self.mismatch(TType::Map) {
+            return self.deserialize_any(visitor);
+        }
+        let ident = self.prot.read_map_begin()?;
+        let mut access = MapAccess {
+            prot: &mut *self.prot,
+            key_type: ident.key_type,
+            value_type: ident.value_type,
+            remaining: ident.size,
+        };
+        let value = visitor.visit_map(&mut access)?;
+        for _ in 0..access.remaining {
+            self.prot.skip_field(ident.key_type)?;
+            self.prot.skip_field(ident.value_type)?;
+        }
+        self.prot.read_map_end()?;
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        if self.mismatch(TType::Struct) {
+            return self.deserialize_any(visitor);
+        }
+        self.prot.read_struct_begin()?;
+        let value = visitor.visit_map(StructAccess {
+            prot: &mut *self.prot,
+            fields: Some(fields),
+            pending: TType::Stop,
+        })?;
+        self.prot.read_struct_end()?;
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        match self.ttype {
+            // top-level enums are always unions, see `Serializer`
+            None | Some(TType::Struct) => {
+                self.prot.read_struct_begin()?;
+                let field = self.prot.read_field_begin()?;
+                let id = match field.id {
+                    Some(id) if field.field_type != TType::Stop && id > 0 => id,
+                    _ => {
+                        return Err(CodecError::new(
+                            CodecErrorKind::InvalidData,
+                            "union without a set field",
+                        ))
+                    }
+                };
+                let value = visitor.visit_enum(UnionAccess {
+                    prot: &mut *self.prot,
+                    ttype: field.field_type,
+                    index: id as u32 - 1,
+                })?;
+                self.prot.read_field_end()?;
+                loop {
+                    let field = self.prot.read_field_begin()?;
+                    if field.field_type == TType::Stop {
+                        break;
+                    }
+                    self.prot.skip_field(field.field_type)?;
+                    self.prot.read_field_end()?;
+                }
+                self.prot.read_struct_end()?;
+                Ok(value)
+            }
+            Some(TType::I32) => {
+                let index = self.prot.read_i32()? as u32;
+                visitor.visit_enum(index.into_deserializer())
+            }
+            Some(_) => self.deserialize_any(visitor),
+        }
+    }
+
+    #[inline]
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        self.deserialize_any(visitor)
+    }
+
+    #[inline]
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        match self.ttype {
+            Some(ttype) => {
+                self.prot.skip_field(ttype)?;
+                visitor.visit_unit()
+            }
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    #[inline]
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+struct StructAccess<'a, P> {
+    prot: &'a mut P,
+    // `None` yields field ids as keys
+    fields: Option<&'static [&'static str]>,
+    pending: TType,
+}
+
+impl<'de, 'a, P: TInputProtocol<'de>> de::MapAccess<'de> for StructAccess<'a, P> {
+    type Error = CodecError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, CodecError> {
+        loop {
+            let field = self.prot.read_field_begin()?;
+            if field.field_type == TType::Stop {
+                return Ok(None);
+            }
+            let id = field.id.unwrap_or_default();
+            let fields = match self.fields {
+                Some(fields) => fields,
+                None => {
+                    self.pending = field.field_type;
+                    return seed.deserialize(id.into_deserializer()).map(Some);
+                }
+            };
+            let name = fields
+                .iter()
+                .enumerate()
+                .find(|(index, name)| field_id(*index, name) == id)
+                .map(|(_, name)| *name);
+            match name {
+                Some(name) => {
+                    self.pending = field.field_type;
+                    return seed.deserialize(name.into_deserializer()).map(Some);
+                }
+                None => {
+                    self.prot.skip_field(field.field_type)?;
+                    self.prot.read_field_end()?;
+                }
+            }
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, CodecError> {
+        let value = seed.deserialize(Deserializer::typed(&mut *self.prot, self.pending))?;
+        self.prot.read_field_end()?;
+        Ok(value)
+    }
+}
+
+struct PositionalAccess<'a, P> {
+    prot: &'a mut P,
+    done: bool,
+}
+
+impl<'de, 'a, P: TInputProtocol<'de>> de::SeqAccess<'de> for PositionalAccess<'a, P> {
+    type Error = CodecError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, CodecError> {
+        if self.done {
+            return Ok(None);
+        }
+        let field = self.prot.read_field_begin()?;
+        if field.field_type == TType::Stop {
+            self.done = true;
+            return Ok(None);
+        }
+        let value = seed.deserialize(Deserializer::typed(&mut *self.prot, field.field_type))?;
+        self.prot.read_field_end()?;
+        Ok(Some(value))
+    }
+}
+
+struct SeqAccess<'a, P> {
+    prot: &'a mut P,
+    element_type: TType,
+    remaining: usize,
+}
+
+impl<'de, 'a, P: TInputProtocol<'de>> de::SeqAccess<'de> for SeqAccess<'a, P> {
+    type Error = CodecError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, CodecError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(Deserializer::typed(&mut *self.prot, self.element_type))
+            .map(Some)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+struct MapAccess<'a, P> {
+    prot: &'a mut P,
+    key_type: TType,
+    value_type: TType,
+    remaining: usize,
+}
+
+impl<'de, 'a, P: TInputProtocol<'de>> de::MapAccess<'de> for MapAccess<'a, P> {
+    type Error = CodecError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, CodecError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(Deserializer::typed(&mut *self.prot, self.key_type))
+            .map(Some)
+    }
+
+    #[inline]
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, CodecError> {
+        seed.deserialize(Deserializer::typed(&mut *self.prot, self.value_type))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+struct UnionAccess<'a, P> {
+    prot: &'a mut P,
+    ttype: TType,
+    index: u32,
+}
+
+impl<'de, 'a, P: TInputProtocol<'de>> de::EnumAccess<'de> for UnionAccess<'a, P> {
+    type Error = CodecError;
+    type Variant = Self;
+
+    #[inline]
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), CodecError> {
+        let variant = seed.deserialize(IntoDeserializer::<CodecError>::into_deserializer(
+            self.index,
+        ))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a, P: TInputProtocol<'de>> de::VariantAccess<'de> for UnionAccess<'a, P> {
+    type Error = CodecError;
+
+    #[inline]
+    fn unit_variant(self) -> Result<(), CodecError> {
+        self.prot.skip_field(self.ttype)
+    }
+
+    #[inline]
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, CodecError> {
+        seed.deserialize(Deserializer::typed(self.prot, self.ttype))
+    }
+
+    #[inline]
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        de::Deserializer::deserialize_tuple(
+            Deserializer::typed(self.prot, self.ttype),
+            len,
+            visitor,
+        )
+    }
+
+    #[inline]
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        de::Deserializer::deserialize_struct(
+            Deserializer::typed(self.prot, self.ttype),
+            "",
+            fields,
+            visitor,
+        )
+    }
+}
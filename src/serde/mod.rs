@@ -0,0 +1,161 @@
+//! Serde bridge for thrift protocols.
+//!
+//! Rust structs are mapped to thrift structs, sequences to lists and maps to
+//! maps. Field ids are taken from the serialized field name: a field renamed to
+//! a number (`#[serde(rename = "3")]`) uses that number, any other field falls
+//! back to its 1-based declaration order. Enum variants are encoded as i32
+//! (unit variants) or as single-field unions keyed by `variant_index + 1`.
+//! A top-level enum is always a union, as no header tells its type. The
+//! elements of a list or map share one thrift type, so serializing one that
+//! mixes the two kinds of variants fails.
+
+use std::fmt::Display;
+
+use crate::{CodecError, CodecErrorKind};
+
+mod de;
+mod ser;
+
+pub use de::{from_binary, from_protocol, Deserializer};
+pub use ser::{to_binary, to_protocol, Serializer};
+
+impl serde::ser::Error for CodecError {
+    fn custom<T: Display>(msg: T) -> Self {
+        CodecError::new(CodecErrorKind::InvalidData, msg.to_string())
+    }
+}
+
+impl serde::de::Error for CodecError {
+    fn custom<T: Display>(msg: T) -> Self {
+        CodecError::new(CodecErrorKind::InvalidData, msg.to_string())
+    }
+}
+
+/// Resolve the thrift field id of the `index`-th field named `name`.
+#[inline]
+pub(crate) fn field_id(index: usize, name: &str) -> i16 {
+    name.parse().unwrap_or(index as i16 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bytes::BytesMut;
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Mixed {
+        Data(i32),
+        Unit,
+        Struct { id: i64, name: String },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Inner {
+        #[serde(rename = "5")]
+        flag: bool,
+        scores: Vec<f64>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Outer<'a> {
+        id: i32,
+        name: &'a str,
+        nick: Option<String>,
+        missing: Option<i64>,
+        inner: Inner,
+        matrix: Vec<Vec<i16>>,
+        index: BTreeMap<String, Vec<Inner>>,
+        kinds: Vec<Mixed>,
+        kind: Mixed,
+        bytes: Vec<u8>,
+    }
+
+    fn round_trip<T>(value: &T)
+    where
+        T: Serialize + for<'de> Deserialize<'de> + std::fmt::Debug + PartialEq,
+    {
+        let buf = encode(value).unwrap();
+        assert_eq!(&from_binary::<T>(&buf).unwrap(), value);
+    }
+
+    fn encode<T: Serialize>(value: &T) -> Result<BytesMut, CodecError> {
+        let mut buf = BytesMut::new();
+        to_binary(value, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn list_elements_of_different_types() {
+        assert!(encode(&vec![Mixed::Data(1), Mixed::Unit]).is_err());
+        assert!(encode(&vec![Mixed::Unit, Mixed::Data(1)]).is_err());
+        assert!(encode(&vec![Mixed::Unit, Mixed::Unit]).is_ok());
+        assert!(encode(&vec![Mixed::Data(1), Mixed::Data(2)]).is_ok());
+    }
+
+    #[test]
+    fn map_entries_of_different_types() {
+        let values = BTreeMap::from([(1, Mixed::Unit), (2, Mixed::Data(1))]);
+        assert!(encode(&values).is_err());
+    }
+
+    #[test]
+    fn struct_round_trip() {
+        let value = Outer {
+            id: 7,
+            name: "borrowed",
+            nick: Some("nick".into()),
+            missing: None,
+            inner: Inner {
+                flag: true,
+                scores: vec![1.5, -2.0],
+            },
+            matrix: vec![vec![1, 2], vec![], vec![3]],
+            index: BTreeMap::from([(
+                "a".to_string(),
+                vec![Inner {
+                    flag: false,
+                    scores: vec![],
+                }],
+            )]),
+            kinds: vec![Mixed::Unit, Mixed::Unit],
+            kind: Mixed::Struct {
+                id: -1,
+                name: "s".into(),
+            },
+            bytes: vec![0, 255, 3],
+        };
+        let buf = encode(&value).unwrap();
+        let decoded: Outer<'_> = from_binary(&buf).unwrap();
+        assert_eq!(decoded, value);
+        // the string is borrowed from the buffer
+        let range = buf.as_ptr_range();
+        assert!(range.contains(&decoded.name.as_ptr()));
+    }
+
+    #[test]
+    fn enum_round_trip() {
+        round_trip(&Mixed::Unit);
+        round_trip(&Mixed::Data(5));
+        round_trip(&Mixed::Struct {
+            id: 1,
+            name: "x".into(),
+        });
+        round_trip(&vec![Mixed::Data(1), Mixed::Data(2)]);
+        round_trip(&vec![Mixed::Unit]);
+        round_trip(&BTreeMap::from([(1, Mixed::Unit), (2, Mixed::Unit)]));
+    }
+
+    #[test]
+    fn container_round_trip() {
+        round_trip(&vec![vec!["a".to_string()], vec![]]);
+        round_trip(&BTreeMap::from([(
+            1i64,
+            BTreeMap::from([(2i8, vec![true])]),
+        )]));
+        round_trip(&Vec::<i32>::new());
+    }
+}
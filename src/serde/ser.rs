@@ -0,0 +1,760 @@
+use std::fmt::Display;
+
+use bytes::BytesMut;
+use serde::ser::{self, Serialize};
+
+use super::field_id;
+use crate::{
+    binary::TBinaryWriter,
+    protocol::TOutputProtocol,
+    thrift::{TListIdentifier, TMapIdentifier, TStructIdentifier, TType},
+    CodecError, CodecErrorKind,
+};
+
+/// Serialize `value` with the given output protocol.
+pub fn to_protocol<T, P>(value: &T, prot: &mut P) -> Result<(), CodecError>
+where
+    T: ?Sized + Serialize,
+    P: TOutputProtocol,
+{
+    value.serialize(Serializer::new(prot))
+}
+
+/// Serialize `value` with the binary protocol, appending to `buf`.
+pub fn to_binary<T>(value: &T, buf: &mut BytesMut) -> Result<(), CodecError>
+where
+    T: ?Sized + Serialize,
+{
+    to_protocol(value, &mut TBinaryWriter::new(buf))
+}
+
+// A list or map header declares one type for all its elements, e.g. a list
+// mixing unit and data variants of an enum can't be written.
+#[inline]
+fn check_element_type(what: &str, expected: TType, actual: TType) -> Result<(), CodecError> {
+    if expected != actual {
+        return Err(CodecError::new(
+            CodecErrorKind::InvalidData,
+            format!("{what} of type {actual:?} where the header declares {expected:?}"),
+        ));
+    }
+    Ok(())
+}
+
+#[inline]
+fn out_of_range<T: Display>(v: T) -> CodecError {
+    CodecError::new(
+        CodecErrorKind::InvalidData,
+        format!("integer {v} out of thrift range"),
+    )
+}
+
+/// Serde serializer driving a `TOutputProtocol`.
+///
+/// When `field_id` is set the value is written as a struct field, with the
+/// field header emitted once the value's thrift type is known.
+pub struct Serializer<'a, P> {
+    prot: &'a mut P,
+    field_id: Option<i16>,
+    // top-level value, whose type no header tells the reader
+    root: bool,
+}
+
+impl<'a, P: TOutputProtocol> Serializer<'a, P> {
+    #[inline]
+    pub fn new(prot: &'a mut P) -> Self {
+        Self {
+            prot,
+            field_id: None,
+            root: true,
+        }
+    }
+
+    #[inline]
+    fn field(prot: &'a mut P, id: i16) -> Self {
+        Self {
+            prot,
+            field_id: Some(id),
+            root: false,
+        }
+    }
+
+    #[inline]
+    fn element(prot: &'a mut P) -> Self {
+        Self {
+            prot,
+            field_id: None,
+            root: false,
+        }
+    }
+
+    #[inline]
+    fn begin(&mut self, ttype: TType) {
+        if let Some(id) = self.field_id {
+            self.prot.write_field_begin(ttype, id);
+        }
+    }
+
+    #[inline]
+    fn end(&mut self) {
+        if self.field_id.is_some() {
+            self.prot.write_field_end();
+        }
+    }
+
+    #[inline]
+    fn struct_begin(
+        mut self,
+        name: &'static str,
+        variant: Option<(u32, &'static str)>,
+    ) -> StructSerializer<'a, P> {
+        self.begin(TType::Struct);
+        self.prot
+            .write_struct_begin(&TStructIdentifier::new(Some(name)));
+        if let Some((index, variant)) = variant {
+            self.prot.write_field_begin(TType::Struct, index as i16 + 1);
+            self.prot
+                .write_struct_begin(&TStructIdentifier::new(Some(variant)));
+        }
+        StructSerializer {
+            prot: self.prot,
+            field_id: self.field_id,
+            index: 0,
+            variant: variant.is_some(),
+        }
+    }
+}
+
+macro_rules! serialize_primitive {
+    ($($method:ident($ty:ty) => $ttype:ident, $write:ident;)*) => {$(
+        #[inline]
+        fn $method(mut self, v: $ty) -> Result<(), CodecError> {
+            self.begin(TType::$ttype);
+            self.prot.$write(v);
+            self.end();
+            Ok(())
+        }
+    )*};
+}
+
+impl<'a, P: TOutputProtocol> ser::Serializer for Serializer<'a, P> {
+    type Ok = ();
+    type Error = CodecError;
+
+    type SerializeSeq = SeqSerializer<'a, P>;
+    type SerializeTuple = StructSerializer<'a, P>;
+    type SerializeTupleStruct = StructSerializer<'a, P>;
+    type SerializeTupleVariant = StructSerializer<'a, P>;
+    type SerializeMap = MapSerializer<'a, P>;
+    type SerializeStruct = StructSerializer<'a, P>;
+    type SerializeStructVariant = StructSerializer<'a, P>;
+
+    serialize_primitive! {
+        serialize_bool(bool) => Bool, write_bool;
+        serialize_i8(i8) => I8, write_i8;
+        serialize_i16(i16) => I16, write_i16;
+        serialize_i32(i32) => I32, write_i32;
+        serialize_i64(i64) => I64, write_i64;
+        serialize_f64(f64) => Double, write_double;
+        serialize_str(&str) => Binary, write_string;
+        serialize_bytes(&[u8]) => Binary, write_bytes;
+    }
+
+    #[inline]
+    fn serialize_u8(self, v: u8) -> Result<(), CodecError> {
+        // thrift byte: keep the bit pattern
+        self.serialize_i8(v as i8)
+    }
+
+    #[inline]
+    fn serialize_u16(self, v: u16) -> Result<(), CodecError> {
+        self.serialize_i16(i16::try_from(v).map_err(|_| out_of_range(v))?)
+    }
+
+    #[inline]
+    fn serialize_u32(self, v: u32) -> Result<(), CodecError> {
+        self.serialize_i32(i32::try_from(v).map_err(|_| out_of_range(v))?)
+    }
+
+    #[inline]
+    fn serialize_u64(self, v: u64) -> Result<(), CodecError> {
+        self.serialize_i64(i64::try_from(v).map_err(|_| out_of_range(v))?)
+    }
+
+    #[inline]
+    fn serialize_f32(self, v: f32) -> Result<(), CodecError> {
+        self.serialize_f64(v as f64)
+    }
+
+    #[inline]
+    fn serialize_char(self, v: char) -> Result<(), CodecError> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    #[inline]
+    fn serialize_none(self) -> Result<(), CodecError> {
+        // an absent optional field is simply not written
+        if self.field_id.is_some() {
+            return Ok(());
+        }
+        Err(CodecError::new(
+            CodecErrorKind::NotImplemented,
+            "optional values are only supported as struct fields",
+        ))
+    }
+
+    #[inline]
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), CodecError> {
+        value.serialize(self)
+    }
+
+    #[inline]
+    fn serialize_unit(self) -> Result<(), CodecError> {
+        self.serialize_unit_struct("")
+    }
+
+    #[inline]
+    fn serialize_unit_struct(self, name: &'static str) -> Result<(), CodecError> {
+        ser::SerializeStruct::end(self.struct_begin(name, None))
+    }
+
+    #[inline]
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), CodecError> {
+        if self.root {
+            // nothing tells an i32 from a union at the top level, so every
+            // variant is a union there
+            let s = self.struct_begin(name, None);
+            Serializer::field(&mut *s.prot, variant_index as i16 + 1).serialize_unit()?;
+            return ser::SerializeStruct::end(s);
+        }
+        self.serialize_i32(variant_index as i32)
+    }
+
+    #[inline]
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        let s = self.struct_begin(name, None);
+        value.serialize(Serializer::field(&mut *s.prot, variant_index as i16 + 1))?;
+        ser::SerializeStruct::end(s)
+    }
+
+    #[inline]
+    fn serialize_seq(mut self, len: Option<usize>) -> Result<Self::SerializeSeq, CodecError> {
+        self.begin(TType::List);
+        Ok(SeqSerializer {
+            prot: self.prot,
+            field_id: self.field_id,
+            size: len.unwrap_or(0),
+            len: 0,
+            element_type: None,
+        })
+    }
+
+    #[inline]
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, CodecError> {
+        Ok(self.struct_begin("", None))
+    }
+
+    #[inline]
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, CodecError> {
+        Ok(self.struct_begin(name, None))
+    }
+
+    #[inline]
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, CodecError> {
+        Ok(self.struct_begin(name, Some((variant_index, variant))))
+    }
+
+    #[inline]
+    fn serialize_map(mut self, len: Option<usize>) -> Result<Self::SerializeMap, CodecError> {
+        self.begin(TType::Map);
+        Ok(MapSerializer {
+            prot: self.prot,
+            field_id: self.field_id,
+            size: len.unwrap_or(0),
+            len: 0,
+            entry_types: None,
+        })
+    }
+
+    #[inline]
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, CodecError> {
+        Ok(self.struct_begin(name, None))
+    }
+
+    #[inline]
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, CodecError> {
+        Ok(self.struct_begin(name, Some((variant_index, variant))))
+    }
+
+    #[inline]
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+pub struct SeqSerializer<'a, P> {
+    prot: &'a mut P,
+    field_id: Option<i16>,
+    size: usize,
+    len: usize,
+    // set once the list header is written
+    element_type: Option<TType>,
+}
+
+impl<'a, P: TOutputProtocol> ser::SerializeSeq for SeqSerializer<'a, P> {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), CodecError> {
+        // the element type goes before any element, so probe the first one
+        let element_type = value.serialize(TypeProbe)?;
+        match self.element_type {
+            Some(expected) => check_element_type("list element", expected, element_type)?,
+            None => {
+                self.prot
+                    .write_list_begin(&TListIdentifier::new(element_type, self.size));
+                self.element_type = Some(element_type);
+            }
+        }
+        value.serialize(Serializer::element(&mut *self.prot))?;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), CodecError> {
+        if self.element_type.is_none() {
+            self.prot
+                .write_list_begin(&TListIdentifier::new(TType::Void, 0));
+        }
+        self.prot.write_list_end(self.len);
+        if self.field_id.is_some() {
+            self.prot.write_field_end();
+        }
+        Ok(())
+    }
+}
+
+pub struct MapSerializer<'a, P> {
+    prot: &'a mut P,
+    field_id: Option<i16>,
+    size: usize,
+    len: usize,
+    // key and value types, set once the map header is written
+    entry_types: Option<(TType, TType)>,
+}
+
+impl<'a, P: TOutputProtocol> ser::SerializeMap for MapSerializer<'a, P> {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), CodecError> {
+        let Some((key_type, _)) = self.entry_types else {
+            return Err(CodecError::new(
+                CodecErrorKind::NotImplemented,
+                "thrift map entries must be serialized with serialize_entry",
+            ));
+        };
+        check_element_type("map key", key_type, key.serialize(TypeProbe)?)?;
+        key.serialize(Serializer::element(&mut *self.prot))
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), CodecError> {
+        if let Some((_, value_type)) = self.entry_types {
+            check_element_type("map value", value_type, value.serialize(TypeProbe)?)?;
+        }
+        value.serialize(Serializer::element(&mut *self.prot))?;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn serialize_entry<K, V>(&mut self, key: &K, value: &V) -> Result<(), CodecError>
+    where
+        K: ?Sized + Serialize,
+        V: ?Sized + Serialize,
+    {
+        if self.entry_types.is_none() {
+            let key_type = key.serialize(TypeProbe)?;
+            let value_type = value.serialize(TypeProbe)?;
+            self.prot
+                .write_map_begin(&TMapIdentifier::new(key_type, value_type, self.size));
+            self.entry_types = Some((key_type, value_type));
+        }
+        self.serialize_key(key)?;
+        self.serialize_value(value)
+    }
+
+    fn end(self) -> Result<(), CodecError> {
+        if self.entry_types.is_none() {
+            self.prot
+                .write_map_begin(&TMapIdentifier::new(TType::Void, TType::Void, 0));
+        }
+        self.prot.write_map_end(self.len);
+        if self.field_id.is_some() {
+            self.prot.write_field_end();
+        }
+        Ok(())
+    }
+}
+
+pub struct StructSerializer<'a, P> {
+    prot: &'a mut P,
+    field_id: Option<i16>,
+    index: usize,
+    // wrapped in a single-field union struct
+    variant: bool,
+}
+
+impl<'a, P: TOutputProtocol> StructSerializer<'a, P> {
+    #[inline]
+    fn write_field<T: ?Sized + Serialize>(&mut self, id: i16, value: &T) -> Result<(), CodecError> {
+        self.index += 1;
+        value.serialize(Serializer::field(&mut *self.prot, id))
+    }
+
+    #[inline]
+    fn write_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), CodecError> {
+        self.write_field(self.index as i16 + 1, value)
+    }
+
+    fn finish(self) -> Result<(), CodecError> {
+        self.prot.write_field_stop();
+        self.prot.write_struct_end();
+        if self.variant {
+            self.prot.write_field_end();
+            self.prot.write_field_stop();
+            self.prot.write_struct_end();
+        }
+        if self.field_id.is_some() {
+            self.prot.write_field_end();
+        }
+        Ok(())
+    }
+}
+
+impl<'a, P: TOutputProtocol> ser::SerializeStruct for StructSerializer<'a, P> {
+    type Ok = ();
+    type Error = CodecError;
+
+    #[inline]
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        self.write_field(field_id(self.index, key), value)
+    }
+
+    #[inline]
+    fn skip_field(&mut self, _key: &'static str) -> Result<(), CodecError> {
+        // keep declaration order ids stable for the following fields
+        self.index += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), CodecError> {
+        self.finish()
+    }
+}
+
+impl<'a, P: TOutputProtocol> ser::SerializeStructVariant for StructSerializer<'a, P> {
+    type Ok = ();
+    type Error = CodecError;
+
+    #[inline]
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    #[inline]
+    fn skip_field(&mut self, key: &'static str) -> Result<(), CodecError> {
+        ser::SerializeStruct::skip_field(self, key)
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), CodecError> {
+        self.finish()
+    }
+}
+
+macro_rules! impl_positional {
+    ($($trait:ident::$method:ident),*) => {$(
+        impl<'a, P: TOutputProtocol> ser::$trait for StructSerializer<'a, P> {
+            type Ok = ();
+            type Error = CodecError;
+
+            #[inline]
+            fn $method<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), CodecError> {
+                self.write_element(value)
+            }
+
+            #[inline]
+            fn end(self) -> Result<(), CodecError> {
+                self.finish()
+            }
+        }
+    )*};
+}
+
+impl_positional!(
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+/// Serializer that only reports the thrift type a value would be written as.
+struct TypeProbe;
+
+struct ProbeCompound(TType);
+
+impl ser::Serializer for TypeProbe {
+    type Ok = TType;
+    type Error = CodecError;
+
+    type SerializeSeq = ProbeCompound;
+    type SerializeTuple = ProbeCompound;
+    type SerializeTupleStruct = ProbeCompound;
+    type SerializeTupleVariant = ProbeCompound;
+    type SerializeMap = ProbeCompound;
+    type SerializeStruct = ProbeCompound;
+    type SerializeStructVariant = ProbeCompound;
+
+    fn serialize_bool(self, _v: bool) -> Result<TType, CodecError> {
+        Ok(TType::Bool)
+    }
+    fn serialize_i8(self, _v: i8) -> Result<TType, CodecError> {
+        Ok(TType::I8)
+    }
+    fn serialize_i16(self, _v: i16) -> Result<TType, CodecError> {
+        Ok(TType::I16)
+    }
+    fn serialize_i32(self, _v: i32) -> Result<TType, CodecError> {
+        Ok(TType::I32)
+    }
+    fn serialize_i64(self, _v: i64) -> Result<TType, CodecError> {
+        Ok(TType::I64)
+    }
+    fn serialize_u8(self, _v: u8) -> Result<TType, CodecError> {
+        Ok(TType::I8)
+    }
+    fn serialize_u16(self, _v: u16) -> Result<TType, CodecError> {
+        Ok(TType::I16)
+    }
+    fn serialize_u32(self, _v: u32) -> Result<TType, CodecError> {
+        Ok(TType::I32)
+    }
+    fn serialize_u64(self, _v: u64) -> Result<TType, CodecError> {
+        Ok(TType::I64)
+    }
+    fn serialize_f32(self, _v: f32) -> Result<TType, CodecError> {
+        Ok(TType::Double)
+    }
+    fn serialize_f64(self, _v: f64) -> Result<TType, CodecError> {
+        Ok(TType::Double)
+    }
+    fn serialize_char(self, _v: char) -> Result<TType, CodecError> {
+        Ok(TType::Binary)
+    }
+    fn serialize_str(self, _v: &str) -> Result<TType, CodecError> {
+        Ok(TType::Binary)
+    }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<TType, CodecError> {
+        Ok(TType::Binary)
+    }
+    fn serialize_none(self) -> Result<TType, CodecError> {
+        Err(CodecError::new(
+            CodecErrorKind::NotImplemented,
+            "optional values are only supported as struct fields",
+        ))
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<TType, CodecError> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<TType, CodecError> {
+        Ok(TType::Struct)
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<TType, CodecError> {
+        Ok(TType::Struct)
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<TType, CodecError> {
+        Ok(TType::I32)
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<TType, CodecError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<TType, CodecError> {
+        Ok(TType::Struct)
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<ProbeCompound, CodecError> {
+        Ok(ProbeCompound(TType::List))
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<ProbeCompound, CodecError> {
+        Ok(ProbeCompound(TType::Struct))
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<ProbeCompound, CodecError> {
+        Ok(ProbeCompound(TType::Struct))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<ProbeCompound, CodecError> {
+        Ok(ProbeCompound(TType::Struct))
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<ProbeCompound, CodecError> {
+        Ok(ProbeCompound(TType::Map))
+    }
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<ProbeCompound, CodecError> {
+        Ok(ProbeCompound(TType::Struct))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<ProbeCompound, CodecError> {
+        Ok(ProbeCompound(TType::Struct))
+    }
+}
+
+macro_rules! impl_probe_compound {
+    ($($trait:ident::$method:ident),*) => {$(
+        impl ser::$trait for ProbeCompound {
+            type Ok = TType;
+            type Error = CodecError;
+
+            #[inline]
+            fn $method<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), CodecError> {
+                Ok(())
+            }
+
+            #[inline]
+            fn end(self) -> Result<TType, CodecError> {
+                Ok(self.0)
+            }
+        }
+    )*};
+}
+
+impl_probe_compound!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+impl ser::SerializeMap for ProbeCompound {
+    type Ok = TType;
+    type Error = CodecError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, _key: &T) -> Result<(), CodecError> {
+        Ok(())
+    }
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), CodecError> {
+        Ok(())
+    }
+    fn end(self) -> Result<TType, CodecError> {
+        Ok(self.0)
+    }
+}
+
+impl ser::SerializeStruct for ProbeCompound {
+    type Ok = TType;
+    type Error = CodecError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        _value: &T,
+    ) -> Result<(), CodecError> {
+        Ok(())
+    }
+    fn end(self) -> Result<TType, CodecError> {
+        Ok(self.0)
+    }
+}
+
+impl ser::SerializeStructVariant for ProbeCompound {
+    type Ok = TType;
+    type Error = CodecError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        _value: &T,
+    ) -> Result<(), CodecError> {
+        Ok(())
+    }
+    fn end(self) -> Result<TType, CodecError> {
+        Ok(self.0)
+    }
+}
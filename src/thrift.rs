@@ -1,23 +1,51 @@
-use std::str::from_utf8_unchecked;
+use std::{
+    borrow::Cow,
+    fmt::{self, Display, Formatter},
+    ops::Deref,
+    str::from_utf8_unchecked,
+};
 
-use crate::{CodecError, CodecErrorKind};
+use bytes::Bytes;
+use smol_str::SmolStr;
+
+use crate::{
+    binary::validate_utf8,
+    protocol::{
+        TAsyncInputProtocol, TAsyncOutputProtocol, TAsyncSkipProtocol, TInputProtocol,
+        TOutputProtocol, TSerializable,
+    },
+    CodecError, CodecErrorKind,
+};
 
 /// Thrift struct identifier.
 #[derive(Clone, Debug, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TStructIdentifier {
     /// Name of the encoded Thrift struct.
-    pub name: Option<&'static str>,
+    pub name: Option<Cow<'static, str>>,
 }
 
 impl TStructIdentifier {
     /// Create a `TStructIdentifier` for a struct named `name`.
     pub const fn new(name: Option<&'static str>) -> TStructIdentifier {
+        let name = match name {
+            Some(name) => Some(Cow::Borrowed(name)),
+            None => None,
+        };
         TStructIdentifier { name }
     }
+
+    /// Set the name of the struct to `name`, e.g. one from a runtime schema.
+    #[inline]
+    pub fn with_name<S: Into<Cow<'static, str>>>(mut self, name: S) -> TStructIdentifier {
+        self.name = Some(name.into());
+        self
+    }
 }
 
 /// Thrift types.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum TType {
     Stop = 0,
@@ -34,6 +62,9 @@ pub enum TType {
     Set = 14,    // 0xe
     List = 15,   // 0xf
     Uuid = 16,   // 0x10
+    /// fbthrift 32-bit float.
+    #[cfg(feature = "float")]
+    Float = 19, // 0x13
 }
 
 impl From<TType> for u8 {
@@ -63,6 +94,8 @@ impl TryFrom<u8> for TType {
             14 => Ok(TType::Set),
             15 => Ok(TType::List),
             16 => Ok(TType::Uuid),
+            #[cfg(feature = "float")]
+            19 => Ok(TType::Float),
             _ => Err(CodecError::new(
                 CodecErrorKind::InvalidData,
                 format!("invalid ttype {}", value),
@@ -73,6 +106,7 @@ impl TryFrom<u8> for TType {
 
 /// Thrift message types.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum TMessageType {
     /// Service-call request.
@@ -143,6 +177,115 @@ impl<'a> CowBytes<'a, [u8]> {
     }
 }
 
+impl<'a, T: ?Sized + AsRef<[u8]>> CowBytes<'a, T> {
+    /// The data as `Bytes`, copied if borrowed.
+    #[inline]
+    pub fn into_bytes(self) -> Bytes {
+        match self {
+            CowBytes::Borrowed(s) => Bytes::copy_from_slice(s.as_ref()),
+            CowBytes::Owned(b) => b,
+        }
+    }
+
+    /// The data no longer borrowed, copied if it was.
+    #[inline]
+    pub fn into_owned(self) -> CowBytes<'static, T> {
+        CowBytes::Owned(self.into_bytes())
+    }
+}
+
+impl<'a> Deref for CowBytes<'a, str> {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<'a> Deref for CowBytes<'a, [u8]> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<'a> AsRef<str> for CowBytes<'a, str> {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<'a> AsRef<[u8]> for CowBytes<'a, str> {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<'a> AsRef<[u8]> for CowBytes<'a, [u8]> {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<'a> Display for CowBytes<'a, str> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'a> From<&'a str> for CowBytes<'a, str> {
+    #[inline]
+    fn from(s: &'a str) -> Self {
+        CowBytes::Borrowed(s)
+    }
+}
+
+impl From<String> for CowBytes<'_, str> {
+    #[inline]
+    fn from(s: String) -> Self {
+        CowBytes::Owned(Bytes::from(s))
+    }
+}
+
+/// Fails if `b` isn't valid UTF-8.
+impl TryFrom<Bytes> for CowBytes<'_, str> {
+    type Error = CodecError;
+
+    #[inline]
+    fn try_from(b: Bytes) -> Result<Self, Self::Error> {
+        validate_utf8(&b)?;
+        Ok(CowBytes::Owned(b))
+    }
+}
+
+impl<'a> From<&'a [u8]> for CowBytes<'a, [u8]> {
+    #[inline]
+    fn from(b: &'a [u8]) -> Self {
+        CowBytes::Borrowed(b)
+    }
+}
+
+impl From<Vec<u8>> for CowBytes<'_, [u8]> {
+    #[inline]
+    fn from(b: Vec<u8>) -> Self {
+        CowBytes::Owned(Bytes::from(b))
+    }
+}
+
+impl From<Bytes> for CowBytes<'_, [u8]> {
+    #[inline]
+    fn from(b: Bytes) -> Self {
+        CowBytes::Owned(b)
+    }
+}
+
 impl<'a, T: ?Sized> Clone for CowBytes<'a, T> {
     #[inline]
     fn clone(&self) -> Self {
@@ -168,8 +311,26 @@ impl<'a> PartialEq for CowBytes<'a, [u8]> {
 }
 impl<'a> Eq for CowBytes<'a, [u8]> {}
 
+#[cfg(feature = "serde")]
+impl<'a> serde::Serialize for CowBytes<'a, str> {
+    #[inline]
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Deserializes to an owned string, whatever the lifetime of the input.
+#[cfg(feature = "serde")]
+impl<'de, 'a> serde::Deserialize<'de> for CowBytes<'a, str> {
+    #[inline]
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(CowBytes::from)
+    }
+}
+
 /// Thrift message identifier.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TMessageIdentifier<'a> {
     /// Service call the message is associated with.
     pub name: CowBytes<'a, str>,
@@ -193,9 +354,45 @@ impl<'a> TMessageIdentifier<'a> {
             sequence_number,
         }
     }
+
+    /// Create a `TMessageIdentifier` for the method `method_name` of the
+    /// multiplexed service `service_name`.
+    pub fn multiplexed(
+        service_name: &str,
+        method_name: &str,
+        message_type: TMessageType,
+        sequence_number: i32,
+    ) -> TMessageIdentifier<'static> {
+        let name = format!("{service_name}{MULTIPLEXED_SEPARATOR}{method_name}");
+        TMessageIdentifier::new(name.into(), message_type, sequence_number)
+    }
+
+    /// Service of a multiplexed name `"Service:method"`, `None` if the name
+    /// has no service.
+    #[inline]
+    pub fn service_name(&self) -> Option<&str> {
+        self.name
+            .as_str()
+            .split_once(MULTIPLEXED_SEPARATOR)
+            .map(|(service, _)| service)
+    }
+
+    /// Method of a multiplexed name `"Service:method"`, the whole name if it
+    /// has no service.
+    #[inline]
+    pub fn method_name(&self) -> &str {
+        let name = self.name.as_str();
+        name.split_once(MULTIPLEXED_SEPARATOR)
+            .map_or(name, |(_, method)| method)
+    }
 }
 
+/// Separator of the service and method in the message names of the
+/// multiplexed protocol.
+pub const MULTIPLEXED_SEPARATOR: char = ':';
+
 #[derive(Clone, Debug, Eq, PartialEq, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TListIdentifier {
     /// Type of the elements in the list.
     pub element_type: TType,
@@ -213,6 +410,7 @@ impl TListIdentifier {
 
 /// Thrift set identifier.
 #[derive(Clone, Debug, Eq, PartialEq, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TSetIdentifier {
     /// Type of the elements in the set.
     pub element_type: TType,
@@ -230,11 +428,13 @@ impl TSetIdentifier {
 
 /// Thrift field identifier.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TFieldIdentifier {
     /// Name of the Thrift field.
     ///
-    /// `None` if it's not sent over the wire.
-    pub name: Option<&'static str>,
+    /// `None` if it's not sent over the wire. Decoders of runtime schemas
+    /// can attach names built at runtime.
+    pub name: Option<SmolStr>,
     /// Field type.
     ///
     /// This may be a primitive, container, or a struct.
@@ -255,16 +455,111 @@ impl TFieldIdentifier {
         field_type: TType,
         id: Option<i16>,
     ) -> TFieldIdentifier {
+        let name = match name {
+            Some(name) => Some(SmolStr::new_static(name)),
+            None => None,
+        };
         TFieldIdentifier {
             name,
             field_type,
             id,
         }
     }
+
+    /// Set the name of the field to `name`, e.g. one from a runtime schema.
+    #[inline]
+    pub fn with_name<S: Into<SmolStr>>(mut self, name: S) -> TFieldIdentifier {
+        self.name = Some(name.into());
+        self
+    }
+}
+
+/// Thrift uuid, in the byte order of the spec: the RFC 4122 layout, as the
+/// hyphenated form reads.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct TUuid([u8; 16]);
+
+impl TUuid {
+    pub const NIL: TUuid = TUuid([0; 16]);
+
+    /// Uuid from its bytes in the spec order, as sent on the wire.
+    #[inline]
+    pub const fn from_be_bytes(bytes: [u8; 16]) -> TUuid {
+        TUuid(bytes)
+    }
+
+    /// The bytes in the spec order, as sent on the wire.
+    #[inline]
+    pub const fn to_be_bytes(self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Uuid from bytes in the little-endian GUID layout, where the first
+    /// three groups are byte swapped, as used by Windows and .NET.
+    #[inline]
+    pub const fn from_le_bytes(b: [u8; 16]) -> TUuid {
+        TUuid([
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9], b[10], b[11], b[12], b[13],
+            b[14], b[15],
+        ])
+    }
+
+    /// The bytes in the little-endian GUID layout, see
+    /// [`TUuid::from_le_bytes`].
+    #[inline]
+    pub const fn to_le_bytes(self) -> [u8; 16] {
+        // swapping the groups is its own inverse
+        TUuid::from_le_bytes(self.0).0
+    }
+
+    #[inline]
+    pub const fn from_u128(v: u128) -> TUuid {
+        TUuid(v.to_be_bytes())
+    }
+
+    #[inline]
+    pub const fn as_u128(self) -> u128 {
+        u128::from_be_bytes(self.0)
+    }
+
+    /// The lowercase hyphenated form, e.g.
+    /// `"67e55044-10b1-426f-9247-bb680e5fe0c8"`.
+    pub fn hyphenated(self) -> String {
+        self.to_string()
+    }
+}
+
+impl Display for TUuid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<uuid::Uuid> for TUuid {
+    #[inline]
+    fn from(u: uuid::Uuid) -> Self {
+        TUuid(u.into_bytes())
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<TUuid> for uuid::Uuid {
+    #[inline]
+    fn from(u: TUuid) -> Self {
+        uuid::Uuid::from_bytes(u.0)
+    }
 }
 
 /// Thrift map identifier.
 #[derive(Clone, Debug, Eq, PartialEq, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TMapIdentifier {
     /// Map key type.
     pub key_type: TType,
@@ -285,3 +580,137 @@ impl TMapIdentifier {
         }
     }
 }
+
+/// Kind of a [`TApplicationException`], unknown kinds received are kept as is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TApplicationExceptionKind(pub i32);
+
+impl TApplicationExceptionKind {
+    pub const UNKNOWN: Self = Self(0);
+    pub const UNKNOWN_METHOD: Self = Self(1);
+    pub const INVALID_MESSAGE_TYPE: Self = Self(2);
+    pub const WRONG_METHOD_NAME: Self = Self(3);
+    pub const BAD_SEQUENCE_ID: Self = Self(4);
+    pub const MISSING_RESULT: Self = Self(5);
+    pub const INTERNAL_ERROR: Self = Self(6);
+    pub const PROTOCOL_ERROR: Self = Self(7);
+    pub const INVALID_TRANSFORM: Self = Self(8);
+    pub const INVALID_PROTOCOL: Self = Self(9);
+    pub const UNSUPPORTED_CLIENT_TYPE: Self = Self(10);
+}
+
+/// Error replied by a server, as the body of a message of type
+/// [`TMessageType::Exception`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TApplicationException {
+    pub kind: TApplicationExceptionKind,
+    pub message: String,
+}
+
+const APPLICATION_EXCEPTION: TStructIdentifier =
+    TStructIdentifier::new(Some("TApplicationException"));
+const MESSAGE_FIELD_ID: i16 = 1;
+const KIND_FIELD_ID: i16 = 2;
+
+impl TApplicationException {
+    pub fn new<S: Into<String>>(kind: TApplicationExceptionKind, message: S) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Read an exception from an async protocol.
+    pub async fn read_async<P>(prot: &mut P) -> Result<Self, CodecError>
+    where
+        P: TAsyncInputProtocol + TAsyncSkipProtocol,
+    {
+        let mut out = Self::new(TApplicationExceptionKind::UNKNOWN, String::new());
+        prot.read_struct_begin().await?;
+        loop {
+            let field = prot.read_field_begin().await?;
+            match (field.id, field.field_type) {
+                (_, TType::Stop) => break,
+                (Some(MESSAGE_FIELD_ID), TType::Binary) => {
+                    let message = prot.read_string().await?;
+                    out.message = String::from_utf8(message.into()).map_err(|_| {
+                        CodecError::new(CodecErrorKind::InvalidData, "invalid utf8 message")
+                    })?;
+                }
+                (Some(KIND_FIELD_ID), TType::I32) => {
+                    out.kind = TApplicationExceptionKind(prot.read_i32().await?);
+                }
+                (_, field_type) => prot.skip_field(field_type).await?,
+            }
+            prot.read_field_end().await?;
+        }
+        prot.read_struct_end().await?;
+        Ok(out)
+    }
+
+    /// Write the exception to an async protocol, it is sent on the next flush.
+    pub fn write_async<P: TAsyncOutputProtocol>(&self, prot: &mut P) {
+        prot.write_struct_begin(&APPLICATION_EXCEPTION);
+        prot.write_field_begin(TType::Binary, MESSAGE_FIELD_ID);
+        prot.write_string(&self.message);
+        prot.write_field_end();
+        prot.write_field_begin(TType::I32, KIND_FIELD_ID);
+        prot.write_i32(self.kind.0);
+        prot.write_field_end();
+        prot.write_field_stop();
+        prot.write_struct_end();
+    }
+}
+
+impl TSerializable for TApplicationException {
+    fn read<'x, P: TInputProtocol<'x>>(prot: &mut P) -> Result<Self, CodecError> {
+        let mut out = Self::new(TApplicationExceptionKind::UNKNOWN, String::new());
+        prot.read_struct_fields(|prot, field| {
+            match (field.id, field.field_type) {
+                (Some(MESSAGE_FIELD_ID), TType::Binary) => {
+                    out.message = prot.read_string()?.to_owned();
+                }
+                (Some(KIND_FIELD_ID), TType::I32) => {
+                    out.kind = TApplicationExceptionKind(prot.read_i32()?);
+                }
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        Ok(out)
+    }
+
+    fn write<P: TOutputProtocol>(&self, prot: &mut P) {
+        prot.write_struct_begin(&APPLICATION_EXCEPTION);
+        prot.write_field_begin(TType::Binary, MESSAGE_FIELD_ID);
+        prot.write_string(&self.message);
+        prot.write_field_end();
+        prot.write_field_begin(TType::I32, KIND_FIELD_ID);
+        prot.write_i32(self.kind.0);
+        prot.write_field_end();
+        prot.write_field_stop();
+        prot.write_struct_end();
+    }
+}
+
+impl Display for TApplicationException {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "application exception {}: {}", self.kind.0, self.message)
+    }
+}
+
+impl std::error::Error for TApplicationException {}
+
+impl From<CodecError> for TApplicationException {
+    fn from(e: CodecError) -> Self {
+        let kind = match e.kind {
+            CodecErrorKind::UnknownMethod => TApplicationExceptionKind::UNKNOWN_METHOD,
+            CodecErrorKind::UnsupportedProtocol => TApplicationExceptionKind::INVALID_PROTOCOL,
+            CodecErrorKind::IOError(_) => TApplicationExceptionKind::INTERNAL_ERROR,
+            _ => TApplicationExceptionKind::PROTOCOL_ERROR,
+        };
+        Self::new(kind, e.to_string())
+    }
+}
@@ -0,0 +1,249 @@
+//! Schema-less thrift values.
+//!
+//! [`TValue`] holds any value with its wire type, so it can be decoded without
+//! an IDL and encoded back as it was. Structs are keyed by field id, in wire
+//! order. The wire doesn't tell strings from binaries, they are decoded as
+//! [`TValue::Binary`].
+
+use bytes::Bytes;
+
+use crate::{
+    protocol::{TInputProtocol, TOutputProtocol},
+    thrift::{TListIdentifier, TMapIdentifier, TSetIdentifier, TStructIdentifier, TType, TUuid},
+    CodecError, CodecErrorKind,
+};
+
+const MAX_DEPTH: usize = 64;
+
+// Containers reserve at most this many elements up front, as their length
+// comes from the input.
+const PREALLOC_LIMIT: usize = 1024;
+
+/// A thrift value of any type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TValue {
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Double(f64),
+    #[cfg(feature = "float")]
+    Float(f32),
+    Binary(Bytes),
+    String(String),
+    Uuid(TUuid),
+    List {
+        element_type: TType,
+        elements: Vec<TValue>,
+    },
+    Set {
+        element_type: TType,
+        elements: Vec<TValue>,
+    },
+    Map {
+        key_type: TType,
+        value_type: TType,
+        entries: Vec<(TValue, TValue)>,
+    },
+    Struct(Vec<(i16, TValue)>),
+}
+
+impl TValue {
+    /// Wire type of the value.
+    pub fn ttype(&self) -> TType {
+        match self {
+            TValue::Bool(_) => TType::Bool,
+            TValue::I8(_) => TType::I8,
+            TValue::I16(_) => TType::I16,
+            TValue::I32(_) => TType::I32,
+            TValue::I64(_) => TType::I64,
+            TValue::Double(_) => TType::Double,
+            #[cfg(feature = "float")]
+            TValue::Float(_) => TType::Float,
+            TValue::Binary(_) | TValue::String(_) => TType::Binary,
+            TValue::Uuid(_) => TType::Uuid,
+            TValue::List { .. } => TType::List,
+            TValue::Set { .. } => TType::Set,
+            TValue::Map { .. } => TType::Map,
+            TValue::Struct(_) => TType::Struct,
+        }
+    }
+
+    /// The string held by a `String`, or by a `Binary` if it is valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            TValue::String(s) => Some(s),
+            TValue::Binary(b) => std::str::from_utf8(b).ok(),
+            _ => None,
+        }
+    }
+
+    /// The value of field `id`, if this is a struct having it.
+    pub fn field(&self, id: i16) -> Option<&TValue> {
+        match self {
+            TValue::Struct(fields) => fields.iter().find(|(i, _)| *i == id).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Read a value of type `ttype`.
+    pub fn read_from<'x, P: TInputProtocol<'x>>(
+        prot: &mut P,
+        ttype: TType,
+    ) -> Result<TValue, CodecError> {
+        read_value(prot, ttype, 0)
+    }
+
+    /// Write the value, fields and elements in the order they are held.
+    pub fn write_to<P: TOutputProtocol>(&self, prot: &mut P) {
+        match self {
+            TValue::Bool(b) => prot.write_bool(*b),
+            TValue::I8(i) => prot.write_i8(*i),
+            TValue::I16(i) => prot.write_i16(*i),
+            TValue::I32(i) => prot.write_i32(*i),
+            TValue::I64(i) => prot.write_i64(*i),
+            TValue::Double(d) => prot.write_double(*d),
+            #[cfg(feature = "float")]
+            TValue::Float(f) => prot.write_float(*f),
+            TValue::Binary(b) => prot.write_bytes_owned(b.clone()),
+            TValue::String(s) => prot.write_string(s),
+            TValue::Uuid(u) => prot.write_tuuid(*u),
+            TValue::List {
+                element_type,
+                elements,
+            } => {
+                prot.write_list_begin(&TListIdentifier::new(*element_type, elements.len()));
+                for element in elements {
+                    element.write_to(prot);
+                }
+                prot.write_list_end(elements.len());
+            }
+            TValue::Set {
+                element_type,
+                elements,
+            } => {
+                prot.write_set_begin(&TSetIdentifier::new(*element_type, elements.len()));
+                for element in elements {
+                    element.write_to(prot);
+                }
+                prot.write_set_end(elements.len());
+            }
+            TValue::Map {
+                key_type,
+                value_type,
+                entries,
+            } => {
+                prot.write_map_begin(&TMapIdentifier::new(*key_type, *value_type, entries.len()));
+                for (key, value) in entries {
+                    key.write_to(prot);
+                    value.write_to(prot);
+                }
+                prot.write_map_end(entries.len());
+            }
+            TValue::Struct(fields) => {
+                prot.write_struct_begin(&TStructIdentifier::new(None));
+                for (id, value) in fields {
+                    prot.write_field_begin(value.ttype(), *id);
+                    value.write_to(prot);
+                    prot.write_field_end();
+                }
+                prot.write_field_stop();
+                prot.write_struct_end();
+            }
+        }
+    }
+}
+
+fn read_value<'x, P: TInputProtocol<'x>>(
+    prot: &mut P,
+    ttype: TType,
+    depth: usize,
+) -> Result<TValue, CodecError> {
+    if depth >= MAX_DEPTH {
+        return Err(CodecError::new(
+            CodecErrorKind::DepthLimit,
+            "exceeded max depth while reading a value",
+        ));
+    }
+    let value = match ttype {
+        TType::Bool => TValue::Bool(prot.read_bool()?),
+        TType::I8 => TValue::I8(prot.read_i8()?),
+        TType::I16 => TValue::I16(prot.read_i16()?),
+        TType::I32 => TValue::I32(prot.read_i32()?),
+        TType::I64 => TValue::I64(prot.read_i64()?),
+        TType::Double => TValue::Double(prot.read_double()?),
+        #[cfg(feature = "float")]
+        TType::Float => TValue::Float(prot.read_float()?),
+        TType::Binary => TValue::Binary(prot.read_bytes_owned()?),
+        TType::Uuid => TValue::Uuid(prot.read_tuuid()?),
+        TType::Struct => {
+            let mut fields = Vec::new();
+            prot.read_struct_begin()?;
+            loop {
+                let field = prot.read_field_begin()?;
+                if field.field_type == TType::Stop {
+                    break;
+                }
+                let value = read_value(prot, field.field_type, depth + 1)?;
+                fields.push((field.id.unwrap_or_default(), value));
+                prot.read_field_end()?;
+            }
+            prot.read_struct_end()?;
+            TValue::Struct(fields)
+        }
+        TType::List => {
+            let ident = prot.read_list_begin()?;
+            let elements = read_elements(prot, ident.element_type, ident.size, depth)?;
+            prot.read_list_end()?;
+            TValue::List {
+                element_type: ident.element_type,
+                elements,
+            }
+        }
+        TType::Set => {
+            let ident = prot.read_set_begin()?;
+            let elements = read_elements(prot, ident.element_type, ident.size, depth)?;
+            prot.read_set_end()?;
+            TValue::Set {
+                element_type: ident.element_type,
+                elements,
+            }
+        }
+        TType::Map => {
+            let ident = prot.read_map_begin()?;
+            let mut entries = Vec::with_capacity(ident.size.min(PREALLOC_LIMIT));
+            for _ in 0..ident.size {
+                let key = read_value(prot, ident.key_type, depth + 1)?;
+                let value = read_value(prot, ident.value_type, depth + 1)?;
+                entries.push((key, value));
+            }
+            prot.read_map_end()?;
+            TValue::Map {
+                key_type: ident.key_type,
+                value_type: ident.value_type,
+                entries,
+            }
+        }
+        _ => {
+            return Err(CodecError::new(
+                CodecErrorKind::InvalidData,
+                format!("invalid ttype {}, normal type is expected", ttype as u8),
+            ));
+        }
+    };
+    Ok(value)
+}
+
+fn read_elements<'x, P: TInputProtocol<'x>>(
+    prot: &mut P,
+    element_type: TType,
+    size: usize,
+    depth: usize,
+) -> Result<Vec<TValue>, CodecError> {
+    let mut elements = Vec::with_capacity(size.min(PREALLOC_LIMIT));
+    for _ in 0..size {
+        elements.push(read_value(prot, element_type, depth + 1)?);
+    }
+    Ok(elements)
+}